@@ -0,0 +1,30 @@
+#[macro_use]
+extern crate rsrpc;
+#[macro_use]
+extern crate log;
+
+// A worker serving its parent on stdin and stdout, until the parent closes
+// them:
+//
+//   let client = Worker::connect_stdio("target/debug/examples/stdio_worker")?;
+service! {
+  Worker {
+    fn double(&mut self, n: u64) -> u64 {
+      n * 2
+    }
+
+    fn count(&mut self, n: u64) -> stream u64 |sink| {
+      for i in 0..n {
+        if sink.send(i).is_err() {
+          return;
+        }
+      }
+    }
+  }
+}
+
+fn main() {
+  let mut server = Worker::listen_stdio();
+
+  server.wait();
+}
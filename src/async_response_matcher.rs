@@ -1,41 +1,111 @@
-use futures::channel::oneshot;
+use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use super::error::Error;
 
 pub struct AsyncResponseMatcher {
-  waiting: HashMap<String, oneshot::Sender<Vec<u8>>>,
+  waiting: HashMap<u64, oneshot::Sender<Result<Bytes, Error>>>,
+  streams: HashMap<u64, mpsc::UnboundedSender<Result<Bytes, Error>>>,
+  // Streams sent by peers, keyed by the peer and the id of the request they belong to
+  incoming: HashMap<(SocketAddr, u64), mpsc::UnboundedSender<Result<Bytes, Error>>>,
+}
+
+impl Default for AsyncResponseMatcher {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl AsyncResponseMatcher {
   pub fn new() -> Self {
     Self {
       waiting: HashMap::new(),
+      streams: HashMap::new(),
+      incoming: HashMap::new(),
+    }
+  }
+
+  pub fn add(&mut self, id: u64, tx: oneshot::Sender<Result<Bytes, Error>>) {
+    trace!("Add waiting {}", id);
+
+    self.waiting.insert(id, tx);
+  }
+
+  pub fn add_stream(&mut self, id: u64, tx: mpsc::UnboundedSender<Result<Bytes, Error>>) {
+    trace!("Add stream {}", id);
+
+    self.streams.insert(id, tx);
+  }
+
+  pub fn resolve(matcher: &mut AsyncResponseMatcher, id: u64, data: Result<Bytes, Error>) {
+    trace!("Resolve waiting {}", id);
+
+    if let Some(tx) = matcher.waiting.remove(&id) {
+      if tx.send(data).is_err() {
+        trace!("Answer {} arrived after its caller gave up", id);
+      }
+
+      return;
     }
+
+    // An error ends the stream it belongs to
+    let ended = data.is_err();
+
+    match matcher.streams.get(&id) {
+      Some(tx) => {
+        if tx.unbounded_send(data).is_err() || ended {
+          matcher.streams.remove(&id);
+        }
+      }
+      None => trace!("Cannot find such answer ! {}", id),
+    };
   }
 
-  pub fn add(&mut self, hash: String, tx: oneshot::Sender<Vec<u8>>) {
-    trace!("Add waiting {}", hash);
+  pub fn end_stream(matcher: &mut AsyncResponseMatcher, id: u64) {
+    trace!("End stream {}", id);
 
-    self.waiting.insert(hash, tx);
+    matcher.streams.remove(&id);
   }
 
-  pub fn resolve(matcher: &mut AsyncResponseMatcher, hash: String, data: Vec<u8>) {
-    trace!("Resolve waiting {}", hash);
+  pub fn add_incoming(&mut self, peer: SocketAddr, id: u64, tx: mpsc::UnboundedSender<Result<Bytes, Error>>) {
+    trace!("Add incoming stream {} from {}", id, peer);
 
-    match matcher.waiting.remove(&hash) {
-      Some(tx) => tx.send(data).unwrap(),
-      None => trace!("Cannot find such answer ! {}", hash),
+    self.incoming.insert((peer, id), tx);
+  }
+
+  /// Feeds an item to a stream sent by `peer`, `None` ends it.
+  pub fn feed(matcher: &mut AsyncResponseMatcher, peer: SocketAddr, id: u64, item: Option<Result<Bytes, Error>>) {
+    let key = (peer, id);
+
+    match (item, matcher.incoming.get(&key)) {
+      (Some(item), Some(tx)) => {
+        let ended = item.is_err();
+
+        if tx.unbounded_send(item).is_err() || ended {
+          matcher.incoming.remove(&key);
+        }
+      }
+      (None, Some(_)) => {
+        trace!("End incoming stream {} from {}", id, peer);
+
+        matcher.incoming.remove(&key);
+      }
+      (_, None) => trace!("Cannot find such stream ! {} from {}", id, peer),
     };
   }
 
-  pub fn remove(matcher: &mut AsyncResponseMatcher, hash: String) {
-    trace!("Remove waiting {}", hash);
+  pub fn remove(matcher: &mut AsyncResponseMatcher, id: u64) {
+    trace!("Remove waiting {}", id);
 
-    matcher.waiting.remove(&hash).unwrap();
+    matcher.waiting.remove(&id);
+    matcher.streams.remove(&id);
   }
 
   pub fn close(&mut self) {
-    for (_, tx) in &self.waiting {
-      drop(tx);
-    }
+    self.waiting.clear();
+    self.streams.clear();
+    self.incoming.clear();
   }
 }
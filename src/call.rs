@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use futures::future::{Future, FutureExt};
+use futures::task::{Context, Poll};
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use super::async_response_matcher::AsyncResponseMatcher;
+use super::error::Error;
+use super::oneshot::Receiver;
+use super::proto::{Packet, PacketKind};
+use super::request_context::PacketSender;
+use super::utils::deserialize_bounded;
+
+/// Tells the peer that the request `id` is given up, unless it was finished
+/// first. Dropping it cancels.
+pub struct Canceller {
+  send: PacketSender,
+  peer: SocketAddr,
+  local_addr: SocketAddr,
+  id: u64,
+  matcher: Arc<Mutex<AsyncResponseMatcher>>,
+  done: bool,
+}
+
+impl Canceller {
+  pub fn new(
+    send: PacketSender,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    id: u64,
+    matcher: Arc<Mutex<AsyncResponseMatcher>>,
+  ) -> Canceller {
+    Canceller {
+      send,
+      peer,
+      local_addr,
+      id,
+      matcher,
+      done: false,
+    }
+  }
+
+  /// Nothing is left to cancel: the request was answered.
+  pub fn finish(&mut self) {
+    self.done = true;
+  }
+
+  pub fn cancel(&mut self) {
+    if self.done {
+      return;
+    }
+
+    self.done = true;
+
+    trace!("Cancel {}", self.id);
+
+    AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), self.id);
+
+    let mut pack = Packet::with_kind(PacketKind::Cancel, Bytes::new(), self.local_addr, 0);
+
+    pack.header.id = self.id;
+
+    (self.send)(&self.peer, pack);
+  }
+}
+
+impl Drop for Canceller {
+  fn drop(&mut self) {
+    self.cancel();
+  }
+}
+
+/// A request sent and not answered yet, to be awaited, waited on, or
+/// cancelled. Dropping it before the answer cancels the request.
+pub struct Call<R, E> {
+  rx: Receiver<Result<Bytes, Error>>,
+  timeout: Receiver<Error>,
+  canceller: Canceller,
+  max_size: usize,
+  _res: PhantomData<fn() -> Result<R, E>>,
+}
+
+impl<R: DeserializeOwned, E> Call<R, E> {
+  pub fn new(
+    rx: Receiver<Result<Bytes, Error>>,
+    timeout: Receiver<Error>,
+    canceller: Canceller,
+    max_size: usize,
+  ) -> Call<R, E> {
+    Call {
+      rx,
+      timeout,
+      canceller,
+      max_size,
+      _res: PhantomData,
+    }
+  }
+
+  /// Blocks until the answer comes.
+  pub fn wait(self) -> Result<Result<R, E>, Error> {
+    futures::executor::block_on(self)
+  }
+
+  /// Gives up on the request: its handler sees it cancelled, and its answer
+  /// is not sent.
+  pub fn cancel(mut self) {
+    self.canceller.cancel();
+  }
+}
+
+impl<R: DeserializeOwned, E> Future for Call<R, E> {
+  type Output = Result<Result<R, E>, Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let this = self.get_mut();
+
+    if let Poll::Ready(res) = this.rx.poll_unpin(cx) {
+      this.canceller.finish();
+
+      let max_size = this.max_size;
+
+      return Poll::Ready(match res {
+        Ok(res) => res.and_then(|data| deserialize_bounded(&data, max_size).map(Ok)),
+        // The network was closed
+        Err(_) => Err(Error::SendFailed),
+      });
+    }
+
+    if let Poll::Ready(Ok(err)) = this.timeout.poll_unpin(cx) {
+      this.canceller.cancel();
+
+      return Poll::Ready(Err(err));
+    }
+
+    Poll::Pending
+  }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, PartialEq)]
+pub enum Error {
+  Timeout,
+  SendFailed,
+  MessageTooLarge,
+  UnknownMethod,
+  /// A subscriber was dropped for not keeping up with its topic.
+  SlowConsumer,
+  /// The caller gave up on the request.
+  Cancelled,
+  Deserialize(String),
+  /// A peer streamed more items than it was granted credits for.
+  WindowExceeded,
+  /// The server already runs as many streaming handlers as it allows.
+  Busy,
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::Timeout => write!(f, "Timeout"),
+      Error::SendFailed => write!(f, "Send failed"),
+      Error::MessageTooLarge => write!(f, "Message too large"),
+      Error::UnknownMethod => write!(f, "Unknown method"),
+      Error::SlowConsumer => write!(f, "Slow consumer"),
+      Error::Cancelled => write!(f, "Cancelled"),
+      Error::Deserialize(e) => write!(f, "Cannot deserialize: {}", e),
+      Error::WindowExceeded => write!(f, "Window exceeded"),
+      Error::Busy => write!(f, "Busy"),
+    }
+  }
+}
@@ -0,0 +1,358 @@
+//! Credit-based flow control for streams.
+//!
+//! A sender spends a credit on every item it streams, and waits once it has
+//! none left. Credits are granted by the receiver as its items are consumed,
+//! up to a window per stream, and up to a window per connection shared by all
+//! the streams received from the same peer. Items waiting to be consumed are
+//! so bounded on the receiving side, whatever the speed of the sender: a
+//! stream sending more than it was granted is failed by its receiver.
+
+use bytes::Bytes;
+use futures::future::Future;
+use futures::task::{Context, Poll, Waker};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::error::Error;
+use super::proto::{Packet, PacketKind};
+use super::request_context::PacketSender;
+
+/// Windows granted by a receiver, in items.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlowControl {
+  /// Items a single stream can have in flight or waiting to be consumed.
+  pub stream_window: u32,
+  /// Items all the streams from a single peer can have at once.
+  pub connection_window: u32,
+}
+
+impl Default for FlowControl {
+  fn default() -> FlowControl {
+    FlowControl {
+      stream_window: 64,
+      connection_window: 1024,
+    }
+  }
+}
+
+/// A stream as seen by its receiver: the sending peer, the id of the request
+/// it belongs to, and whether it answers that request.
+pub type StreamKey = (SocketAddr, u64, bool);
+
+/// The packet granting `grant` more credits to the sender of a stream, or
+/// refusing any more items if `None`.
+pub fn credit_packet(key: StreamKey, grant: Option<u32>, local_addr: SocketAddr) -> Packet {
+  let data = Bytes::from(bincode::serialize(&(key.2, grant)).unwrap());
+
+  let mut pack = Packet::with_kind(PacketKind::Credit, data, local_addr, 0);
+
+  pack.header.id = key.1;
+
+  pack
+}
+
+/// The windows of the streams sent along requests, by request id, with the
+/// peers they are sent to.
+pub type SendWindows = Arc<Mutex<HashMap<u64, (SocketAddr, Weak<SendWindow>)>>>;
+
+struct SendState {
+  credits: u64,
+  closed: Option<Error>,
+  wakers: Vec<Waker>,
+}
+
+/// Credits a sender has left on one stream.
+pub struct SendWindow {
+  state: Mutex<SendState>,
+}
+
+impl SendWindow {
+  pub fn new() -> SendWindow {
+    SendWindow {
+      state: Mutex::new(SendState {
+        credits: 0,
+        closed: None,
+        wakers: vec![],
+      }),
+    }
+  }
+
+  pub fn grant(&self, credits: u32) {
+    let mut state = self.state.lock().unwrap();
+
+    state.credits += u64::from(credits);
+
+    for waker in state.wakers.drain(..) {
+      waker.wake();
+    }
+  }
+
+  /// Fails every wait for a credit from now on with `err`.
+  pub fn close(&self, err: Error) {
+    let mut state = self.state.lock().unwrap();
+
+    if state.closed.is_none() {
+      state.closed = Some(err);
+    }
+
+    for waker in state.wakers.drain(..) {
+      waker.wake();
+    }
+  }
+
+  /// Resolves once a credit is spent.
+  pub fn acquire(&self) -> Acquire<'_> {
+    Acquire { window: self }
+  }
+}
+
+impl Default for SendWindow {
+  fn default() -> SendWindow {
+    SendWindow::new()
+  }
+}
+
+pub struct Acquire<'a> {
+  window: &'a SendWindow,
+}
+
+impl<'a> Future for Acquire<'a> {
+  type Output = Result<(), Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let mut state = self.window.state.lock().unwrap();
+
+    if let Some(err) = &state.closed {
+      return Poll::Ready(Err(err.clone()));
+    }
+
+    if state.credits > 0 {
+      state.credits -= 1;
+
+      return Poll::Ready(Ok(()));
+    }
+
+    state.wakers.push(cx.waker().clone());
+
+    Poll::Pending
+  }
+}
+
+/// Credits granted by a receiver and not consumed yet, per stream and per
+/// peer. Grants are sent once half a window is consumed, rather than one
+/// per item.
+#[derive(Default)]
+pub struct ReceiveWindows {
+  config: FlowControl,
+  peers: HashMap<SocketAddr, u64>,
+  streams: HashMap<StreamKey, u64>,
+  // Items received and not consumed yet, per stream
+  queued: HashMap<StreamKey, u64>,
+  // Streams left without credits for the lack of connection window
+  starved: Vec<StreamKey>,
+}
+
+impl ReceiveWindows {
+  pub fn new() -> ReceiveWindows {
+    ReceiveWindows::default()
+  }
+
+  pub fn set_config(&mut self, config: FlowControl) {
+    self.config = config;
+  }
+
+  /// Gives the first grant of a new stream.
+  pub fn open(&mut self, key: StreamKey) -> Vec<(StreamKey, u32)> {
+    self.streams.insert(key, 0);
+    self.queued.insert(key, 0);
+
+    self.top_up(key, true).map(|grant| vec![(key, grant)]).unwrap_or_default()
+  }
+
+  /// An item arrived: gives `false` if its sender had no credit left for
+  /// it. Streams granted nothing by this receiver are not accounted.
+  pub fn received(&mut self, key: StreamKey) -> bool {
+    let outstanding = match self.streams.get(&key) {
+      Some(outstanding) => *outstanding,
+      None => return true,
+    };
+
+    let queued = self.queued.entry(key).or_insert(0);
+
+    if *queued >= outstanding {
+      return false;
+    }
+
+    *queued += 1;
+
+    true
+  }
+
+  /// An item was consumed: gives the grants it makes room for.
+  pub fn consumed(&mut self, key: StreamKey) -> Vec<(StreamKey, u32)> {
+    if let Some(outstanding) = self.streams.get_mut(&key) {
+      *outstanding = outstanding.saturating_sub(1);
+    } else {
+      return vec![];
+    }
+
+    if let Some(queued) = self.queued.get_mut(&key) {
+      *queued = queued.saturating_sub(1);
+    }
+
+    if let Some(outstanding) = self.peers.get_mut(&key.0) {
+      *outstanding = outstanding.saturating_sub(1);
+    }
+
+    let mut grants: Vec<_> = self.top_up(key, false).map(|grant| (key, grant)).into_iter().collect();
+
+    grants.extend(self.feed_starved(key.0));
+
+    grants
+  }
+
+  /// The stream is over: gives the grants its credits left make room for.
+  pub fn close(&mut self, key: StreamKey) -> Vec<(StreamKey, u32)> {
+    let outstanding = match self.streams.remove(&key) {
+      Some(outstanding) => outstanding,
+      None => return vec![],
+    };
+
+    self.queued.remove(&key);
+    self.starved.retain(|starved| *starved != key);
+
+    let empty = match self.peers.get_mut(&key.0) {
+      Some(peer) => {
+        *peer = peer.saturating_sub(outstanding);
+
+        *peer == 0
+      }
+      None => false,
+    };
+
+    if empty && !self.streams.keys().any(|stream| stream.0 == key.0) {
+      self.peers.remove(&key.0);
+    }
+
+    self.feed_starved(key.0)
+  }
+
+  fn top_up(&mut self, key: StreamKey, force: bool) -> Option<u32> {
+    let stream_window = u64::from(self.config.stream_window);
+    let connection_window = u64::from(self.config.connection_window);
+
+    let outstanding = *self.streams.get(&key)?;
+
+    let wanted = stream_window.saturating_sub(outstanding);
+
+    if wanted == 0 || (!force && outstanding > 0 && wanted < stream_window.div_ceil(2)) {
+      return None;
+    }
+
+    let peer = self.peers.entry(key.0).or_insert(0);
+
+    let grant = std::cmp::min(wanted, connection_window.saturating_sub(*peer));
+
+    if grant == 0 {
+      if !self.starved.contains(&key) {
+        self.starved.push(key);
+      }
+
+      return None;
+    }
+
+    *peer += grant;
+
+    self.streams.insert(key, outstanding + grant);
+
+    self.starved.retain(|starved| *starved != key);
+
+    Some(grant as u32)
+  }
+
+  fn feed_starved(&mut self, peer: SocketAddr) -> Vec<(StreamKey, u32)> {
+    let starved: Vec<StreamKey> = self.starved.iter().filter(|key| key.0 == peer).cloned().collect();
+
+    starved
+      .into_iter()
+      .filter_map(|key| self.top_up(key, true).map(|grant| (key, grant)))
+      .collect()
+  }
+}
+
+/// Grants credits for a received stream as its items are consumed. Dropping
+/// it before the end of the stream refuses any more items.
+pub struct Grantor {
+  windows: Arc<Mutex<ReceiveWindows>>,
+  key: StreamKey,
+  send: PacketSender,
+  local_addr: SocketAddr,
+  closed: bool,
+}
+
+impl Grantor {
+  pub fn open(
+    windows: Arc<Mutex<ReceiveWindows>>,
+    key: StreamKey,
+    send: PacketSender,
+    local_addr: SocketAddr,
+  ) -> Grantor {
+    let grants = windows.lock().unwrap().open(key);
+
+    let grantor = Grantor {
+      windows,
+      key,
+      send,
+      local_addr,
+      closed: false,
+    };
+
+    grantor.send_grants(grants);
+
+    grantor
+  }
+
+  pub fn consumed(&self) {
+    if self.closed {
+      return;
+    }
+
+    let grants = self.windows.lock().unwrap().consumed(self.key);
+
+    self.send_grants(grants);
+  }
+
+  /// Releases the credits of the stream, refusing any more items unless
+  /// the stream `ended`.
+  pub fn close(&mut self, ended: bool) {
+    if self.closed {
+      return;
+    }
+
+    self.closed = true;
+
+    let grants = self.windows.lock().unwrap().close(self.key);
+
+    if !ended {
+      (self.send)(&self.key.0, credit_packet(self.key, None, self.local_addr));
+    }
+
+    self.send_grants(grants);
+  }
+
+  fn send_grants(&self, grants: Vec<(StreamKey, u32)>) {
+    for (key, grant) in grants {
+      trace!("Granting {} credits to stream {} from {}", grant, key.1, key.0);
+
+      (self.send)(&key.0, credit_packet(key, Some(grant), self.local_addr));
+    }
+  }
+}
+
+impl Drop for Grantor {
+  fn drop(&mut self) {
+    self.close(false);
+  }
+}
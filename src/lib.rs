@@ -19,9 +19,9 @@ extern crate futures;
 
 pub extern crate bincode;
 pub extern crate byteorder;
+pub extern crate serde;
 // extern crate hex;
 // extern crate pin_utils;
-// extern crate serde;
 // extern crate serde_bytes;
 // extern crate sha2;
 // extern crate tokio;
@@ -29,16 +29,26 @@ pub extern crate byteorder;
 #[macro_use]
 pub mod service_macro;
 mod async_response_matcher;
+pub mod call;
+pub mod error;
+pub mod flow;
+pub mod limits;
 pub mod network;
 pub mod plugins;
 pub mod proto;
+pub mod pubsub;
+pub mod request_context;
+pub mod runtime;
 pub mod server_callback;
+pub mod stream;
 pub mod tests;
 pub mod timer;
+pub mod transfer;
 pub mod transport;
 pub mod utils;
 
 pub use bincode::{deserialize, serialize};
+pub use bytes::{Bytes, BytesMut};
 pub use futures::channel::oneshot;
 pub use futures::executor::block_on;
 pub use std::collections::HashMap;
@@ -47,10 +57,17 @@ pub use std::sync::Mutex;
 pub use std::thread;
 
 pub use self::async_response_matcher::AsyncResponseMatcher;
+pub use self::call::Call;
+pub use self::error::Error;
+pub use self::flow::FlowControl;
+pub use self::limits::Limits;
 pub use self::network::Network;
 pub use self::plugins::*;
-pub use self::proto::Packet;
+pub use self::proto::{Exchange, Packet};
+pub use self::pubsub::{PubSubConfig, SlowConsumerPolicy, Subscription};
+pub use self::request_context::{HandlerSlot, RequestContext};
 pub use self::server_callback::ServerCallback;
+pub use self::stream::{StreamReceiver, StreamSink, Upload};
 pub use self::service_macro::*;
 pub use self::transport::*;
 pub use self::utils::*;
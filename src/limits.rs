@@ -0,0 +1,28 @@
+/// Upper bounds applied to everything a `Network` reads from the wire.
+///
+/// Frames above `max_frame_size` are dropped by the transport. Requests and
+/// responses that decode above their own limit are answered with
+/// `Error::MessageTooLarge`, but not a dropped frame: it is skipped unread,
+/// so its caller gets `Error::Timeout`.
+///
+/// Stream, upload and channel requests are served by at most
+/// `max_stream_handlers` handlers at once, the others being answered with
+/// `Error::Busy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+  pub max_frame_size: usize,
+  pub max_request_size: usize,
+  pub max_response_size: usize,
+  pub max_stream_handlers: usize,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      max_frame_size: 16 * 1024 * 1024,
+      max_request_size: 8 * 1024 * 1024,
+      max_response_size: 8 * 1024 * 1024,
+      max_stream_handlers: 256,
+    }
+  }
+}
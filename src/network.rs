@@ -1,35 +1,60 @@
 use bincode::serialize;
+use bytes::Bytes;
+use futures::channel::mpsc;
 use futures::future::FutureExt;
 use futures::select;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::thread;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
 use super::async_response_matcher::AsyncResponseMatcher;
+use super::call::{Call, Canceller};
+use super::error::Error;
+use super::flow::{credit_packet, FlowControl, Grantor, ReceiveWindows, SendWindow, SendWindows, StreamKey};
+use super::limits::Limits;
 use super::oneshot::{channel, Receiver};
 use super::plugins::*;
-use super::proto::Packet;
+use super::proto::{Packet, PacketKind};
+use super::pubsub::{PubSubConfig, Subscription, Topics};
+use super::request_context::{AsyncPacketSender, InFlight, InFlightRequests, PacketSender, RequestContext};
+use super::runtime;
 use super::server_callback::ServerCallback;
+use super::stream::{StreamReceiver, StreamSink, Upload};
 use super::timer::Timer;
 use super::transport::*;
 use super::utils::*;
 
-lazy_static! {
-  pub static ref MATCHER: super::Mutex<super::AsyncResponseMatcher> =
-    super::Mutex::new(super::AsyncResponseMatcher::new());
-}
+// Packets read and waiting for the dispatcher, before the reading waits
+const DISPATCH_QUEUE_SIZE: usize = 256;
 
 #[derive(Clone)]
 pub struct Network<T: Transport + Clone> {
   pub transport: T,
   pub plugins: Plugins,
   pub callback: Mutexed<ServerCallback>,
-  pub handle: Option<Arc<thread::JoinHandle<()>>>,
+  pub limits: Mutexed<Limits>,
+  pub matcher: Arc<Mutex<AsyncResponseMatcher>>,
+  pub next_id: Arc<AtomicU64>,
+  pub topics: Arc<Mutex<Topics>>,
+  pub in_flight: InFlightRequests,
+  /// Credits granted to the streams this network receives.
+  pub receive_windows: Arc<Mutex<ReceiveWindows>>,
+  /// Credits left to the streams this network sends along its requests.
+  pub send_windows: SendWindows,
+  /// Stream, upload and channel handlers running.
+  pub handlers: Arc<AtomicUsize>,
+  /// The task reading the transport, kept by the owner of the network.
+  pub handle: Option<Arc<JoinHandle<()>>>,
 }
 
 impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
-  pub fn new_default(addr: &SocketAddr) -> Network<T> {
+  pub fn new_default(addr: &T::Addr) -> Network<T> {
     let t = T::new(addr);
 
     Self::new(t, ServerCallback::new_empty())
@@ -40,121 +65,349 @@ impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
       transport: transport,
       plugins: Plugins::new(),
       callback: Mutexed::new(callback),
+      limits: Mutexed::new(Limits::default()),
+      matcher: Arc::new(Mutex::new(AsyncResponseMatcher::new())),
+      next_id: Arc::new(AtomicU64::new(1)),
+      topics: Arc::new(Mutex::new(Topics::new())),
+      in_flight: Arc::new(Mutex::new(HashMap::new())),
+      receive_windows: Arc::new(Mutex::new(ReceiveWindows::new())),
+      send_windows: Arc::new(Mutex::new(HashMap::new())),
+      handlers: Arc::new(AtomicUsize::new(0)),
       handle: None,
     }
   }
 
   pub fn listen(&mut self) -> &mut Network<T> {
-    self.transport.listen();
-
-    let net = self.clone();
+    runtime::block_on(self.listen_async())
+  }
 
-    trace!("Starting async read loop");
+  pub async fn listen_async(&mut self) -> &mut Network<T> {
+    self.transport.listen().await;
 
-    self.handle = Some(Arc::new(thread::spawn(|| {
-      Self::run_read_thread(net);
-    })));
+    self.spawn_read_loop();
 
     self
   }
 
   pub fn connect(&mut self) -> Result<&mut Network<T>, String> {
-    let res = self.transport.connect();
+    runtime::block_on(self.connect_async())
+  }
 
-    if let Err(e) = res {
-      return Err(e);
-    }
+  pub async fn connect_async(&mut self) -> Result<&mut Network<T>, String> {
+    self.transport.connect().await?;
+
+    self.spawn_read_loop();
 
+    Ok(self)
+  }
+
+  fn spawn_read_loop(&mut self) {
     let net = self.clone();
 
     trace!("Starting async read loop");
 
-    self.handle = Some(Arc::new(thread::spawn(|| {
-      Self::run_read_thread(net);
-    })));
-
-    Ok(self)
+    self.handle = Some(Arc::new(runtime::spawn(Self::read_loop(net))));
   }
 
-  fn run_read_thread(net: Network<T>) {
-    let mut t = net.transport.clone();
-    let net = net.clone();
+  async fn read_loop(net: Network<T>) {
+    // Requests are served apart from the reading, so that a handler waiting
+    // on a call back into its peer still gets the response
+    // A request is registered as in flight as soon as it is read, to be found
+    // by its cancellation while still waiting to be served
+    let (dispatch_tx, mut dispatch_rx) =
+      tokio::sync::mpsc::channel::<(Packet, SocketAddr, Option<Arc<InFlight>>)>(DISPATCH_QUEUE_SIZE);
+
+    // Handlers may block, so they are served off the workers of the runtime
+    let dispatcher = {
+      let net = net.clone();
+
+      tokio::task::spawn_blocking(move || {
+        while let Some((pack, from, _in_flight)) = dispatch_rx.blocking_recv() {
+          if !Self::feed_incoming(&net, &pack, from) && !Self::serve_topics(&net, &pack, from) {
+            (net.callback.get().closure)(pack, from);
+          }
+        }
+      })
+    };
 
-    // loop {
-    //   match t.recv() {
-    //     Ok((buff, from)) => {
-    //       let mut pack: Packet = super::deserialize(&buff).unwrap();
+    loop {
+      let (buff, from) = tokio::select! {
+        received = net.transport.recv() => match received {
+          Some(received) => received,
+          None => break,
+        },
+        Some(peer) = net.transport.dropped() => {
+          Self::close_windows(&net, Some(peer));
+
+          continue;
+        }
+      };
 
-    //       let mut plugins = net.plugins.clone();
+      let limits = net.limits.get();
 
-    //       pack = plugins.run_on_recv(pack.clone());
+      let mut pack = match Packet::decode(buff, limits.max_frame_size) {
+        Ok(pack) => pack,
+        Err(e) => {
+          error!("Cannot read packet from {}: {}", from, e);
 
-    //       let pack_c = pack.clone();
+          continue;
+        }
+      };
 
-    //       {
-    //         let mut guard = MATCHER.lock().unwrap();
+      let plugins = net.plugins.clone();
 
-    //         AsyncResponseMatcher::resolve(&mut *guard, pack.header.response_to.clone(), pack.data.clone());
-    //       }
+      pack = plugins.run_on_recv(pack);
 
-    //       (net.callback.get().closure)(pack_c, from);
-    //     },
-    //     Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
-    //     Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => break,
-    //     Err(e) => {
-    //       if e.kind() != ErrorKind::Other {
-    //         println!("OUAT {}", e);
-    //         error!("Error read {}", e);
-    //       }
+      // Streams sent along a request never reach the callback, but are
+      // fed in order with the requests they belong to
+      if pack.header.response_to == 0 {
+        if let PacketKind::StreamEnd | PacketKind::Error(_) | PacketKind::StreamItem = pack.header.kind {
+          if dispatch_tx.send((pack, from, None)).await.is_err() {
+            break;
+          }
 
-    //       break;
-    //     }
-    //   };
-    // }
-    let recv = t.get_recv();
+          continue;
+        }
+      }
 
-    loop {
-      match recv
-        .lock()
-        .unwrap()
-        .recv_deadline(Instant::now() + Duration::from_millis(1))
-      {
-        Ok((buff, from)) => {
-          let mut pack: Packet = super::deserialize(&buff).unwrap();
+      if pack.header.kind == PacketKind::Credit {
+        Self::credit(&net, from, &pack);
 
-          let mut plugins = net.plugins.clone();
+        continue;
+      }
 
-          pack = plugins.run_on_recv(pack.clone());
+      if pack.header.kind == PacketKind::Cancel {
+        Self::cancel_request(&net, from, pack.header.id);
 
-          let pack_c = pack.clone();
+        continue;
+      }
 
-          {
-            let mut guard = MATCHER.lock().unwrap();
+      if pack.data.len() > limits.max_request_size {
+        match pack.header.kind {
+          PacketKind::Oneway => {
+            warn!("Dropping oneway request of {} bytes from {}", pack.data.len(), from);
 
-            AsyncResponseMatcher::resolve(
-              &mut *guard,
-              pack.header.response_to.clone(),
-              pack.data.clone(),
-            );
+            continue;
           }
+          PacketKind::Request | PacketKind::Batch => {
+            warn!("Request of {} bytes from {} is too large", pack.data.len(), from);
+
+            let err = Packet::new_error(Error::MessageTooLarge, net.transport.local_addr(), pack.header.id);
+
+            Self::send_packet_async(&net, &from, err).await;
 
-          (net.callback.get().closure)(pack_c, from);
+            continue;
+          }
+          _ => (),
         }
-        // Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
-        // Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => break,
-        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-          if !t.is_running() {
-            break;
+      }
+
+      let pack_c = pack.clone();
+
+      let refused = match pack.header.kind {
+        PacketKind::StreamItem => Self::window_exceeded(&net, (from, pack.header.response_to, true)),
+        _ => None,
+      };
+
+      if let Some(refusal) = &refused {
+        Self::send_packet_async(&net, &from, refusal.clone()).await;
+      }
+
+      {
+        let mut guard = net.matcher.lock().unwrap();
+
+        let res = match pack.header.kind {
+          PacketKind::StreamEnd => {
+            AsyncResponseMatcher::end_stream(&mut guard, pack.header.response_to);
+
+            None
           }
+          PacketKind::Error(err) => Some(Err(err)),
+          _ if refused.is_some() => Some(Err(Error::WindowExceeded)),
+          _ if pack.data.len() > limits.max_response_size => Some(Err(Error::MessageTooLarge)),
+          _ => Some(Ok(pack.data.clone())),
+        };
+
+        if let Some(res) = res {
+          AsyncResponseMatcher::resolve(&mut *guard, pack.header.response_to, res);
+        }
+      }
 
-          // thread::sleep(Duration::from_millis(10));
+      let in_flight = match pack_c.header.kind {
+        PacketKind::Request | PacketKind::Batch if pack_c.header.response_to == 0 => {
+          Some(InFlight::register(&net.in_flight, from, pack_c.header.id))
         }
-        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-          if !t.is_running() {
-            break;
+        _ => None,
+      };
+
+      if dispatch_tx.send((pack_c, from, in_flight)).await.is_err() {
+        break;
+      }
+    }
+
+    // No credit can come anymore
+    Self::close_windows(&net, None);
+
+    drop(dispatch_tx);
+
+    // The handlers left are waited for
+    if dispatcher.await.is_err() {
+      error!("Request dispatcher panicked");
+    }
+  }
+
+  /// Feeds an item of a stream sent along a request to its receiver.
+  /// Gives `false` for any other packet.
+  fn feed_incoming(net: &Network<T>, pack: &Packet, from: SocketAddr) -> bool {
+    if pack.header.response_to != 0 {
+      return false;
+    }
+
+    let max_request_size = net.limits.get().max_request_size;
+
+    let item = match &pack.header.kind {
+      PacketKind::StreamEnd => None,
+      PacketKind::Error(err) => Some(Err(err.clone())),
+      PacketKind::StreamItem => match Self::window_exceeded(net, (from, pack.header.id, false)) {
+        Some(refusal) => {
+          Self::send_packet(net, &from, refusal);
+
+          Some(Err(Error::WindowExceeded))
+        }
+        None if pack.data.len() > max_request_size => Some(Err(Error::MessageTooLarge)),
+        None => Some(Ok(pack.data.clone())),
+      },
+      _ => return false,
+    };
+
+    let mut guard = net.matcher.lock().unwrap();
+
+    AsyncResponseMatcher::feed(&mut guard, from, pack.header.id, item);
+
+    true
+  }
+
+  /// Accounts an item of the stream `key` just read. Gives the packet
+  /// refusing any more items if its sender had no credit left for it.
+  fn window_exceeded(net: &Network<T>, key: StreamKey) -> Option<Packet> {
+    if net.receive_windows.lock().unwrap().received(key) {
+      return None;
+    }
+
+    warn!("Stream {} from {} exceeds its window", key.1, key.0);
+
+    Some(credit_packet(key, None, net.transport.local_addr()))
+  }
+
+  /// Marks a request as cancelled by its peer, and ends the stream it sends
+  /// along, if any.
+  fn cancel_request(net: &Network<T>, from: SocketAddr, id: u64) {
+    match InFlight::find(&net.in_flight, from, id) {
+      Some(in_flight) => {
+        debug!("{} cancelled request {}", from, id);
+
+        in_flight.cancel();
+      }
+      None => trace!("Nothing to cancel for {} from {}", id, from),
+    }
+
+    let mut guard = net.matcher.lock().unwrap();
+
+    AsyncResponseMatcher::feed(&mut guard, from, id, Some(Err(Error::Cancelled)));
+  }
+
+  /// Gives the credits granted by `from` to the stream they belong to.
+  fn credit(net: &Network<T>, from: SocketAddr, pack: &Packet) {
+    let (answers, grant): (bool, Option<u32>) = match deserialize_bounded(&pack.data, 64) {
+      Ok(credit) => credit,
+      Err(e) => {
+        error!("Cannot read credits from {}: {}", from, e);
+
+        return;
+      }
+    };
+
+    let id = pack.header.id;
+
+    // Streams answering a request of `from`, or sent along one of ours
+    let window = if answers {
+      InFlight::find(&net.in_flight, from, id).map(|in_flight| in_flight.window.clone())
+    } else {
+      net.send_windows.lock().unwrap().get(&id).and_then(|(_, window)| window.upgrade())
+    };
+
+    match (window, grant) {
+      (Some(window), Some(grant)) => window.grant(grant),
+      (Some(window), None) => window.close(Error::Cancelled),
+      (None, _) => trace!("No stream {} to credit from {}", id, from),
+    }
+  }
+
+  /// Fails the streams waiting for credits from `peer`, or from any peer if
+  /// `None`.
+  fn close_windows(net: &Network<T>, peer: Option<SocketAddr>) {
+    let from = |addr: &SocketAddr| peer.is_none_or(|peer| *addr == peer);
+
+    let windows: Vec<_> = net
+      .send_windows
+      .lock()
+      .unwrap()
+      .values()
+      .filter(|(to, _)| from(to))
+      .filter_map(|(_, window)| window.upgrade())
+      .collect();
+
+    // Collected first: the last reference to a request unregisters it
+    let in_flight: Vec<_> = net
+      .in_flight
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|(key, _)| from(&key.0))
+      .filter_map(|(_, in_flight)| in_flight.upgrade())
+      .collect();
+
+    if let Some(peer) = peer {
+      debug!("Failing {} streams sent to {}", windows.len() + in_flight.len(), peer);
+    }
+
+    for window in windows {
+      window.close(Error::SendFailed);
+    }
+
+    for in_flight in in_flight {
+      in_flight.window.close(Error::SendFailed);
+    }
+  }
+
+  /// Handles subscriptions to the topics of this network. Gives `false`
+  /// for any other packet.
+  fn serve_topics(net: &Network<T>, pack: &Packet, from: SocketAddr) -> bool {
+    match pack.header.kind {
+      PacketKind::Subscribe if pack.header.response_to == 0 => {
+        let id = pack.header.id;
+
+        match deserialize_bounded::<String>(&pack.data, net.limits.get().max_request_size) {
+          Ok(topic) => {
+            // Not a `Response`, which would end the request before its
+            // messages are streamed
+            let ack = Packet::with_kind(PacketKind::Subscribe, Bytes::new(), net.transport.local_addr(), id);
+
+            net.topics.lock().unwrap().subscribe(topic, from, id, ack, net.async_packet_sender());
+          }
+          Err(err) => {
+            Self::send_packet(net, &from, Packet::new_error(err, net.transport.local_addr(), id));
           }
         }
-      };
+
+        true
+      }
+      PacketKind::Unsubscribe => {
+        net.topics.lock().unwrap().unsubscribe(from, pack.header.id);
+
+        true
+      }
+      _ => false,
     }
   }
 
@@ -162,14 +415,25 @@ impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
     self.callback.set(callback);
   }
 
-  pub async fn wait_for(rx1: Receiver<Vec<u8>>, pack_c: Packet) -> Result<Vec<u8>, String> {
-    let err_rx = Timer::new(Duration::from_secs(1), "Timeout".to_string());
+  pub fn set_flow_control(&mut self, flow: FlowControl) {
+    self.receive_windows.lock().unwrap().set_config(flow);
+  }
+
+  pub fn set_limits(&mut self, limits: Limits) {
+    self.transport.set_max_frame_size(limits.max_frame_size);
+
+    self.limits.set(limits);
+  }
+
+  pub async fn wait_for(&self, rx1: Receiver<Result<Bytes, Error>>, id: u64) -> Result<Bytes, Error> {
+    let err_rx = Timer::new(Duration::from_secs(1), Error::Timeout);
 
     select! {
       res1 = rx1.fuse() => {
         match res1 {
-          Ok(r) => Ok(r),
-          _ => panic!("Canceled call"),
+          Ok(r) => r,
+          // The network was closed
+          Err(_) => Err(Error::SendFailed),
         }
       },
       res_err_rx = err_rx.fuse() => {
@@ -178,11 +442,11 @@ impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
             error!("Error sending to {} : {}", "()", err);
 
             {
-              let mut guard = MATCHER.lock().unwrap();
+              let mut guard = self.matcher.lock().unwrap();
 
               let matcher = &mut *guard;
 
-              AsyncResponseMatcher::remove(matcher, pack_c.header.msg_hash.clone());
+              AsyncResponseMatcher::remove(matcher, id);
             }
 
             Err(err)
@@ -193,52 +457,466 @@ impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
     }
   }
 
-  pub fn send(&mut self, addr: &SocketAddr, buff: Vec<u8>) -> Result<Vec<u8>, String> {
-    let (tx1, mut rx1) = channel::<Vec<u8>>();
+  pub fn send(&self, addr: &SocketAddr, buff: Bytes) -> Result<Bytes, Error> {
+    runtime::block_on(self.send_async(addr, buff))
+  }
+
+  /// `send` for async code.
+  pub async fn send_async(&self, addr: &SocketAddr, buff: Bytes) -> Result<Bytes, Error> {
+    let pack = Packet::new(buff, self.transport.local_addr(), 0);
+
+    self.request(addr, pack).await
+  }
+
+  /// Sends many calls in a single request, and returns one result per call.
+  pub fn send_batch(
+    &self,
+    addr: &SocketAddr,
+    calls: Vec<Bytes>,
+  ) -> Result<Vec<Result<Bytes, Error>>, Error> {
+    runtime::block_on(self.send_batch_async(addr, calls))
+  }
+
+  /// `send_batch` for async code.
+  pub async fn send_batch_async(
+    &self,
+    addr: &SocketAddr,
+    calls: Vec<Bytes>,
+  ) -> Result<Vec<Result<Bytes, Error>>, Error> {
+    let buff = Bytes::from(serialize(&calls).unwrap());
+
+    let pack = Packet::new_batch(buff, self.transport.local_addr());
+
+    let data = self.request(addr, pack).await?;
+
+    deserialize_bounded(&data, self.limits.get().max_response_size)
+  }
+
+  /// Every request gets an id unique to this network, so any number of
+  /// callers can wait on the same connection at once.
+  async fn request(&self, addr: &SocketAddr, mut pack: Packet) -> Result<Bytes, Error> {
+    let (tx1, rx1) = channel::<Result<Bytes, Error>>();
+
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    pack.header.id = id;
+
+    self.matcher.lock().unwrap().add(id, tx1);
+
+    // Dropped before the answer, the request is cancelled
+    let mut canceller = self.canceller(addr, id);
+
+    pack = self.plugins.run_on_send(pack);
+
+    // Answered once, only a cancellation may follow
+    if !self.transport.send_on(addr, pack.exchange(), true, pack.encode()).await {
+      canceller.finish();
+
+      AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), id);
+
+      return Err(Error::SendFailed);
+    }
+
+    let res = self.wait_for(rx1, id).await;
+
+    // The handler is told that nobody waits for its answer anymore
+    if res != Err(Error::Timeout) {
+      canceller.finish();
+    }
+
+    res
+  }
+
+  /// Sends a request without waiting for its answer. The returned `Call`
+  /// is awaited or waited on, and cancels the request if dropped before.
+  pub fn call<R: DeserializeOwned, E>(&self, addr: &SocketAddr, buff: Bytes) -> Result<Call<R, E>, Error> {
+    runtime::block_on(self.call_async(addr, buff))
+  }
+
+  /// `call` for async code.
+  pub async fn call_async<R: DeserializeOwned, E>(&self, addr: &SocketAddr, buff: Bytes) -> Result<Call<R, E>, Error> {
+    let (tx1, rx1) = channel::<Result<Bytes, Error>>();
+
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    let mut pack = Packet::new(buff, self.transport.local_addr(), 0);
+
+    pack.header.id = id;
+
+    self.matcher.lock().unwrap().add(id, tx1);
+
+    let mut canceller = self.canceller(addr, id);
+
+    if !Self::send_on_async(self, addr, pack, true).await {
+      canceller.finish();
+
+      AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), id);
+
+      return Err(Error::SendFailed);
+    }
+
+    Ok(Call::new(
+      rx1,
+      Timer::new(Duration::from_secs(1), Error::Timeout),
+      canceller,
+      self.limits.get().max_response_size,
+    ))
+  }
+
+  /// Cancels the request `id` sent to `addr` when asked or dropped.
+  pub fn canceller(&self, addr: &SocketAddr, id: u64) -> Canceller {
+    Canceller::new(
+      self.packet_sender(),
+      *addr,
+      self.transport.local_addr(),
+      id,
+      self.matcher.clone(),
+    )
+  }
+
+  /// Sends a request answered by a stream of items, ended by a `StreamEnd`
+  /// packet or an error.
+  pub fn send_stream<R: DeserializeOwned>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<StreamReceiver<R>, Error> {
+    runtime::block_on(self.send_stream_async(addr, buff))
+  }
+
+  /// `send_stream` for async code.
+  pub async fn send_stream_async<R: DeserializeOwned>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<StreamReceiver<R>, Error> {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    self.open_stream(addr, buff, id).await
+  }
+
+  /// Opens a duplex channel: items flow both ways along the same request
+  /// until each side ends its own stream.
+  pub fn send_channel<I: Serialize, R: DeserializeOwned>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<(StreamSink<I>, StreamReceiver<R>), Error> {
+    runtime::block_on(self.send_channel_async(addr, buff))
+  }
+
+  /// `send_channel` for async code.
+  pub async fn send_channel_async<I: Serialize, R: DeserializeOwned>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<(StreamSink<I>, StreamReceiver<R>), Error> {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    // Registered before the request, so that no credit is missed
+    let window = self.send_window(addr, id);
+
+    let rx = self.open_stream(addr, buff, id).await?;
+
+    let sink = StreamSink::new_request(
+      self.async_packet_sender(),
+      *addr,
+      self.transport.local_addr(),
+      id,
+      self.limits.get().max_request_size,
+    )
+    .with_window(window);
+
+    Ok((sink, rx))
+  }
+
+  async fn open_stream<R: DeserializeOwned>(&self, addr: &SocketAddr, buff: Bytes, id: u64) -> Result<StreamReceiver<R>, Error> {
+    let (tx, rx) = mpsc::unbounded();
+
+    let mut pack = Packet::new(buff, self.transport.local_addr(), 0);
+
+    pack.header.id = id;
+
+    self.matcher.lock().unwrap().add_stream(id, tx);
+
+    let mut canceller = self.canceller(addr, id);
+
+    if !Self::send_packet_async(self, addr, pack).await {
+      canceller.finish();
+
+      AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), id);
+
+      return Err(Error::SendFailed);
+    }
+
+    // Granted after the request, for the peer to know about it
+    let grantor = Grantor::open(
+      self.receive_windows.clone(),
+      (*addr, id, true),
+      self.packet_sender(),
+      self.transport.local_addr(),
+    );
+
+    Ok(
+      StreamReceiver::new(rx, self.limits.get().max_response_size)
+        .cancelled_with(canceller)
+        .with_grantor(grantor),
+    )
+  }
+
+  fn send_window(&self, addr: &SocketAddr, id: u64) -> Arc<SendWindow> {
+    let window = Arc::new(SendWindow::new());
+
+    let mut windows = self.send_windows.lock().unwrap();
+
+    windows.retain(|_, (_, window)| window.strong_count() > 0);
+    windows.insert(id, (*addr, Arc::downgrade(&window)));
+
+    window
+  }
+
+  /// Sends a request along with a stream of items, answered once the stream
+  /// is finished.
+  pub fn send_upload<I: Serialize, R: DeserializeOwned, E>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<Upload<I, R, E>, Error> {
+    runtime::block_on(self.send_upload_async(addr, buff))
+  }
+
+  /// `send_upload` for async code.
+  pub async fn send_upload_async<I: Serialize, R: DeserializeOwned, E>(
+    &self,
+    addr: &SocketAddr,
+    buff: Bytes,
+  ) -> Result<Upload<I, R, E>, Error> {
+    let (tx1, rx1) = channel::<Result<Bytes, Error>>();
+
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    let mut pack = Packet::new(buff, self.transport.local_addr(), 0);
+
+    pack.header.id = id;
+
+    self.matcher.lock().unwrap().add(id, tx1);
 
-    let pack = Packet::new(buff, self.transport.get_addr(), String::new());
+    let window = self.send_window(addr, id);
 
-    let mut pack_c = pack.clone();
+    let mut canceller = self.canceller(addr, id);
 
-    let mut transport = self.transport.clone();
+    if !Self::send_packet_async(self, addr, pack).await {
+      canceller.finish();
 
-    let addr_c = addr.clone();
+      AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), id);
 
-    let mut plugins = self.plugins.clone();
+      return Err(Error::SendFailed);
+    }
+
+    let limits = self.limits.get();
+
+    let sink = StreamSink::new_request(
+      self.async_packet_sender(),
+      *addr,
+      self.transport.local_addr(),
+      id,
+      limits.max_request_size,
+    )
+    .with_window(window);
+
+    let mut net = self.clone();
+
+    net.handle = None;
+
+    let result = Box::pin(async move { net.wait_for(rx1, id).await });
+
+    Ok(Upload::new(sink, result, canceller, limits.max_response_size))
+  }
 
+  /// Subscribes to a topic published by the network at `addr`. Messages
+  /// published from then on are received until the subscription is dropped.
+  pub fn subscribe<P: DeserializeOwned>(&self, addr: &SocketAddr, topic: &str) -> Result<Subscription<P>, Error> {
+    runtime::block_on(self.subscribe_async(addr, topic))
+  }
+
+  /// `subscribe` for async code.
+  pub async fn subscribe_async<P: DeserializeOwned>(&self, addr: &SocketAddr, topic: &str) -> Result<Subscription<P>, Error> {
+    let (tx1, rx1) = channel::<Result<Bytes, Error>>();
+    let (tx, rx) = mpsc::unbounded();
+
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+    let mut pack = Packet::with_kind(
+      PacketKind::Subscribe,
+      Bytes::from(serialize(topic).unwrap()),
+      self.transport.local_addr(),
+      0,
+    );
+
+    pack.header.id = id;
+
+    // The acknowledgement comes first, then the messages on the same id
     {
-      let addr_c = addr_c.clone();
+      let mut guard = self.matcher.lock().unwrap();
+
+      guard.add(id, tx1);
+      guard.add_stream(id, tx);
+    }
+
+    // Dropped before the acknowledgement, the subscription is given up
+    let subscription = Subscription::new(
+      StreamReceiver::new(rx, self.limits.get().max_response_size),
+      id,
+      *addr,
+      self.transport.local_addr(),
+      self.packet_sender(),
+      self.matcher.clone(),
+    );
+
+    if !Self::send_packet_async(self, addr, pack).await {
+      return Err(Error::SendFailed);
+    }
+
+    self.wait_for(rx1, id).await?;
+
+    Ok(subscription)
+  }
+
+  /// Publishes a message to every current subscriber of `topic`, and gives
+  /// how many it was queued for.
+  pub fn publish<P: Serialize>(&self, topic: &str, payload: &P) -> Result<usize, Error> {
+    let data = Bytes::from(serialize(payload).unwrap());
+
+    if data.len() > self.limits.get().max_response_size {
+      return Err(Error::MessageTooLarge);
+    }
+
+    let local_addr = self.transport.local_addr();
+
+    let count = self.topics.lock().unwrap().publish(
+      topic,
+      |id| Packet::with_kind(PacketKind::StreamItem, data.clone(), local_addr, id),
+      |peer| self.transport.is_connected(peer),
+    );
+
+    Ok(count)
+  }
+
+  pub fn subscribers(&self, topic: &str) -> usize {
+    self.topics.lock().unwrap().subscribers(topic)
+  }
 
-      let mut guard = MATCHER.lock().unwrap();
+  pub fn set_pubsub_config(&mut self, config: PubSubConfig) {
+    self.topics.lock().unwrap().set_config(config);
+  }
+
+  /// Sends a request that is never answered: nothing is registered in the
+  /// matcher and only local send failures are reported.
+  pub fn notify(&self, addr: &SocketAddr, buff: Bytes) -> Result<(), Error> {
+    runtime::block_on(self.notify_async(addr, buff))
+  }
 
-      let matcher = &mut *guard;
+  /// `notify` for async code.
+  pub async fn notify_async(&self, addr: &SocketAddr, buff: Bytes) -> Result<(), Error> {
+    let pack = Packet::new_oneway(buff, self.transport.local_addr());
 
-      matcher.add(pack_c.header.msg_hash.clone(), tx1);
+    let pack = self.plugins.run_on_send(pack);
 
-      pack_c = plugins.run_on_send(pack_c.clone());
+    if self.transport.send(addr, pack.encode()).await {
+      Ok(())
+    } else {
+      Err(Error::SendFailed)
+    }
+  }
 
-      let buf = serialize(&pack_c).unwrap();
+  pub fn send_answer(net: &mut Network<T>, addr: &SocketAddr, buff: Bytes, response_to: u64) {
+    if buff.len() > net.limits.get().max_response_size {
+      warn!("Response of {} bytes to {} is too large", buff.len(), addr);
 
-      transport.send(&addr_c, buf);
+      return Self::send_error(net, addr, Error::MessageTooLarge, response_to);
     }
 
-    futures::executor::block_on(Self::wait_for(rx1, pack_c))
+    let pack = Packet::new(buff, net.transport.local_addr(), response_to);
+
+    Self::send_packet(net, addr, pack);
+  }
+
+  pub fn send_error(net: &mut Network<T>, addr: &SocketAddr, err: Error, response_to: u64) {
+    let pack = Packet::new_error(err, net.transport.local_addr(), response_to);
+
+    Self::send_packet(net, addr, pack);
+  }
+
+  fn send_packet(net: &Network<T>, addr: &SocketAddr, pack: Packet) -> bool {
+    runtime::block_on(Self::send_packet_async(net, addr, pack))
+  }
+
+  async fn send_packet_async(net: &Network<T>, addr: &SocketAddr, pack: Packet) -> bool {
+    let last = pack.ends_exchange();
+
+    Self::send_on_async(net, addr, pack, last).await
+  }
+
+  /// Sends `pack`, telling the transport whether its request goes on.
+  async fn send_on_async(net: &Network<T>, addr: &SocketAddr, mut pack: Packet, last: bool) -> bool {
+    pack = net.plugins.run_on_send(pack);
+
+    net.transport.send_on(addr, pack.exchange(), last, pack.encode()).await
+  }
+
+  /// Sends packets through this network, without keeping its task alive.
+  pub fn packet_sender(&self) -> PacketSender {
+    let mut net = self.clone();
+
+    net.handle = None;
+
+    Arc::new(move |addr, pack| Self::send_packet(&net, addr, pack))
   }
 
-  pub fn send_answer(net: &mut Network<T>, addr: &SocketAddr, buff: Vec<u8>, response_to: String) {
-    let mut pack = Packet::new(buff, net.transport.get_addr(), response_to);
+  /// `packet_sender` for the tasks of the runtime.
+  pub fn async_packet_sender(&self) -> AsyncPacketSender {
+    let mut net = self.clone();
 
-    pack = net.plugins.run_on_send(pack.clone());
+    net.handle = None;
 
-    let buf = serialize(&pack).unwrap();
+    let net = Arc::new(net);
 
-    net.transport.send(addr, buf);
+    Arc::new(move |addr, pack| {
+      let net = net.clone();
+
+      Box::pin(async move { Self::send_packet_async(&net, &addr, pack).await })
+    })
+  }
+
+  /// The context handed to the service for a request received from `from`.
+  pub fn request_context(&self, pack: &Packet, from: SocketAddr) -> RequestContext {
+    let mut net = self.clone();
+
+    net.handle = None;
+
+    let in_flight = InFlight::find(&self.in_flight, from, pack.header.id)
+      .unwrap_or_else(|| InFlight::register(&self.in_flight, from, pack.header.id));
+
+    RequestContext {
+      id: pack.header.id,
+      peer: from,
+      local_addr: self.transport.local_addr(),
+      limits: self.limits.get(),
+      send: self.packet_sender(),
+      async_send: self.async_packet_sender(),
+      matcher: self.matcher.clone(),
+      network: Arc::new(net),
+      in_flight,
+      windows: self.receive_windows.clone(),
+      handlers: self.handlers.clone(),
+      peer_identity: self.transport.peer_identity(&from),
+    }
   }
 
   pub fn wait(&mut self) {
     if let Some(handle) = self.handle.take() {
       match Arc::try_unwrap(handle) {
-        Ok(h) => h.join().unwrap(),
+        Ok(h) => runtime::block_on(h).unwrap(),
         Err(a) => warn!(
           "Not waiting: multiple references ({}) to network stay.",
           Arc::strong_count(&a)
@@ -252,6 +930,49 @@ impl<T: 'static + Transport + Clone + Send + Sync> Network<T> {
 
     self.set_callback(ServerCallback::new_empty());
 
-    MATCHER.lock().unwrap().close();
+    self.topics.lock().unwrap().close();
+
+    Self::close_windows(self, None);
+
+    self.send_windows.lock().unwrap().clear();
+
+    self.matcher.lock().unwrap().close();
+  }
+}
+
+/// A network of any transport, as kept by `Duplex` to serve and to connect
+/// from the same socket.
+pub trait SharedNetwork: Send {
+  fn as_any(&self) -> &dyn Any;
+  /// A clone holding the read task, to be waited on without holding this.
+  fn detach(&mut self) -> Box<dyn SharedNetwork>;
+  fn plugins(&mut self) -> &mut Plugins;
+  fn wait(&mut self);
+  fn close(&mut self);
+}
+
+impl<T: 'static + Transport> SharedNetwork for Network<T> {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn detach(&mut self) -> Box<dyn SharedNetwork> {
+    let net = self.clone();
+
+    self.handle = None;
+
+    Box::new(net)
+  }
+
+  fn plugins(&mut self) -> &mut Plugins {
+    &mut self.plugins
+  }
+
+  fn wait(&mut self) {
+    Network::wait(self)
+  }
+
+  fn close(&mut self) {
+    Network::close(self)
   }
 }
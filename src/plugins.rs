@@ -2,12 +2,14 @@ use super::proto::Packet;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
+/// Packets are handed over by value so a wrapper that does not touch the
+/// payload passes it through without any copy.
 pub trait Wrapper: fmt::Debug + Send + Sync {
-  fn on_send(&self, pack: &Packet) -> Packet {
-    pack.clone()
+  fn on_send(&self, pack: Packet) -> Packet {
+    pack
   }
-  fn on_recv(&self, pack: &Packet) -> Packet {
-    pack.clone()
+  fn on_recv(&self, pack: Packet) -> Packet {
+    pack
   }
 }
 
@@ -29,33 +31,29 @@ impl Plugins {
     (*guard).push(Box::new(wrapper));
   }
 
-  pub fn run_on_send(&mut self, data: Packet) -> Packet {
+  pub fn run_on_send(&self, mut data: Packet) -> Packet {
     trace!("Processing Plugins on Send request");
 
-    let mut data = data.clone();
-
     let guard = self.wrappers.lock().unwrap();
 
     for wrapper in (*guard).iter() {
       trace!("- {:?}", wrapper);
 
-      data = wrapper.on_send(&data);
+      data = wrapper.on_send(data);
     }
 
     data
   }
 
-  pub fn run_on_recv(&mut self, data: Packet) -> Packet {
+  pub fn run_on_recv(&self, mut data: Packet) -> Packet {
     trace!("Processing Plugins on Recv request");
 
-    let mut data = data.clone();
-
     let guard = self.wrappers.lock().unwrap();
 
     for wrapper in (*guard).iter().rev() {
       trace!("- {:?}", wrapper);
 
-      data = wrapper.on_recv(&data);
+      data = wrapper.on_recv(data);
     }
 
     data
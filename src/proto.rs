@@ -1,53 +1,183 @@
-use bincode::serialize;
-use hex::encode;
-use sha2::{Digest, Sha256};
+use bincode::serialized_size;
+use bytes::{BufMut, Bytes, BytesMut};
 use std::net::SocketAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use super::byteorder::{ByteOrder, LittleEndian};
+use super::error::Error;
+use super::utils::deserialize_bounded_from;
+
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, PartialEq)]
+pub enum PacketKind {
+  Request,
+  Response,
+  Oneway,
+  Batch,
+  StreamItem,
+  StreamEnd,
+  Error(Error),
+  /// Asks for the messages of a topic, and acknowledges it when answering.
+  Subscribe,
+  Unsubscribe,
+  /// Sent by a caller giving up on the request `id`.
+  Cancel,
+  /// Credits granted to the sender of a stream of the request `id`.
+  Credit,
+}
+
 #[derive(Serialize, Deserialize, Debug, Hash, Clone)]
 pub struct PacketHeader {
   pub sender: SocketAddr,
   pub date: u64,
-  pub msg_hash: String,
-  pub response_to: String,
+  pub id: u64,
+  pub response_to: u64,
+  pub kind: PacketKind,
 }
 
 impl PacketHeader {
-  pub fn new(sender: SocketAddr, response_to: String) -> PacketHeader {
+  /// `response_to` is the id of the answered request, or 0 for a request.
+  pub fn new(sender: SocketAddr, response_to: u64) -> PacketHeader {
+    let kind = if response_to == 0 {
+      PacketKind::Request
+    } else {
+      PacketKind::Response
+    };
+
     PacketHeader {
       sender,
       date: SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_micros() as u64,
-      msg_hash: String::from(""),
+      id: 0,
       response_to,
+      kind,
     }
   }
 }
 
+/// The request a packet belongs to. The packets of a request must arrive in
+/// the order they were sent, those of different requests need not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Exchange {
+  pub id: u64,
+  /// Sent by the peer answering the request, rather than by its caller.
+  pub answers: bool,
+}
+
+/// `data` is reference counted: cloning a `Packet` or slicing its payload
+/// never copies the bytes received from the transport.
 #[derive(Serialize, Deserialize, Debug, Hash, Clone)]
 pub struct Packet {
   pub header: PacketHeader,
-  pub data: Vec<u8>,
+  pub data: Bytes,
 }
 
 impl Packet {
-  pub fn new(data: Vec<u8>, sender: SocketAddr, response_to: String) -> Packet {
-    let mut pack = Packet {
-      header: PacketHeader::new(sender, response_to),
-      data,
-    };
+  pub fn new(data: Bytes, sender: SocketAddr, response_to: u64) -> Packet {
+    let header = PacketHeader::new(sender, response_to);
+
+    Packet { header, data }
+  }
 
-    pack._hash();
+  pub fn with_kind(kind: PacketKind, data: Bytes, sender: SocketAddr, response_to: u64) -> Packet {
+    let mut header = PacketHeader::new(sender, response_to);
 
-    pack
+    header.kind = kind;
+
+    Packet { header, data }
+  }
+
+  pub fn new_error(err: Error, sender: SocketAddr, response_to: u64) -> Packet {
+    Packet::with_kind(PacketKind::Error(err), Bytes::new(), sender, response_to)
+  }
+
+  pub fn new_oneway(data: Bytes, sender: SocketAddr) -> Packet {
+    Packet::with_kind(PacketKind::Oneway, data, sender, 0)
   }
 
-  pub fn _hash(&mut self) {
-    let serie = serialize(self).unwrap();
-    let mut sha = Sha256::default();
-    sha.input(serie.as_slice());
-    self.header.msg_hash = encode(sha.result().as_slice());
+  pub fn new_batch(data: Bytes, sender: SocketAddr) -> Packet {
+    Packet::with_kind(PacketKind::Batch, data, sender, 0)
+  }
+
+  /// The request this packet belongs to, if its order matters.
+  /// Notifications and batches stand on their own.
+  pub fn exchange(&self) -> Option<Exchange> {
+    match self.header.kind {
+      PacketKind::Oneway | PacketKind::Batch => None,
+      // Credits for the answers come from the caller, the others from the
+      // peer answering
+      PacketKind::Credit => {
+        let (answers, _): (bool, Option<u32>) = bincode::deserialize(&self.data).ok()?;
+
+        Some(Exchange {
+          id: self.header.id,
+          answers: !answers,
+        })
+      }
+      _ if self.header.response_to != 0 => Some(Exchange {
+        id: self.header.response_to,
+        answers: true,
+      }),
+      _ => Some(Exchange {
+        id: self.header.id,
+        answers: false,
+      }),
+    }
+  }
+
+  /// Whether nothing follows this packet in its exchange. A request may
+  /// still be followed by the items it streams, so the caller tells.
+  pub fn ends_exchange(&self) -> bool {
+    matches!(
+      self.header.kind,
+      PacketKind::Response
+        | PacketKind::StreamEnd
+        | PacketKind::Error(_)
+        | PacketKind::Cancel
+        | PacketKind::Unsubscribe
+    )
+  }
+
+  /// Same layout as `bincode::serialize`, built with a single copy of `data`.
+  pub fn encode(&self) -> Bytes {
+    let header_size = serialized_size(&self.header).unwrap() as usize;
+
+    let mut buff = BytesMut::with_capacity(header_size + 8 + self.data.len());
+
+    bincode::serialize_into((&mut buff).writer(), &self.header).unwrap();
+
+    buff.put_u64_le(self.data.len() as u64);
+    buff.extend_from_slice(&self.data);
+
+    buff.freeze()
+  }
+
+  /// Decodes the header and slices `data` out of `buff` without copying it.
+  pub fn decode(buff: Bytes, limit: usize) -> Result<Packet, Error> {
+    let mut cursor = &buff[..];
+
+    let header: PacketHeader = deserialize_bounded_from(&mut cursor, limit)?;
+
+    if cursor.len() < 8 {
+      return Err(Error::Deserialize("Truncated packet".to_string()));
+    }
+
+    let size = LittleEndian::read_u64(&cursor[..8]) as usize;
+
+    if size > limit {
+      return Err(Error::MessageTooLarge);
+    }
+
+    let start = buff.len() - cursor.len() + 8;
+
+    if size > buff.len() - start {
+      return Err(Error::Deserialize("Truncated packet".to_string()));
+    }
+
+    Ok(Packet {
+      header,
+      data: buff.slice(start..start + size),
+    })
   }
 }
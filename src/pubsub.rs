@@ -0,0 +1,329 @@
+//! Named topics published by a `Network` to the peers subscribed to them.
+//!
+//! Every subscriber gets its own bounded queue and sending task, so a slow
+//! one never holds back the publisher nor the other subscribers. What happens
+//! once its queue is full is up to the `SlowConsumerPolicy`.
+
+use futures::stream::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+
+use super::async_response_matcher::AsyncResponseMatcher;
+use super::error::Error;
+use super::proto::{Packet, PacketKind};
+use super::request_context::{AsyncPacketSender, PacketSender};
+use super::runtime;
+use super::stream::StreamReceiver;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy {
+  /// Makes room for the new message by dropping the oldest one queued.
+  DropOldest,
+  /// Drops the new message.
+  DropNewest,
+  /// Unsubscribes the subscriber, whose stream ends with `Error::SlowConsumer`.
+  Unsubscribe,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PubSubConfig {
+  /// Messages queued per subscriber before the policy applies.
+  pub queue_size: usize,
+  pub slow_consumer: SlowConsumerPolicy,
+}
+
+impl Default for PubSubConfig {
+  fn default() -> PubSubConfig {
+    PubSubConfig {
+      queue_size: 1024,
+      slow_consumer: SlowConsumerPolicy::DropOldest,
+    }
+  }
+}
+
+struct Queue {
+  packets: VecDeque<Packet>,
+  // Once closed, what is left is sent and nothing more is taken
+  closed: bool,
+}
+
+#[derive(Clone)]
+struct Subscriber {
+  queue: Arc<(Mutex<Queue>, Notify)>,
+}
+
+impl Subscriber {
+  fn spawn(peer: SocketAddr, first: Packet, send: AsyncPacketSender) -> Subscriber {
+    let mut packets = VecDeque::new();
+
+    packets.push_back(first);
+
+    let subscriber = Subscriber {
+      queue: Arc::new((
+        Mutex::new(Queue {
+          packets,
+          closed: false,
+        }),
+        Notify::new(),
+      )),
+    };
+
+    let queue = subscriber.queue.clone();
+
+    runtime::spawn(async move {
+      let (lock, notify) = &*queue;
+
+      loop {
+        let pack = loop {
+          {
+            let mut guard = lock.lock().unwrap();
+
+            if let Some(pack) = guard.packets.pop_front() {
+              break Some(pack);
+            }
+
+            if guard.closed {
+              break None;
+            }
+          }
+
+          notify.notified().await;
+        };
+
+        let pack = match pack {
+          Some(pack) => pack,
+          None => break,
+        };
+
+        if !send(peer, pack).await {
+          debug!("Cannot publish to {}, unsubscribing", peer);
+
+          break;
+        }
+      }
+
+      lock.lock().unwrap().closed = true;
+    });
+
+    subscriber
+  }
+
+  fn is_alive(&self) -> bool {
+    !self.queue.0.lock().unwrap().closed
+  }
+
+  /// Queues a packet, or gives `false` when the subscriber is to be dropped.
+  fn push(&self, pack: Packet, config: &PubSubConfig) -> bool {
+    let (lock, notify) = &*self.queue;
+
+    let mut guard = lock.lock().unwrap();
+
+    if guard.closed {
+      return false;
+    }
+
+    if guard.packets.len() >= config.queue_size {
+      match config.slow_consumer {
+        SlowConsumerPolicy::DropOldest => {
+          guard.packets.pop_front();
+        }
+        SlowConsumerPolicy::DropNewest => return true,
+        SlowConsumerPolicy::Unsubscribe => {
+          let end = Packet::new_error(Error::SlowConsumer, pack.header.sender, pack.header.response_to);
+
+          guard.packets.clear();
+          guard.packets.push_back(end);
+          guard.closed = true;
+
+          notify.notify_one();
+
+          return false;
+        }
+      }
+    }
+
+    guard.packets.push_back(pack);
+
+    notify.notify_one();
+
+    true
+  }
+
+  /// Stops sending, dropping what is still queued.
+  fn close(&self) {
+    let (lock, notify) = &*self.queue;
+
+    let mut guard = lock.lock().unwrap();
+
+    guard.packets.clear();
+    guard.closed = true;
+
+    notify.notify_one();
+  }
+}
+
+/// The topics published by a network, and their subscribers keyed by peer
+/// and subscription id.
+#[derive(Default)]
+pub struct Topics {
+  config: PubSubConfig,
+  topics: HashMap<String, HashMap<(SocketAddr, u64), Subscriber>>,
+}
+
+impl Topics {
+  pub fn new() -> Topics {
+    Topics::default()
+  }
+
+  pub fn set_config(&mut self, config: PubSubConfig) {
+    self.config = config;
+  }
+
+  /// Subscribes `peer` to `topic`, `ack` being sent before any message.
+  pub fn subscribe(&mut self, topic: String, peer: SocketAddr, id: u64, ack: Packet, send: AsyncPacketSender) {
+    debug!("{} subscribes to {}", peer, topic);
+
+    let subscriber = Subscriber::spawn(peer, ack, send);
+
+    if let Some(previous) = self.topics.entry(topic).or_default().insert((peer, id), subscriber) {
+      previous.close();
+    }
+  }
+
+  pub fn unsubscribe(&mut self, peer: SocketAddr, id: u64) {
+    for (topic, subscribers) in self.topics.iter_mut() {
+      if let Some(subscriber) = subscribers.remove(&(peer, id)) {
+        debug!("{} unsubscribes from {}", peer, topic);
+
+        subscriber.close();
+      }
+    }
+
+    self.topics.retain(|_, subscribers| !subscribers.is_empty());
+  }
+
+  /// Queues a message for every subscriber of `topic` for which `connected`
+  /// holds, `packet` building it for a subscription id. Gives the number of
+  /// subscribers it was queued for.
+  pub fn publish<F, C>(&mut self, topic: &str, packet: F, connected: C) -> usize
+  where
+    F: Fn(u64) -> Packet,
+    C: Fn(&SocketAddr) -> bool,
+  {
+    let config = self.config;
+
+    let subscribers = match self.topics.get_mut(topic) {
+      Some(subscribers) => subscribers,
+      None => return 0,
+    };
+
+    let mut count = 0;
+
+    subscribers.retain(|(peer, id), subscriber| {
+      if !connected(peer) {
+        debug!("{} is gone, unsubscribing from {}", peer, topic);
+
+        subscriber.close();
+
+        return false;
+      }
+
+      if !subscriber.push(packet(*id), &config) {
+        warn!("Unsubscribing {} from {}", peer, topic);
+
+        return false;
+      }
+
+      count += 1;
+
+      true
+    });
+
+    if subscribers.is_empty() {
+      self.topics.remove(topic);
+    }
+
+    count
+  }
+
+  /// The subscribers of `topic` still being sent to.
+  pub fn subscribers(&self, topic: &str) -> usize {
+    self
+      .topics
+      .get(topic)
+      .map(|subscribers| subscribers.values().filter(|subscriber| subscriber.is_alive()).count())
+      .unwrap_or(0)
+  }
+
+  pub fn close(&mut self) {
+    for (_, subscribers) in self.topics.drain() {
+      for subscriber in subscribers.values() {
+        subscriber.close();
+      }
+    }
+  }
+}
+
+/// Messages of a topic, as a blocking `Iterator` or as a `futures::Stream`.
+/// Dropping it unsubscribes.
+pub struct Subscription<T> {
+  items: StreamReceiver<T>,
+  id: u64,
+  peer: SocketAddr,
+  local_addr: SocketAddr,
+  send: PacketSender,
+  matcher: Arc<Mutex<AsyncResponseMatcher>>,
+}
+
+impl<T: DeserializeOwned> Subscription<T> {
+  pub fn new(
+    items: StreamReceiver<T>,
+    id: u64,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    send: PacketSender,
+    matcher: Arc<Mutex<AsyncResponseMatcher>>,
+  ) -> Subscription<T> {
+    Subscription {
+      items,
+      id,
+      peer,
+      local_addr,
+      send,
+      matcher,
+    }
+  }
+}
+
+impl<T: DeserializeOwned> Iterator for Subscription<T> {
+  type Item = Result<T, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    Iterator::next(&mut self.items)
+  }
+}
+
+impl<T: DeserializeOwned> Stream for Subscription<T> {
+  type Item = Result<T, Error>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    self.get_mut().items.poll_next_unpin(cx)
+  }
+}
+
+impl<T> Drop for Subscription<T> {
+  fn drop(&mut self) {
+    AsyncResponseMatcher::remove(&mut self.matcher.lock().unwrap(), self.id);
+
+    let mut pack = Packet::with_kind(PacketKind::Unsubscribe, Default::default(), self.local_addr, 0);
+
+    pack.header.id = self.id;
+
+    (self.send)(&self.peer, pack);
+  }
+}
@@ -0,0 +1,211 @@
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use super::async_response_matcher::AsyncResponseMatcher;
+use super::error::Error;
+use super::flow::{Grantor, ReceiveWindows, SendWindow};
+use super::limits::Limits;
+use super::network::Network;
+use super::proto::Packet;
+use super::runtime;
+use super::stream::{StreamReceiver, StreamSink};
+use super::transport::{PeerIdentity, Transport};
+
+/// Sends a packet to a peer through the network that received the request.
+pub type PacketSender = Arc<dyn Fn(&SocketAddr, Packet) -> bool + Send + Sync>;
+
+/// `PacketSender` for the tasks of the runtime, which must not block.
+pub type AsyncPacketSender = Arc<dyn Fn(SocketAddr, Packet) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// The requests being served, keyed by peer and request id.
+pub type InFlightRequests = Arc<Mutex<HashMap<(SocketAddr, u64), Weak<InFlight>>>>;
+
+/// A request being served. It stays registered, and can be cancelled by
+/// its peer, until every context of the request is dropped.
+pub struct InFlight {
+  key: (SocketAddr, u64),
+  cancelled: AtomicBool,
+  registry: InFlightRequests,
+  /// Credits for the items streamed in answer.
+  pub window: Arc<SendWindow>,
+}
+
+impl InFlight {
+  pub fn register(registry: &InFlightRequests, peer: SocketAddr, id: u64) -> Arc<InFlight> {
+    let in_flight = Arc::new(InFlight {
+      key: (peer, id),
+      cancelled: AtomicBool::new(false),
+      registry: registry.clone(),
+      window: Arc::new(SendWindow::new()),
+    });
+
+    registry.lock().unwrap().insert((peer, id), Arc::downgrade(&in_flight));
+
+    in_flight
+  }
+
+  pub fn find(registry: &InFlightRequests, peer: SocketAddr, id: u64) -> Option<Arc<InFlight>> {
+    registry.lock().unwrap().get(&(peer, id)).and_then(Weak::upgrade)
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::SeqCst);
+
+    self.window.close(Error::Cancelled);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+}
+
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    let mut registry = self.registry.lock().unwrap();
+
+    // Unless a new request took the same key
+    if registry.get(&self.key).and_then(Weak::upgrade).is_none() {
+      registry.remove(&self.key);
+    }
+  }
+}
+
+/// What a dispatched call knows about the request it is serving.
+#[derive(Clone)]
+pub struct RequestContext {
+  pub id: u64,
+  pub peer: SocketAddr,
+  pub local_addr: SocketAddr,
+  pub limits: Limits,
+  pub send: PacketSender,
+  pub async_send: AsyncPacketSender,
+  pub matcher: Arc<Mutex<AsyncResponseMatcher>>,
+  /// The `Network` that received the request, without its thread.
+  pub network: Arc<dyn Any + Send + Sync>,
+  pub in_flight: Arc<InFlight>,
+  pub windows: Arc<Mutex<ReceiveWindows>>,
+  /// Stream, upload and channel handlers running on the network.
+  pub handlers: Arc<AtomicUsize>,
+  /// The certificate the peer authenticated with, over TLS.
+  pub peer_identity: Option<PeerIdentity>,
+}
+
+impl RequestContext {
+  /// Whether the peer gave up on the request. Its answer, if any, is not
+  /// sent, and the items of its streams are refused.
+  pub fn is_cancelled(&self) -> bool {
+    self.in_flight.is_cancelled()
+  }
+
+  /// A sink whose items are sent back to the peer as answers to this request.
+  pub fn sink<T: Serialize>(&self) -> StreamSink<T> {
+    StreamSink::new(
+      self.async_send.clone(),
+      self.peer,
+      self.local_addr,
+      self.id,
+      self.limits.max_response_size,
+    )
+    .cancelled_by(self.in_flight.clone())
+    .with_window(self.in_flight.window.clone())
+  }
+
+  /// The items the peer streams along with this request.
+  pub fn receiver<T: DeserializeOwned>(&self) -> StreamReceiver<T> {
+    let (tx, rx) = mpsc::unbounded();
+
+    let mut guard = self.matcher.lock().unwrap();
+
+    // Checked under the lock the cancellation feeds the stream with
+    if self.is_cancelled() {
+      let _ = tx.unbounded_send(Err(Error::Cancelled));
+
+      return StreamReceiver::new(rx, self.limits.max_request_size);
+    }
+
+    guard.add_incoming(self.peer, self.id, tx);
+
+    drop(guard);
+
+    let grantor = Grantor::open(
+      self.windows.clone(),
+      (self.peer, self.id, false),
+      self.send.clone(),
+      self.local_addr,
+    );
+
+    StreamReceiver::new(rx, self.limits.max_request_size).with_grantor(grantor)
+  }
+
+  /// Reserves a handler for a call that outlives its dispatch, or fails with
+  /// `Error::Busy` once `max_stream_handlers` of them run.
+  pub fn handler_slot(&self) -> Result<HandlerSlot, Error> {
+    if self.handlers.fetch_add(1, Ordering::SeqCst) >= self.limits.max_stream_handlers {
+      self.handlers.fetch_sub(1, Ordering::SeqCst);
+
+      warn!("Refusing request {} from {}: too many streaming handlers", self.id, self.peer);
+
+      return Err(Error::Busy);
+    }
+
+    Ok(HandlerSlot {
+      handlers: self.handlers.clone(),
+    })
+  }
+
+  /// The network that received the request, to call back into the peer
+  /// over the same connection. `None` if it does not use transport `T`.
+  pub fn peer_network<T: 'static + Transport>(&self) -> Option<Network<T>> {
+    self.network.downcast_ref::<Network<T>>().cloned()
+  }
+
+  /// Answers the request once, for calls that finish after their dispatch.
+  pub fn answer(&self, data: Bytes) -> bool {
+    if self.is_cancelled() {
+      debug!("Not answering cancelled request {} from {}", self.id, self.peer);
+
+      return false;
+    }
+
+    let pack = if data.len() > self.limits.max_response_size {
+      warn!("Response of {} bytes to {} is too large", data.len(), self.peer);
+
+      Packet::new_error(Error::MessageTooLarge, self.local_addr, self.id)
+    } else {
+      Packet::new(data, self.local_addr, self.id)
+    };
+
+    (self.send)(&self.peer, pack)
+  }
+}
+
+/// A handler reserved by `RequestContext::handler_slot`, released once the
+/// handler it runs returns.
+pub struct HandlerSlot {
+  handlers: Arc<AtomicUsize>,
+}
+
+impl HandlerSlot {
+  /// Runs `handler` on the blocking threads of the runtime.
+  pub fn spawn<F: FnOnce() + Send + 'static>(self, handler: F) {
+    runtime::runtime().spawn_blocking(move || {
+      let _slot = self;
+
+      handler()
+    });
+  }
+}
+
+impl Drop for HandlerSlot {
+  fn drop(&mut self) {
+    self.handlers.fetch_sub(1, Ordering::SeqCst);
+  }
+}
@@ -0,0 +1,77 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+
+thread_local! {
+  // Set on the threads of the runtime
+  static ON_RUNTIME: Cell<bool> = const { Cell::new(false) };
+}
+
+lazy_static! {
+  static ref RUNTIME: Runtime = Builder::new_multi_thread()
+    .thread_name("rsrpc")
+    .on_thread_start(|| ON_RUNTIME.with(|on_runtime| on_runtime.set(true)))
+    .enable_all()
+    .build()
+    .expect("Cannot start the runtime");
+}
+
+/// The tokio runtime driving the transports and networks of the process.
+pub fn runtime() -> &'static Runtime {
+  &RUNTIME
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+  F: Future + Send + 'static,
+  F::Output: Send + 'static,
+{
+  RUNTIME.spawn(future)
+}
+
+/// Wakes the thread blocked on a future.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+/// Waits for `future` from blocking code. Sockets being driven by the
+/// workers of the runtime, it can be called from any thread, even from within
+/// another executor, such as a blocking send inside `futures::executor::block_on`.
+/// From a task of this runtime, its worker hands the other tasks over to
+/// another thread while blocked.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+  if ON_RUNTIME.with(Cell::get) {
+    tokio::task::block_in_place(|| park_on(future))
+  } else {
+    park_on(future)
+  }
+}
+
+fn park_on<F: Future>(future: F) -> F::Output {
+  let _guard = RUNTIME.enter();
+
+  let waker = Waker::from(Arc::new(Unpark(thread::current())));
+
+  let mut context = Context::from_waker(&waker);
+
+  let mut future = Box::pin(future);
+
+  loop {
+    match future.as_mut().poll(&mut context) {
+      Poll::Ready(output) => return output,
+      Poll::Pending => thread::park(),
+    }
+  }
+}
@@ -4,239 +4,451 @@ macro_rules! service {
   (
     $(
       $service_name:ident {
-        $(
-          let $var:ident : $type_:ty $(= $default:expr)* ;
-        )*
-        $(
-          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) $(-> $out:ty)* $(| $error:ty)* $b:block
-        )*
+        $($body:tt)*
       }
     )*
   ) => {
-    service! {{
-      $(
+    $(
+      service! {{
         $service_name {
-          {
-            {
-              $(let $var : $type_ $(= $default)* ;)*
-            }
-          }
-          {{
-            $(fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) $(-> $out)* $(| $error)* $b)*
-          }}
+          { $($body)* }
+          {}
+          {}
+          {}
+          {}
+          {}
+          {}
         }
-      )*
-    }}
+      }}
+    )*
   };
 
   // Variable no default value
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {
-              let $var:ident : $type_:ty ;
-              $($unexpanded_var:tt)*
-            }
-            $($expanded_var:tt)*
-          }
-          {{$($unexpanded_fn:tt)*}}
+      $service_name:ident {
+        {
+          let $var:ident : $type_:ty ;
+          $($unexpanded:tt)*
         }
-      )*
+        { $($expanded_vars:tt)* }
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            { $($unexpanded_var)* }
-            $($expanded_var)*
-            let $var : $type_  = Default::default() ;
-          }
-          {{$($unexpanded_fn)*}}
+      $service_name {
+        { $($unexpanded)* }
+        {
+          $($expanded_vars)*
+          let $var : $type_ = Default::default() ;
         }
-      )*
+        $fns
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
     }}
   };
 
   // Variable with default value
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {
-              let $var:ident : $type_:ty = $default:expr ;
-              $($unexpanded_var:tt)*
-            }
-            $($expanded_var:tt)*
-          }
-          {{$($unexpanded_fn:tt)*}}
+      $service_name:ident {
+        {
+          let $var:ident : $type_:ty = $default:expr ;
+          $($unexpanded:tt)*
         }
-      )*
+        { $($expanded_vars:tt)* }
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            { $($unexpanded_var)* }
-            $($expanded_var)*
-            let $var : $type_  = $default ;
-          }
-          {{$($unexpanded_fn)*}}
+      $service_name {
+        { $($unexpanded)* }
+        {
+          $($expanded_vars)*
+          let $var : $type_ = $default ;
         }
-      )*
+        $fns
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
+    }}
+  };
+
+  // Bidirectional streaming func, the handler gets both the incoming items and the named sink
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $incoming:ident : stream $in_item:ty $(, $arg:ident : $in_:ty )* ) -> stream $item:ty | $sink:ident | $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        { $($expanded_channels:tt)* }
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        $streams
+        $uploads
+        {
+          $($expanded_channels)*
+          fn $fn_name(&mut $self_ , $incoming : stream $in_item $(, $arg : $in_ )* ) -> stream $item | $sink | $b
+        }
+      }
+    }}
+  };
+
+  // Client-streaming func with no return
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $items:ident : stream $item:ty $(, $arg:ident : $in_:ty )* ) $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        { $($expanded_uploads:tt)* }
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        $streams
+        {
+          $($expanded_uploads)*
+          fn $fn_name(&mut $self_ , $items : stream $item $(, $arg : $in_ )* ) -> () | () $b
+        }
+        $channels
+      }
+    }}
+  };
+
+  // Client-streaming func with return
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $items:ident : stream $item:ty $(, $arg:ident : $in_:ty )* ) -> $out:ty $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        { $($expanded_uploads:tt)* }
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        $streams
+        {
+          $($expanded_uploads)*
+          fn $fn_name(&mut $self_ , $items : stream $item $(, $arg : $in_ )* ) -> $out | () $b
+        }
+        $channels
+      }
+    }}
+  };
+
+  // Client-streaming func with error
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $items:ident : stream $item:ty $(, $arg:ident : $in_:ty )* ) | $err:ty $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        { $($expanded_uploads:tt)* }
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        $streams
+        {
+          $($expanded_uploads)*
+          fn $fn_name(&mut $self_ , $items : stream $item $(, $arg : $in_ )* ) -> () | $err $b
+        }
+        $channels
+      }
+    }}
+  };
+
+  // Client-streaming func with return and error
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $items:ident : stream $item:ty $(, $arg:ident : $in_:ty )* ) -> $out:ty | $err:ty $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        $streams:tt
+        { $($expanded_uploads:tt)* }
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        $streams
+        {
+          $($expanded_uploads)*
+          fn $fn_name(&mut $self_ , $items : stream $item $(, $arg : $in_ )* ) -> $out | $err $b
+        }
+        $channels
+      }
     }}
   };
 
+  // Server-streaming func, items are sent through the named sink
+  (
+    {
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> stream $item:ty | $sink:ident | $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        $oneways:tt
+        { $($expanded_streams:tt)* }
+        $uploads:tt
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        $oneways
+        {
+          $($expanded_streams)*
+          fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> stream $item | $sink | $b
+        }
+        $uploads
+        $channels
+      }
+    }}
+  };
 
   // Func with no return
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {}
-            $($expanded_var:tt)*
-          }
-          {
-            {
-              fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) $b:block
-              $($unexpanded_fn:tt)*
-            }
-            $($expanded_fn:tt)*
-          }
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) $b:block
+          $($unexpanded:tt)*
         }
-      )*
+        $vars:tt
+        { $($expanded_fns:tt)* }
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            {}
-            $($expanded_var)*
-          }
-          {
-            {$($unexpanded_fn:tt)*}
-            $($expanded_fn:tt)*
-            fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> () | () $b:block
-          }
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        {
+          $($expanded_fns)*
+          fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> () | () $b
         }
-      )*
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
     }}
   };
 
   // Func with return
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {}
-            $($expanded_var:tt)*
-          }
-          {
-            {
-              fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty $b:block
-              $($unexpanded_fn:tt)*
-            }
-            $($expanded_fn:tt)*
-          }
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty $b:block
+          $($unexpanded:tt)*
         }
-      )*
+        $vars:tt
+        { $($expanded_fns:tt)* }
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            {}
-            $($expanded_var)*
-          }
-          {
-            { $($unexpanded_fn)* }
-            $($expanded_fn)*
-            fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> $out | () $b
-          }
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        {
+          $($expanded_fns)*
+          fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> $out | () $b
         }
-      )*
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
     }}
   };
 
   // Func with error
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {}
-            $($expanded_var:tt)*
-          }
-          {
-            {
-              fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) | $err:ty $b:block
-              $($unexpanded_fn:tt)*
-            }
-            $($expanded_fn:tt)*
-          }
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) | $err:ty $b:block
+          $($unexpanded:tt)*
         }
-      )*
+        $vars:tt
+        { $($expanded_fns:tt)* }
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            {}
-            $($expanded_var)*
-          }
-          {
-            {$($unexpanded_fn)*}
-            $($expanded_fn)*
-            fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> () | $err $b
-          }
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        {
+          $($expanded_fns)*
+          fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> () | $err $b
         }
-      )*
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
     }}
   };
 
   // Func with return and error
   (
     {
-      $(
-        $service_name:ident {
-          {
-            {}
-            $($expanded_var:tt)*
-          }
-          {
-            {
-              fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty | $err:ty $b:block
-              $($unexpanded_fn:tt)*
-            }
-            $($expanded_fn:tt)*
-          }
+      $service_name:ident {
+        {
+          fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty | $err:ty $b:block
+          $($unexpanded:tt)*
         }
-      )*
+        $vars:tt
+        { $($expanded_fns:tt)* }
+        $oneways:tt
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
     }
   ) => {
     service! {{
-      $(
-        $service_name {
-          {
-            {}
-            $($expanded_var)*
-          }
-          {
-            {$($unexpanded_fn)*}
-            $($expanded_fn)*
-            fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> $out | $err $b
-          }
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        {
+          $($expanded_fns)*
+          fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) -> $out | $err $b
         }
-      )*
+        $oneways
+        $streams
+        $uploads
+        $channels
+      }
+    }}
+  };
+
+  // One-way func, never answered
+  (
+    {
+      $service_name:ident {
+        {
+          oneway fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) $b:block
+          $($unexpanded:tt)*
+        }
+        $vars:tt
+        $fns:tt
+        { $($expanded_oneways:tt)* }
+        $streams:tt
+        $uploads:tt
+        $channels:tt
+      }
+    }
+  ) => {
+    service! {{
+      $service_name {
+        { $($unexpanded)* }
+        $vars
+        $fns
+        {
+          $($expanded_oneways)*
+          oneway fn $fn_name(&mut $self_ , $( $arg : $in_ ),* ) $b
+        }
+        $streams
+        $uploads
+        $channels
+      }
     }}
   };
 
@@ -245,139 +457,559 @@ macro_rules! service {
     {
       $(
         $service_name:ident {
-          {
-            {}
-            $(let $var:ident : $type_:ty = $default:expr ;)*
-          }
-          {
-            {}
-            $(fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty | $error:ty $block:block)*
-          }
+          {}
+          { $(let $var:ident : $type_:ty = $default:expr ;)* }
+          { $(fn $fn_name:ident(&mut $self_:ident , $( $arg:ident : $in_:ty ),* ) -> $out:ty | $error:ty $block:block)* }
+          { $(oneway fn $ow_name:ident(&mut $ow_self:ident , $( $ow_arg:ident : $ow_in:ty ),* ) $ow_block:block)* }
+          { $(fn $st_name:ident(&mut $st_self:ident , $( $st_arg:ident : $st_in:ty ),* ) -> stream $st_item:ty | $st_sink:ident | $st_block:block)* }
+          { $(fn $up_name:ident(&mut $up_self:ident , $up_items:ident : stream $up_item:ty $(, $up_arg:ident : $up_in:ty )* ) -> $up_out:ty | $up_error:ty $up_block:block)* }
+          { $(fn $ch_name:ident(&mut $ch_self:ident , $ch_incoming:ident : stream $ch_in:ty $(, $ch_arg:ident : $ch_arg_in:ty )* ) -> stream $ch_item:ty | $ch_sink:ident | $ch_block:block)* }
         }
       )*
     }
   ) => {
-    pub use $crate::{ Transport, UdpTransport };
-
     $(
       #[allow(non_snake_case)]
       pub mod $service_name {
-        pub use $crate::{ Transport, UdpTransport, TcpTransport };
-        use $crate::utils::{to_socket_addr};
+        pub use $crate::{ Transport, UdpTransport, TcpTransport, TlsTransport, QuicTransport, WebSocketTransport, StdioTransport, UnixTransport, UnixDatagramTransport, InMemoryTransport };
+        use $crate::utils::{to_addr};
         use std::sync::{ Arc, Mutex };
         use std::net::SocketAddr;
         use lazy_static::*;
         use $crate::plugins::Wrapper;
 
-        #[allow(unused)]
-        #[derive(Clone)]
-        pub struct $service_name {
-          pub actual_sender: SocketAddr,
-          $(pub $var: $type_,)*
+        #[allow(unused)]
+        #[derive(Clone)]
+        pub struct $service_name {
+          pub actual_sender: SocketAddr,
+          /// The request being served, set for the duration of each call.
+          pub request_context: Option<$crate::RequestContext>,
+          $(pub $var: $type_,)*
+        }
+
+        impl $service_name {
+          pub fn new() -> $service_name {
+            $service_name {
+              actual_sender: SocketAddr::new("127.0.0.1".parse().unwrap(), 0),
+              request_context: None,
+              $($var: $default,)*
+            }
+          }
+        }
+
+        impl Default for $service_name {
+          fn default() -> $service_name {
+            $service_name::new()
+          }
+        }
+
+        pub trait ServiceTrait {
+          $(
+            fn $fn_name(&mut $self_, $($arg:$in_),*) -> $out;
+          )*
+
+          $(
+            fn $ow_name(&mut $ow_self, $($ow_arg:$ow_in),*);
+          )*
+
+          $(
+            fn $st_name(&mut $st_self, $($st_arg:$st_in,)* $st_sink: $crate::StreamSink<$st_item>);
+          )*
+
+          $(
+            fn $up_name(&mut $up_self, $up_items: $crate::StreamReceiver<$up_item>, $($up_arg:$up_in),*) -> $up_out;
+          )*
+
+          $(
+            fn $ch_name(&mut $ch_self, $ch_incoming: $crate::StreamReceiver<$ch_in>, $($ch_arg:$ch_arg_in,)* $ch_sink: $crate::StreamSink<$ch_item>);
+          )*
+
+          /// Runs the called method. Gives `None` when nothing is to be
+          /// answered: oneway calls, and streams that answer through their sink.
+          fn dispatch(ctx: &mut $service_name, pack: $crate::Packet, req: &$crate::RequestContext) -> Result<Option<$crate::Bytes>, $crate::Error> {
+            let (func_id, body) = $crate::extract_u64_head(pack.data.clone())?;
+
+            let limits = &req.limits;
+
+            // fixme: This is dirty as hell, we redefine a HashMap each time dispatch is called !
+            let mut hmap: $crate::HashMap<usize, Box<Fn() -> Result<Option<$crate::Bytes>, $crate::Error>>> = $crate::HashMap::new();
+
+            $(
+              hmap.insert($crate::hash_ident!($fn_name), Box::new(|| -> Result<Option<$crate::Bytes>, $crate::Error> {
+                let mut ctx_c = ctx.clone();
+
+                let ($($arg,)*) : ($($in_,)*) = $crate::deserialize_bounded(&body, limits.max_request_size)?;
+
+                debug!("Server: {} > {}", &pack.header.sender, stringify!($fn_name));
+
+                ctx_c.actual_sender = pack.header.sender;
+                ctx_c.request_context = Some(req.clone());
+
+                let call_res = &ctx_c.$fn_name($($arg,)*);
+
+                debug!("Server: {} < {}", &pack.header.sender, stringify!($fn_name));
+
+                Ok(Some($crate::bincode::serialize(call_res).unwrap().into()))
+              }));
+            )*
+
+            $(
+              hmap.insert($crate::hash_ident!($ow_name), Box::new(|| -> Result<Option<$crate::Bytes>, $crate::Error> {
+                let mut ctx_c = ctx.clone();
+
+                let ($($ow_arg,)*) : ($($ow_in,)*) = $crate::deserialize_bounded(&body, limits.max_request_size)?;
+
+                debug!("Server: {} > {} (oneway)", &pack.header.sender, stringify!($ow_name));
+
+                ctx_c.actual_sender = pack.header.sender;
+                ctx_c.request_context = Some(req.clone());
+
+                ctx_c.$ow_name($($ow_arg,)*);
+
+                Ok(None)
+              }));
+            )*
+
+            $(
+              hmap.insert($crate::hash_ident!($st_name), Box::new(|| -> Result<Option<$crate::Bytes>, $crate::Error> {
+                let mut ctx_c = ctx.clone();
+
+                let ($($st_arg,)*) : ($($st_in,)*) = $crate::deserialize_bounded(&body, limits.max_request_size)?;
+
+                debug!("Server: {} > {} (stream)", &pack.header.sender, stringify!($st_name));
+
+                ctx_c.actual_sender = pack.header.sender;
+                ctx_c.request_context = Some(req.clone());
+
+                // The stream outlives the call, so it gets a handler of its own
+                let slot = req.handler_slot()?;
+
+                let sink = req.sink::<$st_item>();
+
+                slot.spawn(move || ctx_c.$st_name($($st_arg,)* sink));
+
+                Ok(None)
+              }));
+            )*
+
+            $(
+              hmap.insert($crate::hash_ident!($up_name), Box::new(|| -> Result<Option<$crate::Bytes>, $crate::Error> {
+                let mut ctx_c = ctx.clone();
+
+                let ($($up_arg,)*) : ($($up_in,)*) = $crate::deserialize_bounded(&body, limits.max_request_size)?;
+
+                debug!("Server: {} > {} (upload)", &pack.header.sender, stringify!($up_name));
+
+                ctx_c.actual_sender = pack.header.sender;
+                ctx_c.request_context = Some(req.clone());
+
+                let slot = req.handler_slot()?;
+
+                // Registered before returning, so that no item is missed
+                let items = req.receiver::<$up_item>();
+
+                let req_c = req.clone();
+
+                // Answered once the whole stream is consumed
+                slot.spawn(move || {
+                  let call_res = &ctx_c.$up_name(items, $($up_arg,)*);
+
+                  debug!("Server: {} < {} (upload)", &req_c.peer, stringify!($up_name));
+
+                  req_c.answer($crate::bincode::serialize(call_res).unwrap().into());
+                });
+
+                Ok(None)
+              }));
+            )*
+
+            $(
+              hmap.insert($crate::hash_ident!($ch_name), Box::new(|| -> Result<Option<$crate::Bytes>, $crate::Error> {
+                let mut ctx_c = ctx.clone();
+
+                let ($($ch_arg,)*) : ($($ch_arg_in,)*) = $crate::deserialize_bounded(&body, limits.max_request_size)?;
+
+                debug!("Server: {} > {} (channel)", &pack.header.sender, stringify!($ch_name));
+
+                ctx_c.actual_sender = pack.header.sender;
+                ctx_c.request_context = Some(req.clone());
+
+                let slot = req.handler_slot()?;
+
+                let incoming = req.receiver::<$ch_in>();
+                let sink = req.sink::<$ch_item>();
+
+                slot.spawn(move || ctx_c.$ch_name(incoming, $($ch_arg,)* sink));
+
+                Ok(None)
+              }));
+            )*
+
+            let tocall = hmap.get(&(func_id as usize)).ok_or($crate::Error::UnknownMethod)?;
+
+            tocall()
+          }
+
+          fn dispatch_batch(ctx: &mut $service_name, pack: $crate::Packet, req: &$crate::RequestContext) -> Result<$crate::Bytes, $crate::Error> {
+            let calls: Vec<$crate::Bytes> = $crate::deserialize_bounded(&pack.data, req.limits.max_request_size)?;
+
+            debug!("Server: {} > batch of {}", &pack.header.sender, calls.len());
+
+            // Only plain methods are answered in a batch, so no other one is
+            // run before the batch is refused
+            let not_plain: &[usize] = &[
+              $($crate::hash_ident!($ow_name),)*
+              $($crate::hash_ident!($st_name),)*
+              $($crate::hash_ident!($up_name),)*
+              $($crate::hash_ident!($ch_name),)*
+            ];
+
+            let refused = calls.iter().any(|data| {
+              matches!($crate::extract_u64_head(data.clone()), Ok((func_id, _)) if not_plain.contains(&(func_id as usize)))
+            });
+
+            if refused {
+              warn!("Refusing batch from {}: not only plain methods", &pack.header.sender);
+
+              return Err($crate::Error::UnknownMethod);
+            }
+
+            let results: Vec<Result<$crate::Bytes, $crate::Error>> = calls
+              .into_iter()
+              .map(|data| {
+                let mut call = pack.clone();
+
+                call.data = data;
+
+                Self::dispatch(ctx, call, req).and_then(|res| res.ok_or($crate::Error::UnknownMethod))
+              })
+              .collect();
+
+            Ok($crate::bincode::serialize(&results).unwrap().into())
+          }
+        }
+
+        #[allow(unused)]
+        #[derive(Clone)]
+        pub struct Client<T: Transport> {
+          pub serv_addr: $crate::SocketAddr,
+          pub network: $crate::Network<T>,
+        }
+
+        impl<T: 'static + Transport> Client<T> {
+          /// A client for the peer that sent the request, calling back over
+          /// the connection the request came from. The peer serves it with
+          /// `listen_with_network` on its own client's network.
+          #[allow(unused)]
+          pub fn for_peer(req: &$crate::RequestContext) -> Option<Client<T>> {
+            req.peer_network::<T>().map(|network| Client {
+              serv_addr: req.peer,
+              network,
+            })
+          }
+
+          #[allow(unused)]
+          fn wait(&mut self) {
+            self.network.wait();
+          }
+
+          #[allow(unused)]
+          pub fn close(&mut self) {
+            trace!("Client: Closing...");
+
+            self.network.close();
+
+            self.wait();
+
+            debug!("Client: Closed");
+          }
+          /// Sends calls without waiting for their answer, each giving a
+          /// `Call` to await, wait on, or cancel.
+          #[allow(unused)]
+          pub fn calls(&self) -> Calls<'_, T> {
+            Calls { client: self }
+          }
+
+          /// The methods of this client as futures, for async code.
+          #[allow(unused)]
+          pub fn async_calls(&self) -> AsyncCalls<'_, T> {
+            AsyncCalls { client: self }
+          }
+
+          /// Starts a batch of calls sent in a single request by `Batch::send`.
+          #[allow(unused)]
+          pub fn batch(&self) -> Batch<'_, T, ()> {
+            Batch {
+              client: self,
+              calls: vec![],
+              decode: Box::new(|_| ()),
+            }
+          }
+
+          /// Subscribes to a topic published by the server.
+          #[allow(unused)]
+          pub fn subscribe<P: $crate::serde::de::DeserializeOwned>(&self, topic: &str) -> Result<$crate::Subscription<P>, $crate::Error> {
+            self.network.subscribe(&self.serv_addr, topic)
+          }
+
+          #[allow(unused)]
+          fn get_serv_addr(&self) -> $crate::SocketAddr {
+            self.serv_addr.clone()
+          }
+
+          #[allow(unused)]
+          fn send(&self, addr: &$crate::SocketAddr, data: $crate::Bytes) -> Result<$crate::Bytes, $crate::Error> {
+            self.network.send(addr, data)
+          }
+
+          #[allow(unused)]
+          pub fn set_limits(&mut self, limits: $crate::Limits) {
+            self.network.set_limits(limits);
+          }
+
+          #[allow(unused)]
+          pub fn set_flow_control(&mut self, flow: $crate::FlowControl) {
+            self.network.set_flow_control(flow);
+          }
+
+          $(
+            #[allow(unused)]
+            pub fn $fn_name(&self, $($arg:$in_),*) -> Result<Result<$out, $error>, $crate::Error> {
+              $crate::runtime::block_on(self.async_calls().$fn_name($($arg),*))
+            }
+          )*
+
+          $(
+            #[allow(unused)]
+            pub fn $ow_name(&self, $($ow_arg:$ow_in),*) -> Result<(), $crate::Error> {
+              $crate::runtime::block_on(self.async_calls().$ow_name($($ow_arg),*))
+            }
+          )*
+
+          $(
+            #[allow(unused)]
+            pub fn $st_name(&self, $($st_arg:$st_in),*) -> Result<$crate::StreamReceiver<$st_item>, $crate::Error> {
+              $crate::runtime::block_on(self.async_calls().$st_name($($st_arg),*))
+            }
+          )*
+
+          $(
+            /// Items go through the returned `Upload`, and `finish` gives the result.
+            #[allow(unused)]
+            pub fn $up_name(&self, $($up_arg:$up_in),*) -> Result<$crate::Upload<$up_item, $up_out, $up_error>, $crate::Error> {
+              $crate::runtime::block_on(self.async_calls().$up_name($($up_arg),*))
+            }
+          )*
+
+          $(
+            /// Opens a channel: dropping the sink ends the items sent to the
+            /// server, and the receiver ends when the server is done.
+            #[allow(unused)]
+            pub fn $ch_name(&self, $($ch_arg:$ch_arg_in),*) -> Result<($crate::StreamSink<$ch_in>, $crate::StreamReceiver<$ch_item>), $crate::Error> {
+              $crate::runtime::block_on(self.async_calls().$ch_name($($ch_arg),*))
+            }
+          )*
+        }
+
+        /// The methods of `Client` as futures, sending and waiting without
+        /// blocking: `client.async_calls().hello(..).await`.
+        pub struct AsyncCalls<'a, T: Transport> {
+          client: &'a Client<T>,
         }
 
-        impl $service_name {
-          pub fn new() -> $service_name {
-            $service_name {
-              actual_sender: SocketAddr::new("127.0.0.1".parse().unwrap(), 0),
-              $($var: $default,)*
-            }
+        impl<'a, T: Transport> Clone for AsyncCalls<'a, T> {
+          fn clone(&self) -> Self {
+            *self
           }
         }
 
-        pub trait ServiceTrait {
+        impl<'a, T: Transport> Copy for AsyncCalls<'a, T> {}
+
+        impl<'a, T: 'static + Transport> AsyncCalls<'a, T> {
+          /// Subscribes to a topic published by the server.
+          #[allow(unused)]
+          pub async fn subscribe<P: $crate::serde::de::DeserializeOwned>(self, topic: &str) -> Result<$crate::Subscription<P>, $crate::Error> {
+            self.client.network.subscribe_async(&self.client.serv_addr, topic).await
+          }
+
           $(
-            fn $fn_name(&mut $self_, $($arg:$in_),*) -> $out;
-          )*
+            #[allow(unused)]
+            pub async fn $fn_name(self, $($arg:$in_),*) -> Result<Result<$out, $error>, $crate::Error> {
+              let req_data = ($($arg,)*);
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($fn_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
 
-          fn dispatch(ctx: &mut $service_name, pack: $crate::Packet) -> Vec<u8> {
-            let (func_id, body) = $crate::extract_u64_head(pack.data.clone());
+              debug!("Client: {} < {}", addr, stringify!($fn_name));
 
-            // fixme: This is dirty as hell, we redefine a HashMap each time dispatch is called !
-            let mut hmap: $crate::HashMap<usize, Box<Fn() -> Vec<u8>>> = $crate::HashMap::new();
+              let res = self.client.network.send_async(&addr, req_bytes).await;
 
-            $(
-              hmap.insert($crate::hash_ident!($fn_name), Box::new(|| -> Vec<u8> {
-                let mut ctx_c = ctx.clone();
+              let max_response_size = self.client.network.limits.get().max_response_size;
 
-                let ($($arg,)*) : ($($in_,)*) = $crate::bincode::deserialize(&body).unwrap();
+              res.and_then(|data| {
+                debug!("Client: {} > {}", addr, stringify!($fn_name));
 
-                debug!("Server: {} > {}", &pack.header.sender, stringify!($fn_name));
+                $crate::deserialize_bounded(&data, max_response_size).map(Ok)
+              }).map_err(|err| {
+                error!("Error client send for {}: {}", stringify!($fn_name), err);
 
-                ctx_c.actual_sender = pack.header.sender;
+                err
+              })
+            }
+          )*
 
-                let call_res = &ctx_c.$fn_name($($arg,)*);
+          $(
+            #[allow(unused)]
+            pub async fn $ow_name(self, $($ow_arg:$ow_in),*) -> Result<(), $crate::Error> {
+              let req_data = ($($ow_arg,)*);
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($ow_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
 
-                debug!("Server: {} < {}", &pack.header.sender, stringify!($fn_name));
+              debug!("Client: {} < {} (oneway)", addr, stringify!($ow_name));
 
-                $crate::bincode::serialize(call_res).unwrap()
-              }));
-            )*;
+              self.client.network.notify_async(&addr, req_bytes).await.map_err(|err| {
+                error!("Error client notify for {}: {}", stringify!($ow_name), err);
 
-            let tocall = hmap.get(&(func_id as usize)).unwrap();
+                err
+              })
+            }
+          )*
 
-            tocall()
-          }
-        }
+          $(
+            #[allow(unused)]
+            pub async fn $st_name(self, $($st_arg:$st_in),*) -> Result<$crate::StreamReceiver<$st_item>, $crate::Error> {
+              let req_data = ($($st_arg,)*);
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($st_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
 
-        #[allow(unused)]
-        #[derive(Clone)]
-        pub struct Client<T: Transport> {
-          pub serv_addr: $crate::SocketAddr,
-          pub network: $crate::Network<T>,
-        }
+              debug!("Client: {} < {} (stream)", addr, stringify!($st_name));
 
-        impl<T: 'static + Transport> Client<T> {
-          #[allow(unused)]
-          fn wait(&mut self) {
-            self.network.wait();
-          }
+              self.client.network.send_stream_async(&addr, req_bytes).await.map_err(|err| {
+                error!("Error client send for {}: {}", stringify!($st_name), err);
 
-          #[allow(unused)]
-          pub fn close(&mut self) {
-            trace!("Client: Closing...");
+                err
+              })
+            }
+          )*
 
-            self.network.close();
+          $(
+            /// Items go through the returned `Upload`, and `finish` gives the result.
+            #[allow(unused)]
+            pub async fn $up_name(self, $($up_arg:$up_in),*) -> Result<$crate::Upload<$up_item, $up_out, $up_error>, $crate::Error> {
+              let req_data = ($($up_arg,)*);
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($up_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
 
-            self.wait();
+              debug!("Client: {} < {} (upload)", addr, stringify!($up_name));
 
-            debug!("Client: Closed");
-          }
-          #[allow(unused)]
-          fn get_serv_addr(&mut self) -> $crate::SocketAddr {
-            self.serv_addr.clone()
-          }
+              self.client.network.send_upload_async(&addr, req_bytes).await.map_err(|err| {
+                error!("Error client send for {}: {}", stringify!($up_name), err);
 
-          #[allow(unused)]
-          fn send(&mut self, addr: &$crate::SocketAddr, data: Vec<u8>) -> Result<Vec<u8>, String> {
-            self.network.send(addr, data)
-          }
+                err
+              })
+            }
+          )*
 
           $(
+            /// Opens a channel: dropping the sink ends the items sent to the
+            /// server, and the receiver ends when the server is done.
+            #[allow(unused)]
+            pub async fn $ch_name(self, $($ch_arg:$ch_arg_in),*) -> Result<($crate::StreamSink<$ch_in>, $crate::StreamReceiver<$ch_item>), $crate::Error> {
+              let req_data = ($($ch_arg,)*);
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($ch_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
+
+              debug!("Client: {} < {} (channel)", addr, stringify!($ch_name));
+
+              self.client.network.send_channel_async(&addr, req_bytes).await.map_err(|err| {
+                error!("Error client send for {}: {}", stringify!($ch_name), err);
+
+                err
+              })
+            }
+          )*
+        }
 
+        /// Accumulates calls, each one nesting its own result into `R`:
+        /// `client.calls().hello(..)` gives a `Call` answered by `hello`.
+        // Unread by services without plain methods
+        #[allow(dead_code)]
+        pub struct Calls<'a, T: Transport> {
+          client: &'a Client<T>,
+        }
+
+        impl<'a, T: 'static + Transport> Calls<'a, T> {
+          $(
             #[allow(unused)]
-            pub fn $fn_name(&mut self, $($arg:$in_),*) -> Result<Result<$out, $error>, String> {
+            pub fn $fn_name(&self, $($arg:$in_),*) -> Result<$crate::Call<$out, $error>, $crate::Error> {
               let req_data = ($($arg,)*);
-              let req_data_bytes = $crate::bincode::serialize(&req_data).unwrap();
-              let req_bytes = $crate::prepend_u64($crate::hash_ident!($fn_name) as u64, req_data_bytes);
-              let addr = self.get_serv_addr();
+              let req_bytes = $crate::serialize_with_u64_head($crate::hash_ident!($fn_name) as u64, &req_data);
+              let addr = self.client.get_serv_addr();
 
-              debug!("Client: {} < {}", addr, stringify!($fn_name));
+              debug!("Client: {} < {} (call)", addr, stringify!($fn_name));
 
-              let res = self.send(&addr, req_bytes);
+              self.client.network.call(&addr, req_bytes)
+            }
+          )*
+        }
 
-              res.map(|data| {
-                debug!("Client: {} > {}", addr, stringify!($fn_name));
+        /// `client.batch().hello(..).eq(..).send()` gives `Ok((((), hello), eq))`.
+        pub struct Batch<'a, T: Transport, R> {
+          client: &'a Client<T>,
+          calls: Vec<$crate::Bytes>,
+          decode: Box<dyn FnOnce(&mut std::vec::IntoIter<Result<$crate::Bytes, $crate::Error>>) -> R + Send>,
+        }
 
-                Ok($crate::bincode::deserialize(&data).unwrap())
-              }).map_err(|err| {
-                error!("Error client send for {}", stringify!($fn_name));
+        impl<'a, T: 'static + Transport, R: 'static> Batch<'a, T, R> {
+          $(
+            #[allow(unused)]
+            pub fn $fn_name(mut self, $($arg:$in_),*) -> Batch<'a, T, (R, Result<Result<$out, $error>, $crate::Error>)> {
+              let req_data = ($($arg,)*);
 
-                Default::default()
-              })
+              self.calls.push($crate::serialize_with_u64_head($crate::hash_ident!($fn_name) as u64, &req_data));
+
+              let decode = self.decode;
+              let max_response_size = self.client.network.limits.get().max_response_size;
+
+              Batch {
+                client: self.client,
+                calls: self.calls,
+                decode: Box::new(move |results| {
+                  let prev = decode(results);
 
+                  let res = match results.next() {
+                    Some(Ok(data)) => $crate::deserialize_bounded(&data, max_response_size).map(Ok),
+                    Some(Err(err)) => Err(err),
+                    None => Err($crate::Error::Deserialize("Missing batch result".to_string())),
+                  };
+
+                  (prev, res)
+                }),
+              }
             }
           )*
+
+          #[allow(unused)]
+          pub fn send(self) -> Result<R, $crate::Error> {
+            $crate::runtime::block_on(self.send_async())
+          }
+
+          /// `send` for async code.
+          #[allow(unused)]
+          pub async fn send_async(self) -> Result<R, $crate::Error> {
+            let addr = self.client.get_serv_addr();
+
+            debug!("Client: {} < batch of {}", addr, self.calls.len());
+
+            let results = self.client.network.send_batch_async(&addr, self.calls).await.map_err(|err| {
+              error!("Error client send for batch: {}", err);
+
+              err
+            })?;
+
+            debug!("Client: {} > batch of {}", addr, results.len());
+
+            Ok((self.decode)(&mut results.into_iter()))
+          }
         }
 
         #[derive(Clone)]
@@ -401,6 +1033,32 @@ macro_rules! service {
             self.network.wait();
           }
 
+          #[allow(unused)]
+          pub fn set_limits(&mut self, limits: $crate::Limits) {
+            self.network.set_limits(limits);
+          }
+
+          #[allow(unused)]
+          pub fn set_flow_control(&mut self, flow: $crate::FlowControl) {
+            self.network.set_flow_control(flow);
+          }
+
+          /// Publishes a message to every client subscribed to `topic`.
+          #[allow(unused)]
+          pub fn publish<P: $crate::serde::Serialize>(&self, topic: &str, payload: &P) -> Result<usize, $crate::Error> {
+            self.network.publish(topic, payload)
+          }
+
+          #[allow(unused)]
+          pub fn subscribers(&self, topic: &str) -> usize {
+            self.network.subscribers(topic)
+          }
+
+          #[allow(unused)]
+          pub fn set_pubsub_config(&mut self, config: $crate::PubSubConfig) {
+            self.network.set_pubsub_config(config);
+          }
+
           #[allow(unused)]
           pub fn close(&mut self) {
             trace!("Server: Closing...");
@@ -418,31 +1076,56 @@ macro_rules! service {
         }
 
         lazy_static! {
-          pub static ref DUPLEX: Arc<Mutex<Option<$crate::Network<UdpTransport>>>> = Arc::new(Mutex::new(None));
+          pub static ref DUPLEX: Arc<Mutex<Option<Box<dyn $crate::network::SharedNetwork>>>> = Arc::new(Mutex::new(None));
         }
 
         impl Duplex {
           #[allow(unused)]
           pub fn listen(addr: &str) -> Server<UdpTransport> {
-            let mut network = $crate::Network::new_default(&$crate::utils::to_socket_addr(addr));
+            Self::listen_with::<UdpTransport>(addr)
+          }
+
+          #[allow(unused)]
+          pub fn listen_with<T: 'static + Transport>(addr: &str) -> Server<T> {
+            let mut network = $crate::Network::<T>::new_default(&to_addr(addr));
 
             network.listen();
 
             let mut guard = DUPLEX.lock().unwrap();
 
-            *guard = Some(network.clone());
+            *guard = Some(Box::new(network.clone()));
 
             listen_with_network(network)
           }
 
           #[allow(unused)]
           pub fn connect(addr: &str) -> Client<UdpTransport> {
-            let mut net = DUPLEX.lock().unwrap().as_ref().unwrap().clone();
+            Self::connect_with::<UdpTransport>(addr).unwrap()
+          }
+
+          /// Connects to `addr` from the socket listened on with `listen_with`.
+          #[allow(unused)]
+          pub fn connect_with<T: 'static + Transport>(addr: &str) -> Result<Client<T>, String> {
+            let mut network = match DUPLEX.lock().unwrap().as_ref().and_then(|net| net.as_any().downcast_ref::<$crate::Network<T>>()) {
+              Some(net) => net.clone(),
+              None => return Err("Duplex: Not listening with this transport".to_string()),
+            };
+
+            let addr: T::Addr = to_addr(addr);
+
+            let serv_addr = match network.transport.resolve(&addr) {
+              Some(serv_addr) => serv_addr,
+              None => return Err(format!("Duplex: Cannot send to {:?} from a shared socket", addr)),
+            };
 
-            net.connect().unwrap();
+            network.handle = None;
 
+            debug!("Client: Connected {:?}", addr);
 
-            connect_with_network(net)
+            Ok(Client {
+              serv_addr,
+              network,
+            })
           }
 
           #[allow(unused)]
@@ -453,13 +1136,8 @@ macro_rules! service {
 
             {
               let mut guard = DUPLEX.lock().unwrap();
-              let mut n = (*guard).take().unwrap();
 
-              net = n.clone();
-
-              n.handle = None;
-
-              *guard = Some(n);
+              net = guard.as_mut().unwrap().detach();
             }
 
             net.wait();
@@ -472,7 +1150,7 @@ macro_rules! service {
             let mut net;
             {
               let mut guard = DUPLEX.lock().unwrap();
-              net = (*guard).take().unwrap().clone();
+              net = (*guard).take().unwrap();
             }
 
             net.close();
@@ -489,7 +1167,7 @@ macro_rules! service {
             let mut guard = DUPLEX.lock().unwrap();
 
             for net in  (*guard).iter_mut() {
-              net.plugins.add(plugin.clone());
+              net.plugins().add(plugin.clone());
             }
           }
         }
@@ -498,6 +1176,22 @@ macro_rules! service {
           $(
             fn $fn_name(&mut $self_, $( $arg : $in_ ),* ) -> $out $block
           )*
+
+          $(
+            fn $ow_name(&mut $ow_self, $( $ow_arg : $ow_in ),* ) $ow_block
+          )*
+
+          $(
+            fn $st_name(&mut $st_self, $( $st_arg : $st_in, )* $st_sink: $crate::StreamSink<$st_item>) $st_block
+          )*
+
+          $(
+            fn $up_name(&mut $up_self, $up_items: $crate::StreamReceiver<$up_item>, $( $up_arg : $up_in ),* ) -> $up_out $up_block
+          )*
+
+          $(
+            fn $ch_name(&mut $ch_self, $ch_incoming: $crate::StreamReceiver<$ch_in>, $( $ch_arg : $ch_arg_in, )* $ch_sink: $crate::StreamSink<$ch_item>) $ch_block
+          )*
         }
 
         #[allow(unused)]
@@ -510,21 +1204,96 @@ macro_rules! service {
           connect_with::<TcpTransport>(serv_addr)
         }
 
+        #[allow(unused)]
+        pub fn connect_udp_with_options(serv_addr: &str, options: &$crate::SocketOptions) -> Result<Client<UdpTransport>, String> {
+          let mut transport = UdpTransport::new(&to_addr(serv_addr));
+
+          transport.set_options(options.clone());
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.connect()?;
+
+          Ok(connect_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn connect_tcp_with_options(serv_addr: &str, options: &$crate::SocketOptions) -> Result<Client<TcpTransport>, String> {
+          let mut transport = TcpTransport::new(&to_addr(serv_addr));
+
+          transport.set_options(options.clone());
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.connect()?;
+
+          Ok(connect_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn connect_tls(serv_addr: &str, config: &$crate::TlsConfig) -> Result<Client<TlsTransport>, String> {
+          let mut transport = TlsTransport::new(&to_addr(serv_addr));
+
+          transport.set_config(config)?;
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.connect()?;
+
+          Ok(connect_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn connect_quic(serv_addr: &str, config: &$crate::TlsConfig) -> Result<Client<QuicTransport>, String> {
+          let mut transport = QuicTransport::new(&to_addr(serv_addr));
+
+          transport.set_config(config)?;
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.connect()?;
+
+          Ok(connect_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn connect_websocket(url: &str) -> Result<Client<WebSocketTransport>, String> {
+          connect_with::<WebSocketTransport>(url)
+        }
+
+        #[allow(unused)]
+        pub fn connect_unix(path: &str) -> Result<Client<UnixTransport>, String> {
+          connect_with::<UnixTransport>(path)
+        }
+
+        #[allow(unused)]
+        pub fn connect_unix_datagram(path: &str) -> Result<Client<UnixDatagramTransport>, String> {
+          connect_with::<UnixDatagramTransport>(path)
+        }
+
+        #[allow(unused)]
+        pub fn connect_stdio(command: &str) -> Result<Client<StdioTransport>, String> {
+          connect_with::<StdioTransport>(command)
+        }
+
+        #[allow(unused)]
+        pub fn connect_in_memory(name: &str) -> Result<Client<InMemoryTransport>, String> {
+          connect_with::<InMemoryTransport>(name)
+        }
+
         pub fn connect_with<T: 'static +  Transport>(serv_addr: &str) -> Result<Client<T>, String> {
-          let mut network = $crate::Network::new_default(&to_socket_addr(serv_addr));
+          let mut network = $crate::Network::<T>::new_default(&to_addr(serv_addr));
 
-          if let Err(e) = network.connect() {
-            return Err(e);
-          }
+          network.connect()?;
 
           Ok(connect_with_network(network))
         }
 
         pub fn connect_with_network<T: 'static +  Transport>(network: $crate::Network<T>) -> Client<T> {
-          debug!("Client: Connected {}", network.transport.get_addr());
+          debug!("Client: Connected {:?}", network.transport.get_addr());
 
           Client {
-            serv_addr: network.transport.get_addr(),
+            serv_addr: network.transport.peer_addr(),
             network,
           }
         }
@@ -539,9 +1308,103 @@ macro_rules! service {
           listen_with::<TcpTransport>(addr)
         }
 
+        #[allow(unused)]
+        pub fn listen_udp_with_options(addr: &str, options: &$crate::SocketOptions) -> Server<$crate::UdpTransport> {
+          let mut transport = UdpTransport::new(&to_addr(addr));
+
+          transport.set_options(options.clone());
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.listen();
+
+          listen_with_network(network)
+        }
+
+        #[allow(unused)]
+        pub fn listen_tcp_with_options(addr: &str, options: &$crate::SocketOptions) -> Server<$crate::TcpTransport> {
+          let mut transport = TcpTransport::new(&to_addr(addr));
+
+          transport.set_options(options.clone());
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.listen();
+
+          listen_with_network(network)
+        }
+
+        #[allow(unused)]
+        pub fn listen_tls(addr: &str, config: &$crate::TlsConfig) -> Result<Server<$crate::TlsTransport>, String> {
+          let mut transport = TlsTransport::new(&to_addr(addr));
+
+          transport.set_config(config)?;
+
+          if !transport.can_listen() {
+            return Err("TlsTransport: No certificate to listen with".to_string());
+          }
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.listen();
+
+          Ok(listen_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn listen_quic(addr: &str, config: &$crate::TlsConfig) -> Result<Server<$crate::QuicTransport>, String> {
+          let mut transport = QuicTransport::new(&to_addr(addr));
+
+          transport.set_config(config)?;
+
+          if !transport.can_listen() {
+            return Err("QuicTransport: No certificate to listen with".to_string());
+          }
+
+          let mut network = $crate::Network::new(transport, $crate::ServerCallback::new_empty());
+
+          network.listen();
+
+          Ok(listen_with_network(network))
+        }
+
+        #[allow(unused)]
+        pub fn listen_websocket(url: &str) -> Server<$crate::WebSocketTransport> {
+          listen_with::<WebSocketTransport>(url)
+        }
+
+        #[allow(unused)]
+        pub fn listen_unix(path: &str) -> Server<$crate::UnixTransport> {
+          listen_with::<UnixTransport>(path)
+        }
+
+        #[allow(unused)]
+        pub fn listen_unix_datagram(path: &str) -> Server<$crate::UnixDatagramTransport> {
+          listen_with::<UnixDatagramTransport>(path)
+        }
+
+        /// Serves on the stdin and stdout of this process, until they are
+        /// closed by the other end.
+        #[allow(unused)]
+        pub fn listen_stdio() -> Server<$crate::StdioTransport> {
+          let network = $crate::Network::<StdioTransport>::new_default(&Default::default());
+
+          // Served before reading, the parent writing as soon as it spawned us
+          let mut server = listen_with_network(network);
+
+          server.network.listen();
+
+          server
+        }
+
+        #[allow(unused)]
+        pub fn listen_in_memory(name: &str) -> Server<$crate::InMemoryTransport> {
+          listen_with::<InMemoryTransport>(name)
+        }
+
         #[allow(unused)]
         pub fn listen_with<T: 'static +  Transport>(addr: &str) -> Server<T> {
-          let mut network = $crate::Network::new_default(&to_socket_addr(addr));
+          let mut network = $crate::Network::<T>::new_default(&to_addr(addr));
 
           network.listen();
 
@@ -550,18 +1413,19 @@ macro_rules! service {
 
         #[allow(unused)]
         pub fn listen_with_network<T: 'static +  Transport>(net: $crate::Network<T>) -> Server<T> {
-          debug!("Server: Listening {}", net.transport.get_addr());
+          debug!("Server: Listening {:?}", net.transport.get_addr());
           let mut net_c = net.clone();
 
           net_c.handle = None;
 
-          let mut server = Server::new(net_c.clone());
+          // The only one keeping the task handle, to wait on it
+          let mut server = Server::new(net);
 
           let mut context = server.context.clone();
 
           server.network.set_callback($crate::ServerCallback {
             closure: Arc::new(move |pack, from| {
-              if pack.header.response_to.len() == 0 {
+              if pack.header.response_to == 0 {
 
                 let mut net = net_c.clone();
 
@@ -569,9 +1433,27 @@ macro_rules! service {
 
                 let mut guard = context.lock().unwrap();
 
-                let res = $service_name::dispatch(&mut *guard, pack.clone());
-
-                $crate::Network::send_answer(&mut net, &from, res, pack.header.msg_hash.clone());
+                let req = net.request_context(&pack, from);
+
+                let res = if pack.header.kind == $crate::proto::PacketKind::Batch {
+                  $service_name::dispatch_batch(&mut *guard, pack.clone(), &req).map(Some)
+                } else {
+                  $service_name::dispatch(&mut *guard, pack.clone(), &req)
+                };
+
+                if pack.header.kind == $crate::proto::PacketKind::Oneway {
+                  if let Err(err) = res {
+                    error!("Server: oneway call from {} failed: {}", from, err);
+                  }
+                } else if req.is_cancelled() {
+                  debug!("Server: {} cancelled {}", from, pack.header.id);
+                } else {
+                  match res {
+                    Ok(Some(res)) => $crate::Network::send_answer(&mut net, &from, res, pack.header.id),
+                    Ok(None) => (),
+                    Err(err) => $crate::Network::send_error(&mut net, &from, err, pack.header.id),
+                  }
+                }
               }
 
               pack
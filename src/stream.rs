@@ -0,0 +1,305 @@
+use bytes::Bytes;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::executor::block_on;
+use futures::future::BoxFuture;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use super::call::Canceller;
+use super::error::Error;
+use super::flow::{Grantor, SendWindow};
+use super::proto::{Packet, PacketKind};
+use super::request_context::{AsyncPacketSender, InFlight};
+use super::runtime;
+use super::utils::deserialize_bounded;
+
+/// Sending half of a stream.
+///
+/// Every item is sent as its own packet tagged with the request id. The
+/// end-of-stream marker is sent when the sink is dropped, unless the stream
+/// was ended with `fail`.
+pub struct StreamSink<T> {
+  send: AsyncPacketSender,
+  peer: SocketAddr,
+  local_addr: SocketAddr,
+  id: u64,
+  // Items answer request `id` when sent by the server, and belong to it
+  // when sent by the caller
+  answers: bool,
+  max_size: usize,
+  done: bool,
+  // The request answered, which refuses items once cancelled
+  in_flight: Option<Arc<InFlight>>,
+  // Credits granted by the receiver, if it grants any
+  window: Option<Arc<SendWindow>>,
+  _item: PhantomData<fn(T)>,
+}
+
+impl<T: Serialize> StreamSink<T> {
+  /// A sink answering the request `id` received from `peer`.
+  pub fn new(send: AsyncPacketSender, peer: SocketAddr, local_addr: SocketAddr, id: u64, max_size: usize) -> StreamSink<T> {
+    StreamSink {
+      send,
+      peer,
+      local_addr,
+      id,
+      answers: true,
+      max_size,
+      done: false,
+      in_flight: None,
+      window: None,
+      _item: PhantomData,
+    }
+  }
+
+  /// A sink streaming items along the request `id` sent to `peer`.
+  pub fn new_request(
+    send: AsyncPacketSender,
+    peer: SocketAddr,
+    local_addr: SocketAddr,
+    id: u64,
+    max_size: usize,
+  ) -> StreamSink<T> {
+    let mut sink = StreamSink::new(send, peer, local_addr, id, max_size);
+
+    sink.answers = false;
+
+    sink
+  }
+
+  /// Fails sending once `in_flight` is cancelled by the peer.
+  pub fn cancelled_by(mut self, in_flight: Arc<InFlight>) -> StreamSink<T> {
+    self.in_flight = Some(in_flight);
+
+    self
+  }
+
+  /// Spends a credit of `window` on every item sent.
+  pub fn with_window(mut self, window: Arc<SendWindow>) -> StreamSink<T> {
+    self.window = Some(window);
+
+    self
+  }
+
+  /// Sends an item, blocking while the receiver grants no credit.
+  pub fn send(&self, item: T) -> Result<(), Error> {
+    runtime::block_on(self.send_async(item))
+  }
+
+  /// Sends an item once the receiver grants a credit.
+  pub async fn send_async(&self, item: T) -> Result<(), Error> {
+    if self.is_cancelled() {
+      return Err(Error::Cancelled);
+    }
+
+    let data = Bytes::from(bincode::serialize(&item).unwrap());
+
+    if data.len() > self.max_size {
+      return Err(Error::MessageTooLarge);
+    }
+
+    if let Some(window) = &self.window {
+      window.acquire().await?;
+    }
+
+    self.send_kind(PacketKind::StreamItem, data).await
+  }
+
+  /// Ends the stream with an error instead of the end-of-stream marker.
+  pub fn fail(mut self, err: Error) {
+    self.done = true;
+
+    let _ = runtime::block_on(self.send_kind(PacketKind::Error(err), Bytes::new()));
+  }
+}
+
+impl<T> StreamSink<T> {
+  pub fn is_cancelled(&self) -> bool {
+    self.in_flight.as_ref().is_some_and(|in_flight| in_flight.is_cancelled())
+  }
+
+  /// Ends the stream without blocking, as dropping the sink does.
+  async fn end(mut self) {
+    self.done = true;
+
+    if !self.is_cancelled() {
+      let _ = self.send_kind(PacketKind::StreamEnd, Bytes::new()).await;
+    }
+  }
+
+  async fn send_kind(&self, kind: PacketKind, data: Bytes) -> Result<(), Error> {
+    let pack = if self.answers {
+      Packet::with_kind(kind, data, self.local_addr, self.id)
+    } else {
+      let mut pack = Packet::with_kind(kind, data, self.local_addr, 0);
+
+      pack.header.id = self.id;
+
+      pack
+    };
+
+    if (self.send)(self.peer, pack).await {
+      Ok(())
+    } else {
+      Err(Error::SendFailed)
+    }
+  }
+}
+
+impl<T> Drop for StreamSink<T> {
+  fn drop(&mut self) {
+    if !self.done && !self.is_cancelled() {
+      let _ = runtime::block_on(self.send_kind(PacketKind::StreamEnd, Bytes::new()));
+    }
+  }
+}
+
+/// Client side of a client-streaming call: items are sent through `send`,
+/// and `finish` ends the stream then waits for the single result. Dropping
+/// it without finishing cancels the call.
+pub struct Upload<T, R, E> {
+  // Dropped first, so that the end of the stream is not taken for a finish
+  canceller: Canceller,
+  sink: StreamSink<T>,
+  // Locked only to be `Sync`, so that items can be sent from async code
+  result: Mutex<BoxFuture<'static, Result<Bytes, Error>>>,
+  max_size: usize,
+  _res: PhantomData<fn() -> Result<R, E>>,
+}
+
+impl<T: Serialize, R: DeserializeOwned, E> Upload<T, R, E> {
+  pub fn new(
+    sink: StreamSink<T>,
+    result: BoxFuture<'static, Result<Bytes, Error>>,
+    canceller: Canceller,
+    max_size: usize,
+  ) -> Upload<T, R, E> {
+    Upload {
+      canceller,
+      sink,
+      result: Mutex::new(result),
+      max_size,
+      _res: PhantomData,
+    }
+  }
+
+  pub fn send(&self, item: T) -> Result<(), Error> {
+    self.sink.send(item)
+  }
+
+  pub async fn send_async(&self, item: T) -> Result<(), Error> {
+    self.sink.send_async(item).await
+  }
+
+  pub fn finish(self) -> Result<Result<R, E>, Error> {
+    runtime::block_on(self.finish_async())
+  }
+
+  /// `finish` for async code.
+  pub async fn finish_async(self) -> Result<Result<R, E>, Error> {
+    let Upload {
+      sink,
+      result,
+      mut canceller,
+      max_size,
+      ..
+    } = self;
+
+    sink.end().await;
+
+    let res = result.into_inner().unwrap().await;
+
+    // Given up on timeout only
+    if res != Err(Error::Timeout) {
+      canceller.finish();
+    }
+
+    res.and_then(|data| deserialize_bounded(&data, max_size).map(Ok))
+  }
+}
+
+/// Receiving half of a stream, returned to the caller of a streaming method.
+///
+/// It can be consumed as a blocking `Iterator` or as a `futures::Stream`.
+/// It ends after the end-of-stream marker, or right after an error. The
+/// caller's side cancels the call when dropped before the end.
+pub struct StreamReceiver<T> {
+  rx: UnboundedReceiver<Result<Bytes, Error>>,
+  max_size: usize,
+  canceller: Option<Canceller>,
+  grantor: Option<Grantor>,
+  _item: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> StreamReceiver<T> {
+  pub fn new(rx: UnboundedReceiver<Result<Bytes, Error>>, max_size: usize) -> StreamReceiver<T> {
+    StreamReceiver {
+      rx,
+      max_size,
+      canceller: None,
+      grantor: None,
+      _item: PhantomData,
+    }
+  }
+
+  /// Cancels the call with `canceller` if dropped before the end.
+  pub fn cancelled_with(mut self, canceller: Canceller) -> StreamReceiver<T> {
+    self.canceller = Some(canceller);
+
+    self
+  }
+
+  /// Grants credits to the sender with `grantor` as items are consumed.
+  pub fn with_grantor(mut self, grantor: Grantor) -> StreamReceiver<T> {
+    self.grantor = Some(grantor);
+
+    self
+  }
+
+  fn decode(&mut self, res: Option<Result<Bytes, Error>>) -> Option<Result<T, Error>> {
+    if let Some(Ok(_)) = res {
+      if let Some(grantor) = self.grantor.as_ref() {
+        grantor.consumed();
+      }
+    } else {
+      if let Some(canceller) = self.canceller.as_mut() {
+        canceller.finish();
+      }
+
+      if let Some(grantor) = self.grantor.as_mut() {
+        grantor.close(true);
+      }
+    }
+
+    res.map(|res| res.and_then(|data| deserialize_bounded(&data, self.max_size)))
+  }
+}
+
+impl<T: DeserializeOwned> Iterator for StreamReceiver<T> {
+  type Item = Result<T, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let res = block_on(self.rx.next());
+
+    self.decode(res)
+  }
+}
+
+impl<T: DeserializeOwned> Stream for StreamReceiver<T> {
+  type Item = Result<T, Error>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+
+    match this.rx.poll_next_unpin(cx) {
+      Poll::Ready(res) => Poll::Ready(this.decode(res)),
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
@@ -190,6 +190,156 @@ mod multi_service {
   }
 }
 
+mod bound_addr {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use std::net::SocketAddr;
+
+  service! {
+    Foo {
+      fn whoami(&mut self,) -> SocketAddr {
+        self.request_context.as_ref().unwrap().peer
+      }
+    }
+  }
+
+  #[test]
+  fn ephemeral_port_tcp_ipv6() {
+    let mut server = Foo::listen_tcp("[::1]:0");
+
+    let addr = server.network.transport.get_addr();
+
+    assert!(addr.is_ipv6() && addr.ip().is_loopback());
+    assert_ne!(addr.port(), 0);
+
+    let mut client = Foo::connect_tcp(&addr.to_string()).unwrap();
+
+    let local = client.network.transport.get_addr();
+
+    assert_ne!(local.port(), 0);
+    assert_eq!(client.whoami(), Ok(Ok(local)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn ephemeral_port_udp_ipv6() {
+    let mut server = Foo::listen_udp("[::1]:0");
+
+    let addr = server.network.transport.get_addr();
+
+    assert!(addr.is_ipv6() && addr.ip().is_loopback());
+    assert_ne!(addr.port(), 0);
+
+    let mut client = Foo::connect_udp(&addr.to_string()).unwrap();
+
+    let local = client.network.transport.get_addr();
+
+    // Bound to the address the server is reached from
+    assert_eq!(local.ip(), addr.ip());
+    assert_ne!(local.port(), 0);
+    assert_eq!(client.whoami(), Ok(Ok(local)));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod socket_options {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use crate::SocketOptions;
+  #[allow(unused_imports)]
+  use std::net::SocketAddr;
+  #[allow(unused_imports)]
+  use std::time::Duration;
+
+  service! {
+    Foo {
+      fn whoami(&mut self,) -> SocketAddr {
+        self.actual_sender
+      }
+    }
+  }
+
+  #[test]
+  fn tcp() {
+    let options = SocketOptions {
+      nodelay: Some(true),
+      keepalive: Some(Duration::from_secs(60)),
+      send_buffer_size: Some(64 * 1024),
+      recv_buffer_size: Some(64 * 1024),
+      ttl: Some(32),
+      ..Default::default()
+    };
+
+    let mut server = Foo::listen_tcp_with_options("127.0.0.1:0", &options);
+
+    let addr = server.network.transport.get_addr().to_string();
+
+    let bind_addr: SocketAddr = "127.0.0.1:3230".parse().unwrap();
+
+    // The port may still be in TIME_WAIT from a previous run
+    let options = SocketOptions {
+      reuse_address: Some(true),
+      bind_addr: Some(bind_addr),
+      ..options
+    };
+
+    let mut client = Foo::connect_tcp_with_options(&addr, &options).unwrap();
+
+    assert_eq!(client.whoami(), Ok(Ok(bind_addr)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn udp() {
+    let options = SocketOptions {
+      recv_buffer_size: Some(256 * 1024),
+      ttl: Some(32),
+      ..Default::default()
+    };
+
+    let mut server = Foo::listen_udp_with_options("127.0.0.1:0", &options);
+
+    let addr = server.network.transport.get_addr().to_string();
+
+    let bind_addr: SocketAddr = "127.0.0.1:3231".parse().unwrap();
+
+    let options = SocketOptions {
+      bind_addr: Some(bind_addr),
+      ..options
+    };
+
+    let mut client = Foo::connect_udp_with_options(&addr, &options).unwrap();
+
+    assert_eq!(client.whoami(), Ok(Ok(bind_addr)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn reuse_port() {
+    let options = SocketOptions {
+      reuse_port: Some(true),
+      ..Default::default()
+    };
+
+    // Both bind the port, which panics without the option
+    let mut server = Foo::listen_udp_with_options("127.0.0.1:3232", &options);
+    let mut other = Foo::listen_udp_with_options("127.0.0.1:3232", &options);
+
+    other.close();
+    server.close();
+  }
+}
+
 mod context {
   #[allow(unused_imports)]
   use std::net::SocketAddr;
@@ -229,8 +379,8 @@ mod context {
 
   #[test]
   fn test_tcp() {
-    let mut server = Foo::listen_tcp("127.0.0.1:3020");
-    let mut client = Foo::connect_tcp("127.0.0.1:3020").unwrap();
+    let mut server = Foo::listen_tcp("127.0.0.1:3021");
+    let mut client = Foo::connect_tcp("127.0.0.1:3021").unwrap();
 
     assert_eq!(client.inc(1), Ok(Ok(1)));
     assert_eq!(client.inc(2), Ok(Ok(3)));
@@ -258,7 +408,7 @@ mod duplex {
   #[test]
   fn test_duplex() {
     let server = Foo::Duplex::listen("127.0.0.1:3030");
-    let mut client = Foo::Duplex::connect("127.0.0.1:3030");
+    let client = Foo::Duplex::connect("127.0.0.1:3030");
 
     assert_eq!(
       client.hello("test".to_string()),
@@ -270,3 +420,2528 @@ mod duplex {
     Foo::Duplex::close();
   }
 }
+
+mod limits {
+  #[allow(unused_imports)]
+  use super::super::{Error, Limits};
+
+  service! {
+    Foo {
+      fn len(&mut self, data: Vec<u8>) -> usize {
+        data.len()
+      }
+
+      fn make(&mut self, n: usize) -> Vec<u8> {
+        vec![0; n]
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn small_limits() -> Limits {
+    Limits {
+      max_frame_size: 64 * 1024,
+      max_request_size: 1024,
+      max_response_size: 1024,
+      ..Limits::default()
+    }
+  }
+
+  #[test]
+  fn request_too_large_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3040");
+    let mut client = Foo::connect_tcp("127.0.0.1:3040").unwrap();
+
+    server.set_limits(small_limits());
+
+    assert_eq!(client.len(vec![1; 10]), Ok(Ok(10)));
+    assert_eq!(client.len(vec![1; 2048]), Err(Error::MessageTooLarge));
+    assert_eq!(client.len(vec![1; 20]), Ok(Ok(20)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn response_too_large_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3041");
+    let mut client = Foo::connect_udp("127.0.0.1:3041").unwrap();
+
+    server.set_limits(small_limits());
+
+    assert_eq!(client.make(10), Ok(Ok(vec![0; 10])));
+    assert_eq!(client.make(2048), Err(Error::MessageTooLarge));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn frame_too_large_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3042");
+    let mut client = Foo::connect_tcp("127.0.0.1:3042").unwrap();
+
+    server.set_limits(small_limits());
+
+    assert_eq!(client.len(vec![1; 128 * 1024]), Err(Error::Timeout));
+    assert_eq!(client.len(vec![1; 20]), Ok(Ok(20)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn request_too_short_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3043");
+    let mut client = Foo::connect_tcp("127.0.0.1:3043").unwrap();
+
+    // Shorter than the id of the method called
+    let res = client.network.send(&client.serv_addr, vec![1, 2, 3].into());
+
+    assert!(matches!(res, Err(Error::Deserialize(_))));
+    assert_eq!(client.len(vec![1; 20]), Ok(Ok(20)));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod proto {
+  #[allow(unused_imports)]
+  use super::super::{to_socket_addr, Bytes, Packet};
+
+  #[test]
+  fn decode_does_not_copy_payload() {
+    let mut pack = Packet::new(Bytes::from(vec![42; 4096]), to_socket_addr("127.0.0.1:3050"), 0);
+
+    pack.header.id = 7;
+
+    let buff = pack.encode();
+
+    assert_eq!(buff, Bytes::from(super::super::serialize(&pack).unwrap()));
+
+    let decoded = Packet::decode(buff.clone(), 1024 * 1024).unwrap();
+
+    let start = buff.as_ptr() as usize;
+    let ptr = decoded.data.as_ptr() as usize;
+
+    assert_eq!(decoded.data, pack.data);
+    assert_eq!(decoded.header.id, 7);
+    assert!(ptr >= start && ptr + decoded.data.len() <= start + buff.len());
+  }
+}
+
+mod oneway {
+  #[allow(unused_imports)]
+  use std::sync::{Arc, Mutex};
+
+  service! {
+    Foo {
+      let lines: Arc<Mutex<Vec<String>>>;
+
+      oneway fn log(&mut self, line: String) {
+        self.lines.lock().unwrap().push(line);
+      }
+
+      fn count(&mut self,) -> usize {
+        self.lines.lock().unwrap().len()
+      }
+    }
+  }
+
+  #[test]
+  fn test_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3060");
+    let mut client = Foo::connect_tcp("127.0.0.1:3060").unwrap();
+
+    assert_eq!(client.log("first".to_string()), Ok(()));
+    assert_eq!(client.log("second".to_string()), Ok(()));
+    assert_eq!(client.count(), Ok(Ok(2)));
+
+    assert_eq!(
+      *server.context.lock().unwrap().lines.lock().unwrap(),
+      vec!["first".to_string(), "second".to_string()]
+    );
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn test_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3061");
+    let mut client = Foo::connect_udp("127.0.0.1:3061").unwrap();
+
+    assert_eq!(client.log("first".to_string()), Ok(()));
+    assert_eq!(client.count(), Ok(Ok(1)));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod batch {
+  #[allow(unused_imports)]
+  use super::super::{serialize_with_u64_head, Error};
+
+  service! {
+    Foo {
+      let notes: std::sync::Arc<std::sync::Mutex<Vec<u8>>>;
+
+      fn hello(&mut self, name: String) -> String {
+        format!("hello {}", name)
+      }
+
+      fn eq(&mut self, s1: u8, s2: u8) -> bool {
+        s1 == s2
+      }
+
+      oneway fn note(&mut self, n: u8) {
+        self.notes.lock().unwrap().push(n);
+      }
+
+      fn notes(&mut self,) -> Vec<u8> {
+        self.notes.lock().unwrap().clone()
+      }
+
+      fn count(&mut self, n: u8) -> stream u8 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3070");
+    let mut client = Foo::connect_tcp("127.0.0.1:3070").unwrap();
+
+    let (((), hello), eq) = client
+      .batch()
+      .hello("test".to_string())
+      .eq(42, 42)
+      .send()
+      .unwrap();
+
+    assert_eq!(hello, Ok(Ok("hello test".to_string())));
+    assert_eq!(eq, Ok(Ok(true)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn per_call_failure_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3071");
+    let mut client = Foo::connect_udp("127.0.0.1:3071").unwrap();
+
+    let addr = client.serv_addr;
+
+    let results = client
+      .network
+      .send_batch(
+        &addr,
+        vec![
+          serialize_with_u64_head(crate::hash_ident!(eq) as u64, &(1u8, 2u8)),
+          serialize_with_u64_head(0, &()),
+        ],
+      )
+      .unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert!(!super::super::deserialize::<bool>(results[0].as_ref().unwrap()).unwrap());
+    assert_eq!(results[1], Err(Error::UnknownMethod));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn only_plain_methods_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3072");
+    let mut client = Foo::connect_tcp("127.0.0.1:3072").unwrap();
+
+    let addr = client.serv_addr;
+
+    for call in [
+      serialize_with_u64_head(crate::hash_ident!(note) as u64, &(1u8,)),
+      serialize_with_u64_head(crate::hash_ident!(count) as u64, &(3u8,)),
+    ] {
+      let batch = vec![serialize_with_u64_head(crate::hash_ident!(eq) as u64, &(1u8, 1u8)), call];
+
+      assert_eq!(client.network.send_batch(&addr, batch), Err(Error::UnknownMethod));
+    }
+
+    // Nothing was run
+    assert_eq!(client.notes(), Ok(Ok(vec![])));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod shared_client {
+  #[allow(unused_imports)]
+  use std::sync::Arc;
+  #[allow(unused_imports)]
+  use std::thread;
+
+  service! {
+    Foo {
+      fn hello(&mut self, name: String) -> String {
+        format!("hello {}", name)
+      }
+
+      fn slow(&mut self, ms: u64) -> bool {
+        std::thread::sleep(std::time::Duration::from_millis(ms));
+
+        true
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn assert_send_sync<T: Send + Sync>() {}
+
+  #[test]
+  fn concurrent_calls_tcp() {
+    assert_send_sync::<Foo::Client<Foo::TcpTransport>>();
+
+    let mut server = Foo::listen_tcp("127.0.0.1:3080");
+    let client = Arc::new(Foo::connect_tcp("127.0.0.1:3080").unwrap());
+
+    let handles: Vec<_> = (0..8)
+      .map(|i| {
+        let client = client.clone();
+
+        thread::spawn(move || {
+          for j in 0..10 {
+            let name = format!("{}-{}", i, j);
+
+            assert_eq!(client.hello(name.clone()), Ok(Ok(format!("hello {}", name))));
+          }
+        })
+      })
+      .collect();
+
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    Arc::try_unwrap(client).ok().unwrap().close();
+    server.close();
+  }
+
+  #[test]
+  fn closed_while_waiting_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3081");
+    let mut client = Foo::connect_tcp("127.0.0.1:3081").unwrap();
+
+    let waiting = {
+      let client = client.clone();
+
+      thread::spawn(move || client.slow(500))
+    };
+
+    thread::sleep(std::time::Duration::from_millis(50));
+
+    // The call waiting on the closed client fails rather than panics
+    client.close();
+
+    assert_eq!(waiting.join().unwrap(), Err(crate::Error::SendFailed));
+
+    server.close();
+  }
+}
+
+mod async_calls {
+  #[allow(unused_imports)]
+  use crate::runtime;
+  #[allow(unused_imports)]
+  use futures::stream::StreamExt;
+
+  service! {
+    Foo {
+      fn double(&mut self, n: u64) -> u64 {
+        n * 2
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+
+      fn sum(&mut self, items: stream u64) -> u64 {
+        items.map(|item| item.unwrap()).sum()
+      }
+    }
+  }
+
+  #[test]
+  fn concurrent_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3186");
+    let mut client = Foo::connect_tcp("127.0.0.1:3186").unwrap();
+
+    let (a, b) = runtime::block_on(async { futures::join!(client.async_calls().double(1), client.async_calls().double(2)) });
+
+    assert_eq!(a, Ok(Ok(2)));
+    assert_eq!(b, Ok(Ok(4)));
+
+    let mut quotes = runtime::block_on(client.async_calls().subscribe::<u64>("quotes")).unwrap();
+
+    assert_eq!(server.publish("quotes", &7u64), Ok(1));
+    assert_eq!(runtime::block_on(StreamExt::next(&mut quotes)), Some(Ok(7)));
+
+    // Calls made from a task of the runtime do not hold up its workers
+    let task = {
+      let client = client.clone();
+
+      runtime::spawn(async move {
+        let calls = client.async_calls();
+
+        let doubled = calls.double(21).await;
+        let items: Vec<_> = StreamExt::collect(calls.count(100).await.unwrap()).await;
+
+        let upload = calls.sum().await.unwrap();
+
+        for i in 0..100 {
+          upload.send_async(i).await.unwrap();
+        }
+
+        let sum = upload.finish_async().await;
+
+        let batch = client.batch().double(1).double(2).send_async().await;
+
+        (doubled, items, sum, batch)
+      })
+    };
+
+    let (doubled, items, sum, batch) = runtime::block_on(task).unwrap();
+
+    assert_eq!(doubled, Ok(Ok(42)));
+    assert_eq!(items, (0..100).map(Ok).collect::<Vec<_>>());
+    assert_eq!(sum, Ok(Ok(4950)));
+    assert_eq!(batch, Ok((((), Ok(Ok(2))), Ok(Ok(4)))));
+
+    // The blocking calls still work alongside
+    assert_eq!(client.double(5), Ok(Ok(10)));
+
+    drop(quotes);
+    client.close();
+    server.close();
+  }
+}
+
+mod stream {
+  #[allow(unused_imports)]
+  use crate::Error;
+
+  service! {
+    Foo {
+      fn count(&mut self, from: u64, to: u64) -> stream u64 |sink| {
+        for i in from..to {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+
+      fn broken(&mut self, first: u64) -> stream u64 |sink| {
+        sink.send(first).unwrap();
+
+        sink.fail(crate::Error::SendFailed);
+      }
+    }
+  }
+
+  #[test]
+  fn test_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3090");
+    let mut client = Foo::connect_tcp("127.0.0.1:3090").unwrap();
+
+    let items: Vec<_> = client.count(0, 100).unwrap().collect();
+
+    assert_eq!(items, (0..100).map(Ok).collect::<Vec<_>>());
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn ends_on_error_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3091");
+    let mut client = Foo::connect_tcp("127.0.0.1:3091").unwrap();
+
+    let items: Vec<_> = client.broken(1).unwrap().collect();
+
+    assert_eq!(items, vec![Ok(1), Err(Error::SendFailed)]);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn as_futures_stream_udp() {
+    use futures::stream::StreamExt;
+
+    let mut server = Foo::listen_udp("127.0.0.1:3092");
+    let mut client = Foo::connect_udp("127.0.0.1:3092").unwrap();
+
+    let stream = client.count(5, 8).unwrap();
+
+    let items: Vec<_> = futures::executor::block_on(StreamExt::collect(stream));
+
+    assert_eq!(items, vec![Ok(5), Ok(6), Ok(7)]);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn busy_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3093");
+    let mut client = Foo::connect_tcp("127.0.0.1:3093").unwrap();
+
+    server.set_limits(crate::Limits {
+      max_stream_handlers: 1,
+      ..crate::Limits::default()
+    });
+
+    // Held by its handler, waiting for credits
+    let mut first = client.count(0, u64::MAX).unwrap();
+
+    assert_eq!(first.next(), Some(Ok(0)));
+
+    let items: Vec<_> = client.count(0, 3).unwrap().collect();
+
+    assert_eq!(items, vec![Err(Error::Busy)]);
+
+    drop(first);
+
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    let items: Vec<_> = client.count(0, 3).unwrap().collect();
+
+    assert_eq!(items, vec![Ok(0), Ok(1), Ok(2)]);
+
+    client.close();
+    server.close();
+  }
+}
+
+mod upload {
+  service! {
+    Foo {
+      fn sum(&mut self, items: stream u64, offset: u64) -> u64 {
+        offset + items.map(|item| item.unwrap()).sum::<u64>()
+      }
+
+      fn count(&mut self, items: stream Vec<u8>) -> Result<usize, String> | String {
+        let mut total = 0;
+
+        for item in items {
+          total += item.map_err(|err| err.to_string())?.len();
+        }
+
+        Ok(total)
+      }
+    }
+  }
+
+  #[test]
+  fn test_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3100");
+    let mut client = Foo::connect_tcp("127.0.0.1:3100").unwrap();
+
+    let upload = client.sum(1000).unwrap();
+
+    for i in 0..100 {
+      upload.send(i).unwrap();
+    }
+
+    assert_eq!(upload.finish(), Ok(Ok(1000 + 4950)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn larger_than_a_datagram_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3101");
+    let mut client = Foo::connect_udp("127.0.0.1:3101").unwrap();
+
+    let upload = client.count().unwrap();
+
+    for _ in 0..10 {
+      upload.send(vec![0; 50 * 1024]).unwrap();
+    }
+
+    assert_eq!(upload.finish(), Ok(Ok(Ok(500 * 1024))));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod channel {
+  service! {
+    Foo {
+      fn shout(&mut self, lines: stream String, suffix: String) -> stream String |sink| {
+        for line in lines {
+          let line = line.unwrap();
+
+          if line == "bye" {
+            return;
+          }
+
+          sink.send(line.to_uppercase() + &suffix).unwrap();
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3110");
+    let mut client = Foo::connect_tcp("127.0.0.1:3110").unwrap();
+
+    let (tx, mut rx) = client.shout("!".to_string()).unwrap();
+
+    for line in &["hello", "world"] {
+      tx.send(line.to_string()).unwrap();
+
+      assert_eq!(rx.next(), Some(Ok(line.to_uppercase() + "!")));
+    }
+
+    // The client ends its side, so does the server
+    drop(tx);
+
+    assert_eq!(rx.next(), None);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn closed_by_server_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3111");
+    let mut client = Foo::connect_udp("127.0.0.1:3111").unwrap();
+
+    let (tx, rx) = client.shout("?".to_string()).unwrap();
+
+    tx.send("a".to_string()).unwrap();
+    tx.send("bye".to_string()).unwrap();
+
+    assert_eq!(rx.collect::<Vec<_>>(), vec![Ok("A?".to_string())]);
+
+    client.close();
+    server.close();
+  }
+}
+
+mod fragment {
+  #[allow(unused_imports)]
+  use crate::transport::{fragment, Reassembler};
+  #[allow(unused_imports)]
+  use std::time::{Duration, Instant};
+
+  service! {
+    Foo {
+      fn echo(&mut self, data: Vec<u8>) -> Vec<u8> {
+        data
+      }
+    }
+  }
+
+  #[test]
+  fn larger_than_a_datagram_udp() {
+    let mut server = Foo::listen_udp("127.0.0.1:3120");
+    let mut client = Foo::connect_udp("127.0.0.1:3120").unwrap();
+
+    let data: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+
+    assert_eq!(client.echo(data.clone()), Ok(Ok(data)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn reassembles_out_of_order() {
+    let from = "127.0.0.1:1".parse().unwrap();
+    let mut reassembler = Reassembler::new(1024, Duration::from_secs(1));
+
+    let mut datagrams = fragment(1, &[1, 2, 3, 4, 5], 2);
+
+    datagrams.reverse();
+
+    assert_eq!(reassembler.push(from, datagrams[0].clone()), None);
+    assert_eq!(reassembler.push(from, datagrams[1].clone()), None);
+    assert_eq!(reassembler.push(from, datagrams[2].clone()), Some(vec![1, 2, 3, 4, 5].into()));
+    assert_eq!(reassembler.pending(), 0);
+  }
+
+  #[test]
+  fn drops_incomplete_and_oversized() {
+    let from = "127.0.0.1:1".parse().unwrap();
+    let mut reassembler = Reassembler::new(4, Duration::from_secs(1));
+
+    let datagrams = fragment(1, &[1, 2, 3, 4], 2);
+
+    assert_eq!(reassembler.push(from, datagrams[0].clone()), None);
+
+    reassembler.expire(Instant::now() + Duration::from_secs(2));
+
+    assert_eq!(reassembler.pending(), 0);
+
+    let datagrams = fragment(2, &[1, 2, 3, 4, 5, 6], 2);
+
+    for datagram in datagrams {
+      assert_eq!(reassembler.push(from, datagram), None);
+    }
+
+    assert_eq!(reassembler.pending(), 0);
+  }
+
+  #[test]
+  fn bounds_pending_messages() {
+    let peer1 = "127.0.0.1:1".parse().unwrap();
+    let peer2 = "127.0.0.1:2".parse().unwrap();
+    let mut reassembler = Reassembler::new(1024, Duration::from_secs(1));
+
+    // Room for two fragments of 100 bytes and their headers per peer, three
+    // in all
+    reassembler.set_max_pending(300, 400);
+
+    let messages: Vec<_> = (0..4).map(|id| fragment(id, &[id as u8; 200], 100)).collect();
+
+    for message in &messages[..3] {
+      assert_eq!(reassembler.push(peer1, message[0].clone()), None);
+    }
+
+    // The oldest one was dropped for the others
+    assert_eq!(reassembler.pending(), 2);
+    assert_eq!(reassembler.push(peer1, messages[0][1].clone()), None);
+    assert_eq!(reassembler.push(peer1, messages[2][1].clone()), Some(vec![2; 200].into()));
+
+    // Messages 1 and 0 are left, the latter started over, then a second
+    // peer goes past the limit of all of them
+    assert_eq!(reassembler.push(peer2, messages[3][0].clone()), None);
+    assert_eq!(reassembler.push(peer2, messages[2][0].clone()), None);
+
+    assert_eq!(reassembler.pending(), 3);
+
+    // Message 1, the oldest, was dropped
+    assert_eq!(reassembler.push(peer1, messages[1][1].clone()), None);
+  }
+}
+
+mod reliable {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use super::super::network::Network;
+  #[allow(unused_imports)]
+  use super::super::server_callback::ServerCallback;
+  #[allow(unused_imports)]
+  use super::super::to_socket_addr;
+  #[allow(unused_imports)]
+  use std::net::{SocketAddr, UdpSocket};
+  #[allow(unused_imports)]
+  use std::sync::atomic::{AtomicBool, Ordering};
+  #[allow(unused_imports)]
+  use std::sync::{Arc, Mutex};
+  #[allow(unused_imports)]
+  use std::thread;
+  #[allow(unused_imports)]
+  use std::time::Duration;
+  #[allow(unused_imports)]
+  use crate::transport::ReliabilityConfig;
+
+  service! {
+    Foo {
+      let seen: Arc<Mutex<Vec<u64>>>;
+
+      fn echo(&mut self, data: Vec<u8>) -> Vec<u8> {
+        data
+      }
+
+      oneway fn push(&mut self, i: u64) {
+        self.seen.lock().unwrap().push(i);
+      }
+
+      fn seen(&mut self,) -> Vec<u64> {
+        self.seen.lock().unwrap().clone()
+      }
+    }
+  }
+
+  /// Scatters `n` over 0..32, the same way on every run.
+  #[allow(dead_code)]
+  fn scatter(n: usize) -> u64 {
+    (n as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 59
+  }
+
+  /// Forwards datagrams between one client and `target`, sending each one
+  /// `copies(n)` times, where `n` counts the datagrams seen so far.
+  #[allow(dead_code)]
+  fn lossy_proxy(addr: &str, target: &str, copies: fn(usize) -> usize) -> Arc<AtomicBool> {
+    let socket = UdpSocket::bind(addr).unwrap();
+    let target = to_socket_addr(target);
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_c = stop.clone();
+
+    socket.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+
+    thread::spawn(move || {
+      let mut buff = [0; 64 * 1024];
+      let mut client: Option<SocketAddr> = None;
+      let mut n = 0;
+
+      while !stop_c.load(Ordering::Relaxed) {
+        let (amount, from) = match socket.recv_from(&mut buff) {
+          Ok(res) => res,
+          Err(_) => continue,
+        };
+
+        let to = if from == target {
+          client.unwrap()
+        } else {
+          client = Some(from);
+
+          target
+        };
+
+        for _ in 0..copies(n) {
+          socket.send_to(&buff[..amount], to).unwrap();
+        }
+
+        n += 1;
+      }
+    });
+
+    stop
+  }
+
+  #[allow(dead_code)]
+  fn fast_retransmit() -> ReliabilityConfig {
+    ReliabilityConfig {
+      initial_rto: Duration::from_millis(10),
+      ..ReliabilityConfig::default()
+    }
+  }
+
+  #[allow(dead_code)]
+  fn reliable_pair(server_addr: &str, proxy_addr: &str) -> (Foo::Server<Foo::UdpTransport>, Foo::Client<Foo::UdpTransport>) {
+    let mut transport = Foo::UdpTransport::new(&to_socket_addr(server_addr));
+
+    transport.set_reliable(true);
+    transport.set_reliability_config(fast_retransmit());
+
+    let mut net1 = Network::new(transport, ServerCallback::new_empty());
+
+    net1.listen();
+
+    let mut transport = Foo::UdpTransport::new(&to_socket_addr(proxy_addr));
+
+    transport.set_reliable(true);
+    transport.set_reliability_config(fast_retransmit());
+
+    let mut net2 = Network::new(transport, ServerCallback::new_empty());
+
+    net2.connect().unwrap();
+
+    (Foo::listen_with_network(net1), Foo::connect_with_network(net2))
+  }
+
+  #[test]
+  fn survives_loss_udp() {
+    let stop = lossy_proxy("127.0.0.1:3131", "127.0.0.1:3130", |n| if scatter(n) < 6 { 0 } else { 1 });
+
+    let (mut server, mut client) = reliable_pair("127.0.0.1:3130", "127.0.0.1:3131");
+
+    for i in 0..20 {
+      let data = vec![i as u8; 10 * 1024];
+
+      assert_eq!(client.echo(data.clone()), Ok(Ok(data)));
+    }
+
+    client.close();
+    server.close();
+    stop.store(true, Ordering::Relaxed);
+  }
+
+  #[test]
+  fn delivers_once_and_in_order_udp() {
+    let stop = lossy_proxy("127.0.0.1:3133", "127.0.0.1:3132", |n| match scatter(n) {
+      0..=5 => 0,
+      6..=11 => 2,
+      _ => 1,
+    });
+
+    let (mut server, mut client) = reliable_pair("127.0.0.1:3132", "127.0.0.1:3133");
+
+    for i in 0..20 {
+      client.push(i).unwrap();
+    }
+
+    assert_eq!(client.seen(), Ok(Ok((0..20).collect())));
+
+    client.close();
+    server.close();
+    stop.store(true, Ordering::Relaxed);
+  }
+
+  #[test]
+  fn full_buffer_is_not_acknowledged() {
+    use crate::transport::Reliability;
+    use std::time::Instant;
+
+    let sender_addr = to_socket_addr("127.0.0.1:1");
+    let receiver_addr = to_socket_addr("127.0.0.1:2");
+    let now = Instant::now();
+
+    let mut sender = Reliability::new(fast_retransmit());
+    let mut receiver = Reliability::new(fast_retransmit());
+
+    // The first datagram is lost, so the others wait for it, up to 4096
+    let first = sender.wrap(receiver_addr, &[0], now);
+
+    for i in 1..=4097u64 {
+      let datagram = sender.wrap(receiver_addr, &i.to_le_bytes(), now);
+      let (ack, delivered) = receiver.receive(sender_addr, datagram, now);
+
+      assert!(delivered.is_empty());
+      assert_eq!(ack.is_some(), i <= 4096, "{}", i);
+
+      if let Some(ack) = ack {
+        sender.receive(receiver_addr, ack, now);
+      }
+    }
+
+    // The one refused is still retransmitted
+    assert_eq!(sender.unacked(), 2);
+
+    let (_, delivered) = receiver.receive(sender_addr, first, now);
+
+    assert_eq!(delivered.len(), 4097);
+
+    let (resend, _) = sender.poll(now + Duration::from_secs(1));
+    let resend: Vec<_> = resend.into_iter().map(|(_, datagram)| receiver.receive(sender_addr, datagram, now)).collect();
+
+    assert!(resend.iter().any(|(_, delivered)| delivered == &vec![bytes::Bytes::copy_from_slice(&4097u64.to_le_bytes())]));
+  }
+}
+
+mod transfer {
+  #[allow(unused_imports)]
+  use crate::transfer::{self, Chunk, FileInfo, Transfer, TransferError};
+  #[allow(unused_imports)]
+  use std::fs;
+  #[allow(unused_imports)]
+  use std::path::PathBuf;
+
+  /// A fresh directory holding a `source` file of `size` bytes.
+  #[allow(dead_code)]
+  fn setup(name: &str, size: usize) -> (PathBuf, Vec<u8>) {
+    let dir = std::env::temp_dir().join(format!("rsrpc-transfer-{}-{}", name, std::process::id()));
+    let data: Vec<u8> = (0..size).map(|i| (i * 7 % 251) as u8).collect();
+
+    let _ = fs::remove_dir_all(&dir);
+
+    fs::create_dir_all(dir.join("server")).unwrap();
+    fs::write(dir.join("source"), &data).unwrap();
+
+    (dir, data)
+  }
+
+  #[test]
+  fn upload_then_download_tcp() {
+    let (dir, data) = setup("roundtrip", 300 * 1024 + 17);
+
+    let mut server = transfer::listen::<Transfer::TcpTransport>("127.0.0.1:3140", &dir.join("server"));
+    let mut client = Transfer::connect_tcp("127.0.0.1:3140").unwrap();
+
+    transfer::upload(&client, &dir.join("source"), "blob", 64 * 1024).unwrap();
+
+    assert_eq!(fs::read(dir.join("server/blob")).unwrap(), data);
+
+    transfer::download(&client, "blob", &dir.join("copy"), 64 * 1024).unwrap();
+
+    assert_eq!(fs::read(dir.join("copy")).unwrap(), data);
+
+    client.close();
+    server.close();
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn resumes_upload_tcp() {
+    let (dir, data) = setup("resume", 200 * 1024);
+
+    let mut server = transfer::listen::<Transfer::TcpTransport>("127.0.0.1:3141", &dir.join("server"));
+
+    let info = FileInfo {
+      size: data.len() as u64,
+      sha256: transfer::sha256(&data),
+    };
+
+    // A first client sends two chunks and goes away
+    let mut client = Transfer::connect_tcp("127.0.0.1:3141").unwrap();
+
+    assert_eq!(client.begin_upload("blob".to_string(), info.clone()), Ok(Ok(Ok(0))));
+
+    for offset in &[0, 1000] {
+      let data = data[*offset..*offset + 1000].to_vec();
+      let chunk = Chunk {
+        offset: *offset as u64,
+        sha256: transfer::sha256(&data),
+        data,
+      };
+
+      client.put_chunk("blob".to_string(), chunk).unwrap().unwrap().unwrap();
+    }
+
+    client.close();
+
+    let mut client = Transfer::connect_tcp("127.0.0.1:3141").unwrap();
+
+    assert_eq!(client.begin_upload("blob".to_string(), info), Ok(Ok(Ok(2000))));
+
+    transfer::upload(&client, &dir.join("source"), "blob", 64 * 1024).unwrap();
+
+    assert_eq!(fs::read(dir.join("server/blob")).unwrap(), data);
+
+    client.close();
+    server.close();
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn rejects_corruption_and_paths_tcp() {
+    let (dir, data) = setup("reject", 10);
+
+    let mut server = transfer::listen::<Transfer::TcpTransport>("127.0.0.1:3142", &dir.join("server"));
+    let mut client = Transfer::connect_tcp("127.0.0.1:3142").unwrap();
+
+    let info = FileInfo {
+      size: data.len() as u64,
+      sha256: transfer::sha256(&data),
+    };
+
+    client.begin_upload("blob".to_string(), info).unwrap().unwrap().unwrap();
+
+    let chunk = Chunk {
+      offset: 0,
+      data: data.clone(),
+      sha256: transfer::sha256(b"something else"),
+    };
+
+    assert_eq!(client.put_chunk("blob".to_string(), chunk), Ok(Ok(Err(TransferError::ChecksumMismatch))));
+    assert_eq!(client.stat("../source".to_string()), Ok(Ok(Err(TransferError::InvalidName))));
+
+    client.close();
+    server.close();
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn serves_nothing_without_root_tcp() {
+    let mut server = Transfer::listen_tcp("127.0.0.1:3143");
+    let mut client = Transfer::connect_tcp("127.0.0.1:3143").unwrap();
+
+    assert_eq!(client.stat("Cargo.toml".to_string()), Ok(Ok(Err(TransferError::NotServed))));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod callback {
+  service! {
+    Bar {
+      let title: String = String::new();
+
+      fn title(&mut self, name: String) -> String {
+        format!("{} {}", self.title, name)
+      }
+    }
+
+    Foo {
+      fn greet(&mut self, name: String) -> Result<String, crate::Error> {
+        let req = self.request_context.as_ref().unwrap();
+
+        let bar = super::Bar::Client::<super::Bar::TcpTransport>::for_peer(req).ok_or(crate::Error::SendFailed)?;
+
+        let title = bar.title(name)?.unwrap();
+
+        Ok(format!("Hello, {}", title))
+      }
+    }
+  }
+
+  #[test]
+  fn calls_back_into_client_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3150");
+    let mut client = Foo::connect_tcp("127.0.0.1:3150").unwrap();
+
+    // Served over the connection to the server, without listening
+    let bar = Bar::listen_with_network(client.network.clone());
+
+    bar.context.lock().unwrap().title = "Dr.".to_string();
+
+    assert_eq!(client.greet("Who".to_string()), Ok(Ok(Ok("Hello, Dr. Who".to_string()))));
+    assert_eq!(client.greet("No".to_string()), Ok(Ok(Ok("Hello, Dr. No".to_string()))));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod pubsub {
+  #[allow(unused_imports)]
+  use crate::proto::{Packet, PacketKind};
+  #[allow(unused_imports)]
+  use crate::pubsub::Topics;
+  #[allow(unused_imports)]
+  use crate::{Error, PubSubConfig, SlowConsumerPolicy};
+  #[allow(unused_imports)]
+  use std::sync::mpsc::{channel, Receiver};
+  #[allow(unused_imports)]
+  use std::sync::{Arc, Mutex};
+  #[allow(unused_imports)]
+  use std::thread;
+  #[allow(unused_imports)]
+  use std::time::Duration;
+
+  #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+  pub struct Quote {
+    pub symbol: String,
+    pub price: u64,
+  }
+
+  service! {
+    Foo {
+      fn ping(&mut self,) -> bool {
+        true
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn quote(price: u64) -> Quote {
+    Quote {
+      symbol: "RUST".to_string(),
+      price,
+    }
+  }
+
+  #[test]
+  fn fans_out_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3160");
+    let mut client1 = Foo::connect_tcp("127.0.0.1:3160").unwrap();
+    let mut client2 = Foo::connect_tcp("127.0.0.1:3160").unwrap();
+
+    let mut quotes1 = client1.subscribe::<Quote>("quotes").unwrap();
+    let mut quotes2 = client2.subscribe::<Quote>("quotes").unwrap();
+    let mut other = client2.subscribe::<String>("other").unwrap();
+
+    assert_eq!(server.subscribers("quotes"), 2);
+
+    assert_eq!(server.publish("quotes", &quote(1)), Ok(2));
+    assert_eq!(server.publish("quotes", &quote(2)), Ok(2));
+    assert_eq!(server.publish("other", &"hello".to_string()), Ok(1));
+    assert_eq!(server.publish("nobody", &0u8), Ok(0));
+
+    assert_eq!(quotes1.next(), Some(Ok(quote(1))));
+    assert_eq!(quotes1.next(), Some(Ok(quote(2))));
+    assert_eq!(quotes2.next(), Some(Ok(quote(1))));
+    assert_eq!(quotes2.next(), Some(Ok(quote(2))));
+    assert_eq!(other.next(), Some(Ok("hello".to_string())));
+
+    // Calls still work alongside
+    assert_eq!(client1.ping(), Ok(Ok(true)));
+
+    drop(quotes1);
+
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(server.subscribers("quotes"), 1);
+
+    client1.close();
+    client2.close();
+    server.close();
+  }
+
+  #[test]
+  fn unsubscribes_disconnected_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3161");
+    let mut client1 = Foo::connect_tcp("127.0.0.1:3161").unwrap();
+    let mut client2 = Foo::connect_tcp("127.0.0.1:3161").unwrap();
+
+    // Kept, but its connection goes away
+    let _quotes1 = client1.subscribe::<Quote>("quotes").unwrap();
+    let mut quotes2 = client2.subscribe::<Quote>("quotes").unwrap();
+
+    client1.close();
+
+    thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(server.publish("quotes", &quote(1)), Ok(1));
+    assert_eq!(server.subscribers("quotes"), 1);
+
+    assert_eq!(quotes2.next(), Some(Ok(quote(1))));
+
+    client2.close();
+    server.close();
+  }
+
+  // A subscriber blocked in its first send, until `release` is called
+  #[allow(dead_code)]
+  fn stalled(topics: &mut Topics) -> (Receiver<Packet>, Box<dyn Fn()>) {
+    let (entered_tx, entered_rx) = channel();
+    let (sent_tx, sent_rx) = channel();
+
+    let entered_tx = Mutex::new(entered_tx);
+    let sent_tx = Mutex::new(sent_tx);
+    let gate = Arc::new(tokio::sync::Semaphore::new(0));
+
+    let addr = "127.0.0.1:1".parse().unwrap();
+
+    let send_gate = gate.clone();
+
+    topics.subscribe(
+      "quotes".to_string(),
+      addr,
+      7,
+      Packet::new(Default::default(), addr, 7),
+      Arc::new(move |_, pack| {
+        let _ = entered_tx.lock().unwrap().send(());
+
+        let gate = send_gate.clone();
+        let sent_tx = sent_tx.lock().unwrap().clone();
+
+        Box::pin(async move {
+          gate.acquire().await.unwrap().forget();
+
+          sent_tx.send(pack).is_ok()
+        })
+      }),
+    );
+
+    entered_rx.recv().unwrap();
+
+    (sent_rx, Box::new(move || gate.add_permits(16)))
+  }
+
+  #[allow(dead_code)]
+  fn publish(topics: &mut Topics, price: u64) -> usize {
+    let addr = "127.0.0.1:2".parse().unwrap();
+
+    topics.publish(
+      "quotes",
+      |id| Packet::with_kind(PacketKind::StreamItem, crate::serialize(&quote(price)).unwrap().into(), addr, id),
+      |_| true,
+    )
+  }
+
+  #[allow(dead_code)]
+  fn prices(sent: &Receiver<Packet>) -> Vec<Result<u64, Error>> {
+    let mut prices = vec![];
+
+    while let Ok(pack) = sent.recv_timeout(Duration::from_millis(100)) {
+      match pack.header.kind {
+        PacketKind::StreamItem => prices.push(Ok(crate::deserialize::<Quote>(&pack.data).unwrap().price)),
+        PacketKind::Error(err) => prices.push(Err(err)),
+        _ => (),
+      }
+    }
+
+    prices
+  }
+
+  #[test]
+  fn slow_consumer_policies() {
+    let policies = vec![
+      (SlowConsumerPolicy::DropOldest, vec![1, 1, 1, 1, 1], vec![Ok(4), Ok(5)]),
+      (SlowConsumerPolicy::DropNewest, vec![1, 1, 1, 1, 1], vec![Ok(1), Ok(2)]),
+      (
+        SlowConsumerPolicy::Unsubscribe,
+        vec![1, 1, 0, 0, 0],
+        vec![Err(Error::SlowConsumer)],
+      ),
+    ];
+
+    for (policy, counts, expected) in policies {
+      let mut topics = Topics::new();
+
+      topics.set_config(PubSubConfig {
+        queue_size: 2,
+        slow_consumer: policy,
+      });
+
+      let (sent, release) = stalled(&mut topics);
+
+      let published: Vec<usize> = (1..=5).map(|price| publish(&mut topics, price)).collect();
+
+      assert_eq!(published, counts, "{:?}", policy);
+
+      release();
+
+      assert_eq!(prices(&sent), expected, "{:?}", policy);
+    }
+  }
+}
+
+mod cancel {
+  #[allow(unused_imports)]
+  use crate::Error;
+  #[allow(unused_imports)]
+  use std::thread;
+  #[allow(unused_imports)]
+  use std::time::Duration;
+
+  service! {
+    Foo {
+      let log: std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+      fn slow(&mut self, ms: u64) -> bool {
+        let req = self.request_context.clone().unwrap();
+
+        for _ in 0..ms / 5 {
+          if req.is_cancelled() {
+            self.log.lock().unwrap().push("cancelled".to_string());
+
+            return false;
+          }
+
+          std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        self.log.lock().unwrap().push("finished".to_string());
+
+        true
+      }
+
+      fn quick(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn ticks(&mut self,) -> stream u64 |sink| {
+        for i in 0.. {
+          if let Err(err) = sink.send(i) {
+            self.log.lock().unwrap().push(format!("stream: {}", err));
+
+            return;
+          }
+
+          std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+      }
+
+      fn sum(&mut self, items: stream u64) -> u64 {
+        let mut total = 0;
+
+        for item in items {
+          match item {
+            Ok(item) => total += item,
+            Err(err) => {
+              self.log.lock().unwrap().push(format!("upload: {}", err));
+
+              break;
+            }
+          }
+        }
+
+        total
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn log<T: 'static + crate::Transport>(server: &Foo::Server<T>) -> Vec<String> {
+    thread::sleep(Duration::from_millis(100));
+
+    let log = server.context.lock().unwrap().log.clone();
+
+    let log = log.lock().unwrap().drain(..).collect();
+
+    log
+  }
+
+  #[test]
+  fn calls_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3170");
+    let mut client = Foo::connect_tcp("127.0.0.1:3170").unwrap();
+
+    let call1 = client.calls().quick(1).unwrap();
+    let call2 = client.calls().quick(2).unwrap();
+
+    assert_eq!(futures::executor::block_on(call2), Ok(Ok(3)));
+    assert_eq!(call1.wait(), Ok(Ok(2)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn cancels_calls_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3171");
+    let mut client = Foo::connect_tcp("127.0.0.1:3171").unwrap();
+
+    let call = client.calls().slow(2000).unwrap();
+
+    thread::sleep(Duration::from_millis(50));
+
+    call.cancel();
+
+    assert_eq!(log(&server), vec!["cancelled"]);
+
+    // Dropping the call cancels it too
+    drop(client.calls().slow(2000).unwrap());
+
+    assert_eq!(log(&server), vec!["cancelled"]);
+
+    // Giving up on a blocking call as well
+    assert_eq!(client.slow(2000), Err(Error::Timeout));
+    assert_eq!(log(&server), vec!["cancelled"]);
+
+    assert_eq!(client.slow(10), Ok(Ok(true)));
+    assert_eq!(log(&server), vec!["finished"]);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn cancels_async_calls_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3173");
+    let mut client = Foo::connect_tcp("127.0.0.1:3173").unwrap();
+
+    // Dropping the future of an async call cancels it
+    let res = crate::runtime::block_on(async {
+      tokio::time::timeout(Duration::from_millis(50), client.async_calls().slow(2000)).await
+    });
+
+    assert!(res.is_err());
+    assert_eq!(log(&server), vec!["cancelled"]);
+
+    assert_eq!(crate::runtime::block_on(client.async_calls().slow(10)), Ok(Ok(true)));
+    assert_eq!(log(&server), vec!["finished"]);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn tears_down_streams_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3172");
+    let mut client = Foo::connect_tcp("127.0.0.1:3172").unwrap();
+
+    let ticks: Vec<_> = client.ticks().unwrap().take(3).collect();
+
+    assert_eq!(ticks, vec![Ok(0), Ok(1), Ok(2)]);
+    assert_eq!(log(&server), vec!["stream: Cancelled"]);
+
+    let upload = client.sum().unwrap();
+
+    upload.send(1).unwrap();
+    upload.send(2).unwrap();
+
+    drop(upload);
+
+    assert_eq!(log(&server), vec!["upload: Cancelled"]);
+
+    // What was left is not taken for a new call
+    let upload = client.sum().unwrap();
+
+    upload.send(3).unwrap();
+
+    assert_eq!(upload.finish(), Ok(Ok(3)));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod flow {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use crate::FlowControl;
+  #[allow(unused_imports)]
+  use std::sync::atomic::Ordering;
+  #[allow(unused_imports)]
+  use std::thread;
+  #[allow(unused_imports)]
+  use std::time::{Duration, Instant};
+
+  service! {
+    Foo {
+      let sent: std::sync::Arc<std::sync::atomic::AtomicUsize>;
+      let ended: std::sync::Arc<std::sync::atomic::AtomicBool>;
+
+      fn produce(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            break;
+          }
+
+          self.sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        self.ended.store(true, std::sync::atomic::Ordering::SeqCst);
+      }
+
+      fn consume(&mut self, items: stream u64, delay_ms: u64) -> u64 {
+        std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+
+        items.map(|item| item.unwrap()).sum()
+      }
+
+      fn flood(&mut self, n: u64) -> stream u64 |_sink| {
+        let req = self.request_context.as_ref().unwrap();
+
+        // Without waiting for credits
+        let rogue = crate::StreamSink::new(req.async_send.clone(), req.peer, req.local_addr, req.id, 1024);
+
+        for i in 0..n {
+          let _ = rogue.send(i);
+        }
+      }
+
+      fn overflow(&mut self, items: stream u64, delay_ms: u64) -> Option<crate::Error> {
+        std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+
+        items.filter_map(|item| item.err()).next()
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn sent<T: 'static + crate::Transport>(server: &Foo::Server<T>) -> usize {
+    thread::sleep(Duration::from_millis(100));
+
+    server.context.lock().unwrap().sent.load(Ordering::SeqCst)
+  }
+
+  #[test]
+  fn stream_window_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3180");
+    let mut client = Foo::connect_tcp("127.0.0.1:3180").unwrap();
+
+    client.set_flow_control(FlowControl {
+      stream_window: 8,
+      connection_window: 1024,
+    });
+
+    let items = client.produce(100).unwrap();
+
+    // Nothing consumed yet
+    assert_eq!(sent(&server), 8);
+
+    assert_eq!(items.map(|item| item.unwrap()).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+    assert_eq!(sent(&server), 100);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn connection_window_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3181");
+    let mut client = Foo::connect_tcp("127.0.0.1:3181").unwrap();
+
+    client.set_flow_control(FlowControl {
+      stream_window: 8,
+      connection_window: 12,
+    });
+
+    let items1 = client.produce(50).unwrap();
+    let items2 = client.produce(50).unwrap();
+
+    // The second stream only gets what the first one left
+    assert_eq!(sent(&server), 12);
+
+    assert_eq!(items1.count(), 50);
+    assert_eq!(items2.count(), 50);
+    assert_eq!(sent(&server), 100);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn peer_dropped_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3185");
+    let mut client = Foo::connect_tcp("127.0.0.1:3185").unwrap();
+
+    client.set_flow_control(FlowControl {
+      stream_window: 8,
+      connection_window: 1024,
+    });
+
+    let items = client.produce(100).unwrap();
+
+    // Waiting for credits
+    assert_eq!(sent(&server), 8);
+    assert!(!server.context.lock().unwrap().ended.load(Ordering::SeqCst));
+
+    // Gone without refusing the stream
+    client.close();
+
+    assert_eq!(sent(&server), 8);
+    assert!(server.context.lock().unwrap().ended.load(Ordering::SeqCst));
+
+    drop(items);
+    server.close();
+  }
+
+  #[test]
+  fn upload_window_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3182");
+    let mut client = Foo::connect_tcp("127.0.0.1:3182").unwrap();
+
+    server.set_flow_control(FlowControl {
+      stream_window: 4,
+      connection_window: 1024,
+    });
+
+    let upload = client.consume(300).unwrap();
+
+    let start = Instant::now();
+
+    for i in 0..4 {
+      futures::executor::block_on(upload.send_async(i)).unwrap();
+    }
+
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    // Blocks until the server starts consuming
+    for i in 4..10 {
+      upload.send(i).unwrap();
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(250));
+
+    assert_eq!(upload.finish(), Ok(Ok(45)));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn stream_window_exceeded_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3184");
+    let mut client = Foo::connect_tcp("127.0.0.1:3184").unwrap();
+
+    client.set_flow_control(FlowControl {
+      stream_window: 4,
+      connection_window: 1024,
+    });
+
+    let items = client.flood(10).unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(
+      items.collect::<Vec<_>>(),
+      vec![Ok(0), Ok(1), Ok(2), Ok(3), Err(crate::Error::WindowExceeded)]
+    );
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn upload_window_exceeded_tcp() {
+    let mut server = Foo::listen_tcp("127.0.0.1:3183");
+    let mut client = Foo::connect_tcp("127.0.0.1:3183").unwrap();
+
+    server.set_flow_control(FlowControl {
+      stream_window: 4,
+      connection_window: 1024,
+    });
+
+    let upload = client.overflow(100).unwrap();
+
+    let id = client.network.next_id.load(Ordering::SeqCst) - 1;
+
+    // Streams along the same request, without waiting for credits
+    let rogue = crate::StreamSink::<u64>::new_request(
+      client.network.async_packet_sender(),
+      client.serv_addr,
+      client.network.transport.local_addr(),
+      id,
+      1024,
+    );
+
+    for i in 0..10 {
+      rogue.send(i).unwrap();
+    }
+
+    assert_eq!(upload.finish(), Ok(Ok(Some(crate::Error::WindowExceeded))));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod unix {
+  #[allow(unused_imports)]
+  use std::path::PathBuf;
+
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn socket_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("rsrpc-{}-{}.sock", name, std::process::id())).to_string_lossy().into_owned()
+  }
+
+  #[test]
+  fn simple() {
+    let path = socket_path("simple");
+
+    let mut server = Foo::listen_unix(&path);
+    let mut client = Foo::connect_unix(&path).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+    assert_eq!(client.count(5).unwrap().map(|item| item.unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+
+    client.close();
+    server.close();
+
+    assert!(!PathBuf::from(&path).exists());
+  }
+
+  #[test]
+  fn multiple_clients() {
+    let path = socket_path("multiple");
+
+    let mut server = Foo::listen_unix(&path);
+    let mut client1 = Foo::connect_unix(&path).unwrap();
+    let mut client2 = Foo::connect_unix(&path).unwrap();
+
+    assert_eq!(client1.bar(1).unwrap(), Ok(2));
+    assert_eq!(client2.bar(10).unwrap(), Ok(11));
+    assert_eq!(client1.bar(100).unwrap(), Ok(101));
+
+    client1.close();
+    client2.close();
+    server.close();
+  }
+
+  #[test]
+  fn stale_socket() {
+    let path = socket_path("stale");
+
+    // Left behind by a server that did not close
+    let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+    drop(stale);
+
+    let mut server = Foo::listen_unix(&path);
+    let mut client = Foo::connect_unix(&path).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn live_socket() {
+    let path = socket_path("live");
+
+    let mut server = Foo::listen_unix(&path);
+
+    let other = path.clone();
+
+    // Fails to bind instead of taking the path over
+    assert!(std::thread::spawn(move || {
+      Foo::listen_unix(&other);
+    })
+    .join()
+    .is_err());
+
+    let mut client = Foo::connect_unix(&path).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn no_server() {
+    assert!(Foo::connect_unix(&socket_path("none")).is_err());
+  }
+}
+
+mod unix_datagram {
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn make(&mut self, n: usize) -> Vec<u8> {
+        vec![1; n]
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn socket_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("rsrpc-dgram-{}-{}.sock", name, std::process::id())).to_string_lossy().into_owned()
+  }
+
+  #[test]
+  fn simple() {
+    let path = socket_path("simple");
+
+    let mut server = Foo::listen_unix_datagram(&path);
+    let mut client1 = Foo::connect_unix_datagram(&path).unwrap();
+    let mut client2 = Foo::connect_unix_datagram(&path).unwrap();
+
+    // Each answer goes back to the socket its request came from
+    assert_eq!(client1.bar(1).unwrap(), Ok(2));
+    assert_eq!(client2.bar(10).unwrap(), Ok(11));
+
+    // Fragmented
+    assert_eq!(client1.make(200 * 1024).unwrap(), Ok(vec![1; 200 * 1024]));
+
+    client1.close();
+    client2.close();
+    server.close();
+
+    assert!(!std::path::Path::new(&path).exists());
+  }
+
+  #[test]
+  fn duplex() {
+    let path = socket_path("duplex");
+
+    let server = Foo::Duplex::listen_with::<Foo::UnixDatagramTransport>(&path);
+    let client = Foo::Duplex::connect_with::<Foo::UnixDatagramTransport>(&path).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    // Only the transport listened with can be shared
+    assert!(Foo::Duplex::connect_with::<Foo::UdpTransport>("127.0.0.1:3190").is_err());
+
+    drop(server);
+    drop(client);
+    Foo::Duplex::close();
+  }
+
+  #[test]
+  fn forgets_gone_peers() {
+    let path = socket_path("peers");
+
+    let mut server = Foo::listen_unix_datagram(&path);
+
+    let mut peers = crate::UnixPeers::default();
+
+    let live = peers.addr_of(std::path::Path::new(&path));
+    let gone = peers.addr_of(std::path::Path::new("/nonexistent/gone.sock"));
+
+    for i in 0..2000 {
+      peers.addr_of(std::path::Path::new(&format!("/nonexistent/{}.sock", i)));
+    }
+
+    assert_eq!(peers.path_of(&gone), None);
+    assert_eq!(peers.path_of(&live), Some(path.into()));
+
+    server.close();
+  }
+
+  #[test]
+  fn stale_and_live_socket() {
+    let path = socket_path("stale");
+
+    // Left behind by a server that did not close
+    drop(std::os::unix::net::UnixDatagram::bind(&path).unwrap());
+
+    let mut server = Foo::listen_unix_datagram(&path);
+
+    let other = path.clone();
+
+    // Fails to bind instead of taking the path over
+    assert!(std::thread::spawn(move || {
+      Foo::listen_unix_datagram(&other);
+    })
+    .join()
+    .is_err());
+
+    let mut client = Foo::connect_unix_datagram(&path).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn no_server() {
+    assert!(Foo::connect_unix_datagram(&socket_path("none")).is_err());
+  }
+}
+
+mod in_memory {
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn simple() {
+    let mut server = Foo::listen_in_memory("simple");
+    let mut client1 = Foo::connect_in_memory("simple").unwrap();
+    let mut client2 = Foo::connect_in_memory("simple").unwrap();
+
+    assert_eq!(client1.bar(1).unwrap(), Ok(2));
+    assert_eq!(client2.bar(10).unwrap(), Ok(11));
+    assert_eq!(client1.count(5).unwrap().map(|item| item.unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+
+    client1.close();
+    client2.close();
+    server.close();
+
+    // The name is free again
+    assert!(Foo::connect_in_memory("simple").is_err());
+
+    let mut server = Foo::listen_in_memory("simple");
+
+    server.close();
+  }
+
+  #[test]
+  fn duplex() {
+    let server = Foo::Duplex::listen_with::<Foo::InMemoryTransport>("duplex");
+    let client = Foo::Duplex::connect_with::<Foo::InMemoryTransport>("duplex").unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    // Only names listened on are reached
+    assert!(Foo::Duplex::connect_with::<Foo::InMemoryTransport>("nobody").is_err());
+
+    drop(server);
+    drop(client);
+    Foo::Duplex::close();
+  }
+
+  #[test]
+  #[should_panic(expected = "Name already in use")]
+  fn name_in_use() {
+    let _server = Foo::listen_in_memory("taken");
+
+    Foo::listen_in_memory("taken");
+  }
+
+  #[test]
+  fn no_server() {
+    assert!(Foo::connect_in_memory("none").is_err());
+  }
+}
+
+mod tls {
+  #[allow(unused_imports)]
+  use crate::{ClientAuth, TlsConfig};
+  #[allow(unused_imports)]
+  use std::path::PathBuf;
+
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn whoami(&mut self,) -> Vec<String> {
+        self
+          .request_context
+          .as_ref()
+          .and_then(|req| req.peer_identity.clone())
+          .map(|identity| identity.dns_names)
+          .unwrap_or_default()
+      }
+    }
+  }
+
+  /// A self-signed CA, and PEM files of certificates it signs.
+  #[cfg(test)]
+  pub(super) struct Ca {
+    dir: PathBuf,
+    cert: rcgen::Certificate,
+    key: rcgen::KeyPair,
+  }
+
+  #[cfg(test)]
+  impl Ca {
+    pub(super) fn new(name: &str) -> Ca {
+      let dir = std::env::temp_dir().join(format!("rsrpc-tls-{}-{}", name, std::process::id()));
+
+      std::fs::create_dir_all(&dir).unwrap();
+
+      let key = rcgen::KeyPair::generate().unwrap();
+
+      let mut params = rcgen::CertificateParams::new(vec![]).unwrap();
+
+      params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+
+      let cert = params.self_signed(&key).unwrap();
+
+      std::fs::write(dir.join("ca.pem"), cert.pem()).unwrap();
+
+      Ca { dir, cert, key }
+    }
+
+    pub(super) fn ca_file(&self) -> Option<PathBuf> {
+      Some(self.dir.join("ca.pem"))
+    }
+
+    /// Gives the certificate and key files of `name`.
+    pub(super) fn sign(&self, name: &str, alt_names: &[&str]) -> (Option<PathBuf>, Option<PathBuf>) {
+      let key = rcgen::KeyPair::generate().unwrap();
+
+      let params = rcgen::CertificateParams::new(alt_names.iter().map(|name| name.to_string()).collect::<Vec<_>>()).unwrap();
+
+      let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+
+      let cert_file = self.dir.join(format!("{}.pem", name));
+      let key_file = self.dir.join(format!("{}.key", name));
+
+      std::fs::write(&cert_file, cert.pem()).unwrap();
+      std::fs::write(&key_file, key.serialize_pem()).unwrap();
+
+      (Some(cert_file), Some(key_file))
+    }
+
+    pub(super) fn server(&self, client_auth: ClientAuth) -> TlsConfig {
+      let (cert_file, key_file) = self.sign("server", &["localhost", "127.0.0.1"]);
+
+      TlsConfig {
+        cert_file,
+        key_file,
+        ca_file: self.ca_file(),
+        client_auth,
+        server_name: None,
+      }
+    }
+
+    pub(super) fn client(&self, name: Option<&str>) -> TlsConfig {
+      let (cert_file, key_file) = match name {
+        Some(name) => self.sign(name, &[name]),
+        None => (None, None),
+      };
+
+      TlsConfig {
+        cert_file,
+        key_file,
+        ca_file: self.ca_file(),
+        ..Default::default()
+      }
+    }
+  }
+
+  #[test]
+  fn simple() {
+    let ca = Ca::new("simple");
+
+    let mut server = Foo::listen_tls("127.0.0.1:3200", &ca.server(ClientAuth::Off)).unwrap();
+    let mut client = Foo::connect_tls("127.0.0.1:3200", &ca.client(None)).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+    assert_eq!(client.whoami().unwrap(), Ok(vec![]));
+
+    client.close();
+
+    // Checked against a name rather than the IP address
+    let mut client = Foo::connect_tls(
+      "127.0.0.1:3200",
+      &TlsConfig {
+        server_name: Some("localhost".to_string()),
+        ..ca.client(None)
+      },
+    )
+    .unwrap();
+
+    assert_eq!(client.bar(2).unwrap(), Ok(3));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn mutual() {
+    let ca = Ca::new("mutual");
+
+    let mut server = Foo::listen_tls("127.0.0.1:3201", &ca.server(ClientAuth::Required)).unwrap();
+    let mut client = Foo::connect_tls("127.0.0.1:3201", &ca.client(Some("client.example"))).unwrap();
+
+    assert_eq!(client.whoami().unwrap(), Ok(vec!["client.example".to_string()]));
+
+    client.close();
+
+    // Without a certificate
+    let refused = match Foo::connect_tls("127.0.0.1:3201", &ca.client(None)) {
+      Ok(client) => client.bar(1).is_err(),
+      Err(_) => true,
+    };
+
+    assert!(refused);
+
+    // Signed by another CA
+    let other = Ca::new("mutual-other");
+
+    let refused = match Foo::connect_tls("127.0.0.1:3201", &TlsConfig { ca_file: ca.ca_file(), ..other.client(Some("intruder")) }) {
+      Ok(client) => client.bar(1).is_err(),
+      Err(_) => true,
+    };
+
+    assert!(refused);
+
+    server.close();
+  }
+
+  #[test]
+  fn optional_client_auth() {
+    let ca = Ca::new("optional");
+
+    let mut server = Foo::listen_tls("127.0.0.1:3202", &ca.server(ClientAuth::Optional)).unwrap();
+    let mut anonymous = Foo::connect_tls("127.0.0.1:3202", &ca.client(None)).unwrap();
+    let mut known = Foo::connect_tls("127.0.0.1:3202", &ca.client(Some("known.example"))).unwrap();
+
+    assert_eq!(anonymous.whoami().unwrap(), Ok(vec![]));
+    assert_eq!(known.whoami().unwrap(), Ok(vec!["known.example".to_string()]));
+
+    anonymous.close();
+    known.close();
+    server.close();
+  }
+
+  #[test]
+  fn untrusted_server() {
+    let ca = Ca::new("untrusted");
+    let other = Ca::new("untrusted-other");
+
+    let mut server = Foo::listen_tls("127.0.0.1:3203", &ca.server(ClientAuth::Off)).unwrap();
+
+    assert!(Foo::connect_tls("127.0.0.1:3203", &other.client(None)).is_err());
+
+    server.close();
+  }
+
+  #[test]
+  fn bad_config() {
+    let config = TlsConfig {
+      cert_file: Some(PathBuf::from("/nonexistent/cert.pem")),
+      key_file: Some(PathBuf::from("/nonexistent/key.pem")),
+      ..Default::default()
+    };
+
+    assert!(Foo::listen_tls("127.0.0.1:3204", &config).is_err());
+    assert!(Foo::connect_tls("127.0.0.1:3204", &TlsConfig::default()).is_err());
+
+    // Nothing to listen with
+    assert!(Foo::listen_tls("127.0.0.1:3204", &TlsConfig::default()).is_err());
+  }
+}
+
+mod websocket {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use crate::{Network, ServerCallback, WebSocketAddr, WebSocketTransport};
+  #[allow(unused_imports)]
+  use std::time::Duration;
+
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+    }
+  }
+
+  #[allow(dead_code)]
+  fn listen_pinging(url: &str, interval: Duration) -> Foo::Server<WebSocketTransport> {
+    let mut transport = WebSocketTransport::new(&url.parse().unwrap());
+
+    transport.set_ping_interval(interval);
+
+    let mut network = Network::new(transport, ServerCallback::new_empty());
+
+    network.listen();
+
+    Foo::listen_with_network(network)
+  }
+
+  #[test]
+  fn addr() {
+    let addr: WebSocketAddr = "ws://127.0.0.1:3000/rpc".parse().unwrap();
+
+    assert_eq!(addr.addr, "127.0.0.1:3000".parse().unwrap());
+    assert_eq!(addr.path, "/rpc");
+    assert_eq!("127.0.0.1:3000".parse::<WebSocketAddr>().unwrap().path, "/");
+    assert!("localhost/rpc".parse::<WebSocketAddr>().is_err());
+  }
+
+  #[test]
+  fn simple() {
+    let mut server = Foo::listen_websocket("ws://127.0.0.1:3210/rpc");
+    let mut client = Foo::connect_websocket("ws://127.0.0.1:3210/rpc").unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+    assert_eq!(client.count(100).unwrap().map(|item| item.unwrap()).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+
+    // The server knows the client by the port it connected from
+    assert!(server.network.transport.is_connected(&client.network.transport.local_addr()));
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn wrong_path() {
+    let mut server = Foo::listen_websocket("127.0.0.1:3211/rpc");
+
+    assert!(Foo::connect_websocket("127.0.0.1:3211/other").is_err());
+
+    server.close();
+  }
+
+  #[test]
+  fn keepalive() {
+    let mut server = listen_pinging("127.0.0.1:3212", Duration::from_millis(50));
+    let mut client = Foo::connect_websocket("127.0.0.1:3212").unwrap();
+
+    // Idle, but answering pings
+    std::thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    // Never reading, so never answering
+    let stream = std::net::TcpStream::connect("127.0.0.1:3212").unwrap();
+    let local = stream.local_addr().unwrap();
+    let (_socket, _) = tungstenite::client::client("ws://127.0.0.1:3212/", stream).unwrap();
+
+    std::thread::sleep(Duration::from_millis(50));
+
+    assert!(server.network.transport.is_connected(&local));
+
+    std::thread::sleep(Duration::from_millis(300));
+
+    assert!(!server.network.transport.is_connected(&local));
+
+    client.close();
+    server.close();
+  }
+}
+
+mod quic {
+  #[cfg(test)]
+  use super::tls::Ca;
+  #[allow(unused_imports)]
+  use crate::proto::{Packet, PacketKind};
+  #[allow(unused_imports)]
+  use crate::{ClientAuth, Exchange};
+
+  service! {
+    Foo {
+      fn bar(&mut self, n: u64) -> u64 {
+        n + 1
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+
+      fn shout(&mut self, lines: stream String) -> stream String |sink| {
+        for line in lines {
+          sink.send(line.unwrap().to_uppercase()).unwrap();
+        }
+      }
+
+      fn whoami(&mut self,) -> Vec<String> {
+        self
+          .request_context
+          .as_ref()
+          .and_then(|req| req.peer_identity.clone())
+          .map(|identity| identity.dns_names)
+          .unwrap_or_default()
+      }
+    }
+  }
+
+  #[test]
+  fn exchanges() {
+    let sender = "127.0.0.1:3000".parse().unwrap();
+
+    let mut request = Packet::new(Default::default(), sender, 0);
+
+    request.header.id = 7;
+
+    let answer = Packet::new(Default::default(), sender, 7);
+    let ack = Packet::with_kind(PacketKind::Subscribe, Default::default(), sender, 7);
+
+    assert_eq!(request.exchange(), Some(Exchange { id: 7, answers: false }));
+    assert_eq!(answer.exchange(), Some(Exchange { id: 7, answers: true }));
+    assert!(!request.ends_exchange());
+    assert!(answer.ends_exchange());
+    // Messages follow the acknowledgement of a subscription
+    assert!(!ack.ends_exchange());
+    // Along with the request its answers are granted for
+    assert_eq!(
+      crate::flow::credit_packet((sender, 7, true), Some(1), sender).exchange(),
+      Some(Exchange { id: 7, answers: false })
+    );
+    assert_eq!(Packet::new_oneway(Default::default(), sender).exchange(), None);
+  }
+
+  #[test]
+  fn simple() {
+    let ca = Ca::new("quic-simple");
+
+    let mut server = Foo::listen_quic("127.0.0.1:3220", &ca.server(ClientAuth::Off)).unwrap();
+    let mut client = Foo::connect_quic("127.0.0.1:3220", &ca.client(None)).unwrap();
+
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+    assert_eq!(client.count(100).unwrap().map(|item| item.unwrap()).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+
+    let (tx, rx) = client.shout().unwrap();
+
+    for line in &["hello", "world"] {
+      tx.send(line.to_string()).unwrap();
+    }
+
+    drop(tx);
+
+    assert_eq!(rx.map(|item| item.unwrap()).collect::<Vec<_>>(), vec!["HELLO", "WORLD"]);
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn streams_are_finished() {
+    let ca = Ca::new("quic-finished");
+
+    let mut server = Foo::listen_quic("127.0.0.1:3221", &ca.server(ClientAuth::Off)).unwrap();
+    let mut client = Foo::connect_quic("127.0.0.1:3221", &ca.client(None)).unwrap();
+
+    // More than a peer may have open at once
+    for i in 0..1100 {
+      assert_eq!(client.bar(i).unwrap(), Ok(i + 1));
+    }
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn multiplexed() {
+    let ca = Ca::new("quic-multiplexed");
+
+    let mut server = Foo::listen_quic("127.0.0.1:3222", &ca.server(ClientAuth::Off)).unwrap();
+    let mut client = Foo::connect_quic("127.0.0.1:3222", &ca.client(None)).unwrap();
+
+    let first = client.count(500).unwrap();
+    let second = client.count(500).unwrap();
+
+    // Answered while both streams wait to be read
+    assert_eq!(client.bar(1).unwrap(), Ok(2));
+
+    for (a, b) in first.zip(second) {
+      assert_eq!(a, b);
+    }
+
+    client.close();
+    server.close();
+  }
+
+  #[test]
+  fn mutual() {
+    let ca = Ca::new("quic-mutual");
+
+    let mut server = Foo::listen_quic("127.0.0.1:3223", &ca.server(ClientAuth::Required)).unwrap();
+    let mut client = Foo::connect_quic("127.0.0.1:3223", &ca.client(Some("client.example"))).unwrap();
+
+    assert_eq!(client.whoami().unwrap(), Ok(vec!["client.example".to_string()]));
+
+    client.close();
+
+    let refused = match Foo::connect_quic("127.0.0.1:3223", &ca.client(None)) {
+      Ok(client) => client.bar(1).is_err(),
+      Err(_) => true,
+    };
+
+    assert!(refused);
+
+    server.close();
+  }
+
+  #[test]
+  fn untrusted_server() {
+    let ca = Ca::new("quic-untrusted");
+    let other = Ca::new("quic-untrusted-other");
+
+    let mut server = Foo::listen_quic("127.0.0.1:3224", &ca.server(ClientAuth::Off)).unwrap();
+
+    assert!(Foo::connect_quic("127.0.0.1:3224", &other.client(None)).is_err());
+
+    server.close();
+  }
+
+  #[test]
+  fn no_certificate() {
+    let ca = Ca::new("quic-no-certificate");
+
+    assert!(Foo::listen_quic("127.0.0.1:3225", &ca.client(None)).is_err());
+  }
+}
+
+mod stdio {
+  #[allow(unused_imports)]
+  use crate::Transport;
+  #[allow(unused_imports)]
+  use crate::{Network, ServerCallback, StdioCommand, StdioTransport};
+  #[allow(unused_imports)]
+  use std::time::{Duration, Instant};
+
+  // Served by examples/stdio_worker.rs
+  service! {
+    Foo {
+      fn double(&mut self, n: u64) -> u64 {
+        n * 2
+      }
+
+      fn count(&mut self, n: u64) -> stream u64 |sink| {
+        for i in 0..n {
+          if sink.send(i).is_err() {
+            return;
+          }
+        }
+      }
+    }
+  }
+
+  /// The worker example, built by cargo along with the tests.
+  #[allow(dead_code)]
+  fn worker() -> String {
+    let exe = std::env::current_exe().unwrap();
+
+    exe.parent().unwrap().parent().unwrap().join("examples/stdio_worker").display().to_string()
+  }
+
+  #[test]
+  fn command() {
+    let command: StdioCommand = "python3  worker.py --rpc".parse().unwrap();
+
+    assert_eq!(command.program, "python3");
+    assert_eq!(command.args, vec!["worker.py", "--rpc"]);
+    assert_eq!(command.to_string(), "python3 worker.py --rpc");
+    assert!(" ".parse::<StdioCommand>().is_err());
+  }
+
+  #[test]
+  fn pipes() {
+    let (server, client) = tokio::io::duplex(64 * 1024);
+
+    let (server_in, server_out) = tokio::io::split(server);
+    let (client_in, client_out) = tokio::io::split(client);
+
+    let mut transport = StdioTransport::new(&StdioCommand::default());
+
+    transport.attach(server_in, server_out);
+
+    let mut network = Network::new(transport, ServerCallback::new_empty());
+
+    network.listen();
+
+    let mut server = Foo::listen_with_network(network);
+
+    let mut transport = StdioTransport::new(&StdioCommand::default());
+
+    transport.attach(client_in, client_out);
+
+    let mut network = Network::new(transport, ServerCallback::new_empty());
+
+    network.connect().unwrap();
+
+    let mut client = Foo::connect_with_network(network);
+
+    assert_eq!(client.double(21).unwrap(), Ok(42));
+    assert_eq!(client.count(100).unwrap().map(|item| item.unwrap()).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+
+    // The server stops with the other end of its pipes
+    client.close();
+
+    server.wait();
+  }
+
+  #[test]
+  fn child() {
+    let mut client = Foo::connect_stdio(&worker()).unwrap();
+
+    assert_eq!(client.double(21).unwrap(), Ok(42));
+
+    let start = Instant::now();
+
+    client.close();
+
+    // The worker exits once its stdin is closed, rather than being killed
+    assert!(start.elapsed() < Duration::from_secs(1));
+  }
+
+  #[test]
+  fn no_such_program() {
+    assert!(Foo::connect_stdio("/nonexistent/worker").is_err());
+  }
+}
@@ -1,6 +1,6 @@
 use super::oneshot::{channel, Receiver};
+use super::runtime;
 use std::fmt::Debug;
-use std::thread;
 use std::time::Duration;
 
 pub struct Timer {}
@@ -9,8 +9,8 @@ impl Timer {
   pub fn new<T: 'static + Send + Sync + Debug>(wait_time: Duration, err: T) -> Receiver<T> {
     let (tx, rx) = channel::<T>();
 
-    thread::spawn(move || {
-      thread::sleep(wait_time);
+    runtime::spawn(async move {
+      tokio::time::sleep(wait_time).await;
 
       match tx.send(err) {
         Ok(_) => (),
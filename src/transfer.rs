@@ -0,0 +1,367 @@
+//! Resumable transfer of files between a `Transfer` client and server.
+//!
+//! Files go in fixed-size chunks, each one checked against its SHA-256 before
+//! being written, and the whole file is checked once complete. An interrupted
+//! transfer resumes from the last confirmed offset: on the server for uploads,
+//! where the partial file is kept along with what it should become, and on the
+//! client for downloads.
+
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::network::Network;
+use super::transport::Transport;
+use super::utils::to_addr;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk the server hands out at once.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TransferError {
+  Io(String),
+  NotFound,
+  InvalidName,
+  /// The chunk does not start where the confirmed data ends.
+  OffsetMismatch { expected: u64 },
+  ChecksumMismatch,
+  NotStarted,
+  /// The server was given no directory to serve.
+  NotServed,
+  Rpc(Error),
+}
+
+impl std::fmt::Display for TransferError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      TransferError::Io(e) => write!(f, "I/O error: {}", e),
+      TransferError::NotFound => write!(f, "No such file"),
+      TransferError::InvalidName => write!(f, "Invalid file name"),
+      TransferError::OffsetMismatch { expected } => write!(f, "Expected offset {}", expected),
+      TransferError::ChecksumMismatch => write!(f, "Checksum mismatch"),
+      TransferError::NotStarted => write!(f, "Upload not started"),
+      TransferError::NotServed => write!(f, "No directory served"),
+      TransferError::Rpc(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl From<std::io::Error> for TransferError {
+  fn from(e: std::io::Error) -> TransferError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+      TransferError::NotFound
+    } else {
+      TransferError::Io(e.to_string())
+    }
+  }
+}
+
+impl From<Error> for TransferError {
+  fn from(e: Error) -> TransferError {
+    TransferError::Rpc(e)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileInfo {
+  pub size: u64,
+  pub sha256: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chunk {
+  pub offset: u64,
+  pub data: Vec<u8>,
+  pub sha256: Vec<u8>,
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+  let mut sha = Sha256::default();
+
+  sha.input(data);
+
+  sha.result().to_vec()
+}
+
+pub fn file_sha256(path: &Path) -> Result<Vec<u8>, TransferError> {
+  let mut file = File::open(path)?;
+  let mut sha = Sha256::default();
+  let mut buff = vec![0; DEFAULT_CHUNK_SIZE];
+
+  loop {
+    let amount = file.read(&mut buff)?;
+
+    if amount == 0 {
+      break;
+    }
+
+    sha.input(&buff[..amount]);
+  }
+
+  Ok(sha.result().to_vec())
+}
+
+/// The directory served, refusing everything until one is given.
+fn served(root: &Option<PathBuf>) -> Result<&Path, TransferError> {
+  root.as_deref().ok_or(TransferError::NotServed)
+}
+
+/// Only plain file names are served, never paths out of the root.
+fn resolve(root: &Path, name: &str) -> Result<PathBuf, TransferError> {
+  let valid = !name.is_empty()
+    && name != "."
+    && name != ".."
+    && !name.contains('/')
+    && !name.contains('\\')
+    && !name.ends_with(".part")
+    && !name.ends_with(".meta");
+
+  if valid {
+    Ok(root.join(name))
+  } else {
+    Err(TransferError::InvalidName)
+  }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut path = path.as_os_str().to_owned();
+
+  path.push(suffix);
+
+  PathBuf::from(path)
+}
+
+fn begin_upload(root: &Path, name: &str, info: FileInfo) -> Result<u64, TransferError> {
+  let path = resolve(root, name)?;
+  let part = with_suffix(&path, ".part");
+  let meta = with_suffix(&path, ".meta");
+
+  let previous = fs::read(&meta)
+    .ok()
+    .and_then(|meta| bincode::deserialize::<FileInfo>(&meta).ok());
+
+  if previous.as_ref() == Some(&info) {
+    if let Ok(metadata) = fs::metadata(&part) {
+      return Ok(metadata.len());
+    }
+  }
+
+  fs::write(&meta, bincode::serialize(&info).unwrap())?;
+
+  File::create(&part)?;
+
+  Ok(0)
+}
+
+fn put_chunk(root: &Path, name: &str, chunk: Chunk) -> Result<u64, TransferError> {
+  let part = with_suffix(&resolve(root, name)?, ".part");
+
+  let mut file = match OpenOptions::new().append(true).open(&part) {
+    Ok(file) => file,
+    Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Err(TransferError::NotStarted),
+    Err(e) => return Err(e.into()),
+  };
+
+  let confirmed = file.metadata()?.len();
+
+  if chunk.offset != confirmed {
+    return Err(TransferError::OffsetMismatch { expected: confirmed });
+  }
+
+  if sha256(&chunk.data) != chunk.sha256 {
+    return Err(TransferError::ChecksumMismatch);
+  }
+
+  file.write_all(&chunk.data)?;
+
+  Ok(confirmed + chunk.data.len() as u64)
+}
+
+fn finish_upload(root: &Path, name: &str) -> Result<(), TransferError> {
+  let path = resolve(root, name)?;
+  let part = with_suffix(&path, ".part");
+  let meta = with_suffix(&path, ".meta");
+
+  let info: FileInfo = match fs::read(&meta) {
+    Ok(meta) => bincode::deserialize(&meta).map_err(|e| TransferError::Io(e.to_string()))?,
+    Err(_) => return Err(TransferError::NotStarted),
+  };
+
+  if fs::metadata(&part)?.len() != info.size || file_sha256(&part)? != info.sha256 {
+    // Nothing worth resuming from
+    fs::remove_file(&part)?;
+    fs::remove_file(&meta)?;
+
+    return Err(TransferError::ChecksumMismatch);
+  }
+
+  fs::rename(&part, &path)?;
+  fs::remove_file(&meta)?;
+
+  Ok(())
+}
+
+fn stat(root: &Path, name: &str) -> Result<FileInfo, TransferError> {
+  let path = resolve(root, name)?;
+
+  Ok(FileInfo {
+    size: fs::metadata(&path)?.len(),
+    sha256: file_sha256(&path)?,
+  })
+}
+
+fn get_chunk(root: &Path, name: &str, offset: u64, len: u64) -> Result<Chunk, TransferError> {
+  let mut file = File::open(resolve(root, name)?)?;
+
+  file.seek(SeekFrom::Start(offset))?;
+
+  let mut data = vec![];
+
+  file.take(std::cmp::min(len, MAX_CHUNK_SIZE as u64)).read_to_end(&mut data)?;
+
+  Ok(Chunk {
+    offset,
+    sha256: sha256(&data),
+    data,
+  })
+}
+
+service! {
+  Transfer {
+    let root: Option<std::path::PathBuf> = None;
+
+    fn begin_upload(&mut self, name: String, info: super::FileInfo) -> Result<u64, super::TransferError> {
+      super::begin_upload(super::served(&self.root)?, &name, info)
+    }
+
+    fn put_chunk(&mut self, name: String, chunk: super::Chunk) -> Result<u64, super::TransferError> {
+      super::put_chunk(super::served(&self.root)?, &name, chunk)
+    }
+
+    fn finish_upload(&mut self, name: String) -> Result<(), super::TransferError> {
+      super::finish_upload(super::served(&self.root)?, &name)
+    }
+
+    fn stat(&mut self, name: String) -> Result<super::FileInfo, super::TransferError> {
+      super::stat(super::served(&self.root)?, &name)
+    }
+
+    fn get_chunk(&mut self, name: String, offset: u64, len: u64) -> Result<super::Chunk, super::TransferError> {
+      super::get_chunk(super::served(&self.root)?, &name, offset, len)
+    }
+  }
+}
+
+/// Serves the files of `root` on `addr`, uploads included. The servers
+/// made by the constructors of `Transfer` serve nothing.
+pub fn listen<T: 'static + Transport>(addr: &str, root: &Path) -> Transfer::Server<T> {
+  let mut network = Network::<T>::new_default(&to_addr(addr));
+
+  network.listen();
+
+  let server = Transfer::listen_with_network(network);
+
+  server.context.lock().unwrap().root = Some(root.to_path_buf());
+
+  server
+}
+
+fn flatten<R>(res: Result<Result<Result<R, TransferError>, ()>, Error>) -> Result<R, TransferError> {
+  match res {
+    Ok(Ok(res)) => res,
+    Ok(Err(())) => unreachable!(),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/// Uploads `path` as `name`, resuming after the last chunk the server
+/// confirmed when a previous upload of the same file was interrupted.
+pub fn upload<T: 'static + Transport>(
+  client: &Transfer::Client<T>,
+  path: &Path,
+  name: &str,
+  chunk_size: usize,
+) -> Result<(), TransferError> {
+  let info = FileInfo {
+    size: fs::metadata(path)?.len(),
+    sha256: file_sha256(path)?,
+  };
+
+  let mut offset = flatten(client.begin_upload(name.to_string(), info.clone()))?;
+
+  let mut file = File::open(path)?;
+
+  file.seek(SeekFrom::Start(offset))?;
+
+  let mut buff = vec![0; chunk_size];
+
+  while offset < info.size {
+    let amount = file.read(&mut buff)?;
+
+    // Short of the size announced, it would never end
+    if amount == 0 {
+      return Err(TransferError::Io(format!("{} shrank while uploading", path.display())));
+    }
+
+    let chunk = Chunk {
+      offset,
+      data: buff[..amount].to_vec(),
+      sha256: sha256(&buff[..amount]),
+    };
+
+    offset = flatten(client.put_chunk(name.to_string(), chunk))?;
+  }
+
+  flatten(client.finish_upload(name.to_string()))
+}
+
+/// Downloads `name` to `path`, resuming from the chunks already verified
+/// and written when a previous download of the same file was interrupted.
+pub fn download<T: 'static + Transport>(
+  client: &Transfer::Client<T>,
+  name: &str,
+  path: &Path,
+  chunk_size: usize,
+) -> Result<(), TransferError> {
+  let info = flatten(client.stat(name.to_string()))?;
+
+  let part = with_suffix(path, ".part");
+
+  let mut file = OpenOptions::new().create(true).append(true).open(&part)?;
+
+  let mut offset = file.metadata()?.len();
+
+  if offset > info.size {
+    file.set_len(0)?;
+
+    offset = 0;
+  }
+
+  while offset < info.size {
+    let chunk = flatten(client.get_chunk(name.to_string(), offset, chunk_size as u64))?;
+
+    if chunk.offset != offset || chunk.data.is_empty() || sha256(&chunk.data) != chunk.sha256 {
+      return Err(TransferError::ChecksumMismatch);
+    }
+
+    file.write_all(&chunk.data)?;
+
+    offset += chunk.data.len() as u64;
+  }
+
+  drop(file);
+
+  if file_sha256(&part)? != info.sha256 {
+    fs::remove_file(&part)?;
+
+    return Err(TransferError::ChecksumMismatch);
+  }
+
+  fs::rename(&part, path)?;
+
+  Ok(())
+}
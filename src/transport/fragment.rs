@@ -0,0 +1,275 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::byteorder::{ByteOrder, LittleEndian};
+
+/// Message id, fragment index and fragment count.
+pub const FRAGMENT_HEADER_SIZE: usize = 16;
+
+/// Payload per datagram, small enough to fit the minimum IPv6 MTU.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1200;
+
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes of incomplete messages kept from a single peer, each fragment
+/// counted along with its header.
+pub const DEFAULT_MAX_PENDING_PER_PEER: usize = 64 * 1024 * 1024;
+
+/// Bytes of incomplete messages kept from all peers.
+pub const DEFAULT_MAX_PENDING: usize = 256 * 1024 * 1024;
+
+/// Splits a message into datagrams of at most `fragment_size` bytes of
+/// payload, each prefixed with the message id and its position.
+pub fn fragment(msg_id: u64, data: &[u8], fragment_size: usize) -> Vec<Bytes> {
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![data]
+    } else {
+        data.chunks(fragment_size).collect()
+    };
+
+    let count = chunks.len() as u32;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut datagram = BytesMut::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+
+            datagram.put_u64_le(msg_id);
+            datagram.put_u32_le(index as u32);
+            datagram.put_u32_le(count);
+            datagram.put_slice(chunk);
+
+            datagram.freeze()
+        })
+        .collect()
+}
+
+struct Partial {
+    fragments: HashMap<u32, Bytes>,
+    count: u32,
+    size: usize,
+    // Counted against the pending bytes
+    cost: usize,
+    started: Instant,
+    // Orders the messages started at the same instant
+    seq: u64,
+}
+
+/// Collects fragments per peer and message until the message is complete.
+///
+/// A message growing past `max_message_size` is dropped as soon as it does,
+/// and an incomplete one is dropped `timeout` after its first fragment. The
+/// oldest incomplete messages are dropped as well while a peer, or all of
+/// them, keep more pending bytes than allowed.
+pub struct Reassembler {
+    partials: HashMap<Key, Partial>,
+    max_message_size: usize,
+    timeout: Duration,
+    pending_per_peer: HashMap<SocketAddr, usize>,
+    pending_total: usize,
+    max_pending_per_peer: usize,
+    max_pending: usize,
+    next_seq: u64,
+}
+
+type Key = (SocketAddr, u64);
+
+impl Reassembler {
+    pub fn new(max_message_size: usize, timeout: Duration) -> Reassembler {
+        Reassembler {
+            partials: HashMap::new(),
+            max_message_size,
+            timeout,
+            pending_per_peer: HashMap::new(),
+            pending_total: 0,
+            max_pending_per_peer: DEFAULT_MAX_PENDING_PER_PEER,
+            max_pending: DEFAULT_MAX_PENDING,
+            next_seq: 0,
+        }
+    }
+
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Bounds the bytes of incomplete messages kept per peer and in all.
+    pub fn set_max_pending(&mut self, per_peer: usize, total: usize) {
+        self.max_pending_per_peer = per_peer;
+        self.max_pending = total;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Gives the whole message once its last missing fragment arrives.
+    pub fn push(&mut self, from: SocketAddr, mut datagram: Bytes) -> Option<Bytes> {
+        if datagram.len() < FRAGMENT_HEADER_SIZE {
+            warn!("Dropping datagram of {} bytes from {}: no fragment header", datagram.len(), from);
+
+            return None;
+        }
+
+        let msg_id = LittleEndian::read_u64(&datagram[0..8]);
+        let index = LittleEndian::read_u32(&datagram[8..12]);
+        let count = LittleEndian::read_u32(&datagram[12..16]);
+
+        let payload = datagram.split_off(FRAGMENT_HEADER_SIZE);
+
+        if index >= count {
+            warn!("Dropping fragment {}/{} of message {} from {}", index, count, msg_id, from);
+
+            return None;
+        }
+
+        if payload.len() > self.max_message_size {
+            warn!("Dropping message {} from {}: fragment is too large", msg_id, from);
+
+            return None;
+        }
+
+        // Nothing to wait for: the payload is handed out as is
+        if count == 1 {
+            return Some(payload);
+        }
+
+        let key = (from, msg_id);
+
+        let next_seq = &mut self.next_seq;
+
+        let partial = self.partials.entry(key).or_insert_with(|| {
+            *next_seq += 1;
+
+            Partial {
+                fragments: HashMap::new(),
+                count,
+                size: 0,
+                cost: 0,
+                started: Instant::now(),
+                seq: *next_seq,
+            }
+        });
+
+        if partial.count != count || partial.fragments.contains_key(&index) {
+            trace!("Ignoring fragment {}/{} of message {} from {}", index, count, msg_id, from);
+
+            return None;
+        }
+
+        if partial.size + payload.len() > self.max_message_size {
+            warn!("Dropping message {} from {}: more than {} bytes", msg_id, from, self.max_message_size);
+
+            self.remove(&key);
+
+            return None;
+        }
+
+        let cost = FRAGMENT_HEADER_SIZE + payload.len();
+
+        partial.size += payload.len();
+        partial.cost += cost;
+        partial.fragments.insert(index, payload);
+
+        let complete = partial.fragments.len() == count as usize;
+
+        *self.pending_per_peer.entry(from).or_insert(0) += cost;
+        self.pending_total += cost;
+
+        if !complete {
+            self.evict(from);
+
+            return None;
+        }
+
+        let mut partial = self.remove(&key).unwrap();
+
+        let mut message = BytesMut::with_capacity(partial.size);
+
+        for index in 0..count {
+            message.put(partial.fragments.remove(&index).unwrap());
+        }
+
+        Some(message.freeze())
+    }
+
+    /// When the oldest incomplete message is to be dropped, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.partials.values().map(|partial| partial.started + self.timeout).min()
+    }
+
+    /// Drops the messages still incomplete after the timeout.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+
+        let expired: Vec<Key> = self
+            .partials
+            .iter()
+            .filter(|(_, partial)| now.duration_since(partial.started) >= timeout)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired {
+            let partial = self.remove(&key).unwrap();
+
+            warn!(
+                "Dropping message {} from {}: {}/{} fragments after {:?}",
+                key.1,
+                key.0,
+                partial.fragments.len(),
+                partial.count,
+                timeout
+            );
+        }
+    }
+
+    /// Drops the oldest incomplete messages while `from`, or all peers, keep
+    /// more pending bytes than allowed.
+    fn evict(&mut self, from: SocketAddr) {
+        while self.pending_per_peer.get(&from).copied().unwrap_or(0) > self.max_pending_per_peer {
+            match self.oldest(|peer| peer == from) {
+                Some(key) => self.drop_pending(key, "too many pending bytes from the peer"),
+                None => break,
+            }
+        }
+
+        while self.pending_total > self.max_pending {
+            match self.oldest(|_| true) {
+                Some(key) => self.drop_pending(key, "too many pending bytes"),
+                None => break,
+            }
+        }
+    }
+
+    fn oldest<F: Fn(SocketAddr) -> bool>(&self, from: F) -> Option<Key> {
+        self.partials
+            .iter()
+            .filter(|((peer, _), _)| from(*peer))
+            .min_by_key(|(_, partial)| (partial.started, partial.seq))
+            .map(|(key, _)| *key)
+    }
+
+    fn drop_pending(&mut self, key: Key, reason: &str) {
+        warn!("Dropping message {} from {}: {}", key.1, key.0, reason);
+
+        self.remove(&key);
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Partial> {
+        let partial = self.partials.remove(key)?;
+
+        self.pending_total -= partial.cost;
+
+        if let Some(pending) = self.pending_per_peer.get_mut(&key.0) {
+            *pending -= partial.cost;
+
+            if *pending == 0 {
+                self.pending_per_peer.remove(&key.0);
+            }
+        }
+
+        Some(partial)
+    }
+}
@@ -0,0 +1,147 @@
+use bytes::{Buf, Bytes, BytesMut};
+use std::cmp::min;
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::Incoming;
+use crate::byteorder::{ByteOrder, LittleEndian};
+
+const READ_SIZE: usize = 16 * 1024;
+
+/// Length-prefixed framing for stream transports.
+///
+/// Each frame is a little-endian `u64` length followed by the payload. Both
+/// are handed to the socket in one vectored write, so Nagle's algorithm never
+/// holds the payload back waiting for the length to be acknowledged.
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut head = [0u8; 8];
+
+    LittleEndian::write_u64(&mut head, data.len() as u64);
+
+    let mut written = 0;
+
+    while written < 8 + data.len() {
+        let amount = if written < 8 {
+            writer
+                .write_vectored(&[IoSlice::new(&head[written..]), IoSlice::new(data)])
+                .await
+        } else {
+            writer.write(&data[written - 8..]).await
+        };
+
+        match amount {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(amount) => written += amount,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    writer.flush().await
+}
+
+/// Reads a stream straight into a shared buffer and splits it into frames.
+///
+/// Frames are handed out as `Bytes` views on that buffer, so the payload is
+/// never copied after the socket read. A frame announcing more than
+/// `max_frame_size` bytes is never buffered: its payload is discarded as it
+/// arrives and the stream stays usable.
+#[derive(Default)]
+pub struct FrameReader {
+    buff: BytesMut,
+    skip: usize,
+}
+
+impl FrameReader {
+    pub fn new() -> FrameReader {
+        Default::default()
+    }
+
+    pub async fn read_from_async<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> io::Result<usize> {
+        self.buff.reserve(READ_SIZE);
+
+        reader.read_buf(&mut self.buff).await
+    }
+
+    pub fn frames(&mut self, max_frame_size: usize) -> Vec<Bytes> {
+        let mut frames = vec![];
+
+        loop {
+            if self.skip > 0 {
+                let amount = min(self.skip, self.buff.len());
+
+                self.buff.advance(amount);
+                self.skip -= amount;
+
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            if self.buff.len() < 8 {
+                break;
+            }
+
+            let size = LittleEndian::read_u64(&self.buff[..8]) as usize;
+
+            if size > max_frame_size {
+                warn!("Dropping frame of {} bytes (max {})", size, max_frame_size);
+
+                self.buff.advance(8);
+                self.skip = size;
+
+                continue;
+            }
+
+            if self.buff.len() < 8 + size {
+                self.buff.reserve(8 + size - self.buff.len());
+
+                break;
+            }
+
+            let mut frame = self.buff.split_to(8 + size);
+
+            frame.advance(8);
+
+            frames.push(frame.freeze());
+        }
+
+        frames
+    }
+}
+
+/// Queues the frames read from `reader` as received from `addr`, until the
+/// stream ends or the transport is stopped.
+pub async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: R,
+    addr: SocketAddr,
+    incoming: &Incoming,
+    max_frame_size: &RwLock<usize>,
+) {
+    let mut frames = FrameReader::new();
+
+    loop {
+        let read = tokio::select! {
+            read = frames.read_from_async(&mut reader) => read,
+            _ = incoming.stopped() => break,
+        };
+
+        match read {
+            Ok(0) => break,
+            Ok(_) => {
+                let max_frame_size = *max_frame_size.read().unwrap();
+
+                for frame in frames.frames(max_frame_size) {
+                    incoming.push(frame, addr).await;
+                }
+            }
+            Err(e) => {
+                error!("Error read {}", e);
+
+                break;
+            }
+        }
+    }
+}
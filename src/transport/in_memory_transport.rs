@@ -0,0 +1,185 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{connection_addr, Incoming, Transport};
+use crate::limits::Limits;
+
+struct Endpoint {
+    incoming: Incoming,
+    max_frame_size: Arc<RwLock<usize>>,
+}
+
+/// The endpoints of the process, and the names they listen on.
+#[derive(Default)]
+struct Registry {
+    names: HashMap<String, SocketAddr>,
+    endpoints: HashMap<SocketAddr, Endpoint>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Packets handed over to the queues of the endpoints of the same
+/// process, servers being found by name. Nothing is bound, so any number of
+/// tests or modules can run at once as long as their names differ.
+pub struct InMemoryTransport {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub listening: bool,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub incoming: Incoming,
+}
+
+impl InMemoryTransport {
+    fn register(&mut self) {
+        REGISTRY.lock().unwrap().endpoints.insert(
+            self.addr,
+            Endpoint {
+                incoming: self.incoming.clone(),
+                max_frame_size: self.max_frame_size.clone(),
+            },
+        );
+
+        self.incoming.start();
+    }
+}
+
+impl Clone for InMemoryTransport {
+    fn clone(&self) -> Self {
+        InMemoryTransport {
+            name: self.name.clone(),
+            addr: self.addr,
+            listening: self.listening,
+            max_frame_size: self.max_frame_size.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for InMemoryTransport {
+    type Addr = String;
+
+    fn new(name: &String) -> InMemoryTransport {
+        InMemoryTransport {
+            name: name.clone(),
+            addr: connection_addr(NEXT_ID.fetch_add(1, Ordering::SeqCst)),
+            listening: false,
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        let taken = {
+            let mut registry = REGISTRY.lock().unwrap();
+
+            let taken = registry.names.contains_key(&self.name);
+
+            if !taken {
+                registry.names.insert(self.name.clone(), self.addr);
+            }
+
+            taken
+        };
+
+        // Not while holding the registry, for it not to be poisoned
+        if taken {
+            panic!("Name already in use: {}", self.name);
+        }
+
+        self.listening = true;
+
+        self.register();
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if !REGISTRY.lock().unwrap().names.contains_key(&self.name) {
+            return Err(format!("Nothing listening on {}", self.name));
+        }
+
+        self.register();
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> String {
+        self.name.clone()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.resolve(&self.name).unwrap_or(self.addr)
+    }
+
+    fn resolve(&self, name: &String) -> Option<SocketAddr> {
+        REGISTRY.lock().unwrap().names.get(name).cloned()
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        // Not held while waiting for room in the queue of the peer
+        let incoming = {
+            let registry = REGISTRY.lock().unwrap();
+
+            let endpoint = match registry.endpoints.get(addr) {
+                Some(endpoint) => endpoint,
+                None => {
+                    trace!("Not connected to {}", addr);
+
+                    return false;
+                }
+            };
+
+            // Dropped as a stream transport would drop the frame
+            if buff.len() > *endpoint.max_frame_size.read().unwrap() {
+                warn!("Dropping frame of {} bytes to {}", buff.len(), addr);
+
+                return true;
+            }
+
+            endpoint.incoming.clone()
+        };
+
+        trace!("Sent {} to {}", buff.len(), addr);
+
+        incoming.push(buff, self.addr).await;
+
+        true
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        REGISTRY.lock().unwrap().endpoints.contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        let mut registry = REGISTRY.lock().unwrap();
+
+        registry.endpoints.remove(&self.addr);
+
+        if self.listening && registry.names.get(&self.name) == Some(&self.addr) {
+            registry.names.remove(&self.name);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Mutex};
+
+// Frames queued before the transport stops reading its peers
+const QUEUE_SIZE: usize = 256;
+
+/// The frames received by a transport, waiting to be read by its network,
+/// the peers it lost, and whether the transport still runs. Shared by every
+/// clone.
+#[derive(Clone)]
+pub struct Incoming {
+    sender: Sender<(Bytes, SocketAddr)>,
+    receiver: Arc<Mutex<Receiver<(Bytes, SocketAddr)>>>,
+    // Once per connection, so never more than the peers accepted
+    dropped: UnboundedSender<SocketAddr>,
+    dropped_receiver: Arc<Mutex<UnboundedReceiver<SocketAddr>>>,
+    running: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Incoming {
+    fn default() -> Incoming {
+        Incoming::new()
+    }
+}
+
+impl Incoming {
+    pub fn new() -> Incoming {
+        let (sender, receiver) = channel(QUEUE_SIZE);
+        let (dropped, dropped_receiver) = unbounded_channel();
+
+        Incoming {
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+            dropped,
+            dropped_receiver: Arc::new(Mutex::new(dropped_receiver)),
+            running: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Queues a frame received from `from`, waiting for room while the
+    /// queue is full. Dropped if the transport is stopped meanwhile.
+    pub async fn push(&self, frame: Bytes, from: SocketAddr) {
+        tokio::select! {
+            biased;
+
+            // The receiving end lives as long as this
+            _ = self.sender.send((frame, from)) => (),
+            _ = self.stopped() => trace!("Dropping a frame from {}: stopped", from),
+        }
+    }
+
+    /// Tells the network that the connection to `addr` dropped.
+    pub fn disconnected(&self, addr: SocketAddr) {
+        let _ = self.dropped.send(addr);
+    }
+
+    pub fn start(&self) {
+        self.running.send_replace(true);
+    }
+
+    /// Stops the transport: its network reads what is queued, then ends.
+    pub fn stop(&self) {
+        self.running.send_replace(false);
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.running.borrow()
+    }
+
+    /// Completes once the transport is stopped, for its tasks to end.
+    pub async fn stopped(&self) {
+        let mut running = self.running.subscribe();
+
+        let _ = running.wait_for(|running| !running).await;
+    }
+
+    /// The next frame, or `None` once stopped and the frames received before
+    /// are read.
+    pub async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        let mut running = self.running.subscribe();
+
+        let mut receiver = self.receiver.lock().await;
+
+        tokio::select! {
+            biased;
+
+            frame = receiver.recv() => frame,
+            _ = running.wait_for(|running| !running) => None,
+        }
+    }
+
+    /// The next peer whose connection dropped, or `None` once stopped.
+    pub async fn dropped(&self) -> Option<SocketAddr> {
+        let mut running = self.running.subscribe();
+
+        let mut receiver = self.dropped_receiver.lock().await;
+
+        tokio::select! {
+            biased;
+
+            addr = receiver.recv() => addr,
+            _ = running.wait_for(|running| !running) => None,
+        }
+    }
+}
@@ -1,20 +1,98 @@
-use std::net::SocketAddr;
-use std::sync::mpsc::Receiver;
-use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+use std::fmt::{Debug, Display};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::future::Future;
+use std::str::FromStr;
 
+use crate::proto::Exchange;
+
+mod fragment;
+mod framing;
+mod in_memory_transport;
+mod incoming;
+mod quic_transport;
+mod reliable;
+mod socket_options;
+mod stdio_transport;
 mod tcp_transport;
+mod tls_transport;
 mod udp_transport;
+mod unix_datagram_transport;
+mod unix_transport;
+mod websocket_transport;
 
+pub use self::fragment::{fragment, Reassembler};
+pub use self::framing::{read_frames, write_frame_async, FrameReader};
+pub use self::in_memory_transport::InMemoryTransport;
+pub use self::incoming::Incoming;
+pub use self::quic_transport::QuicTransport;
+pub use self::reliable::{Reliability, ReliabilityConfig};
+pub use self::socket_options::SocketOptions;
+pub use self::stdio_transport::{StdioCommand, StdioTransport};
 pub use self::tcp_transport::TcpTransport;
+pub use self::tls_transport::{ClientAuth, PeerIdentity, TlsConfig, TlsTransport};
 pub use self::udp_transport::UdpTransport;
+pub use self::unix_datagram_transport::{UnixDatagramTransport, UnixPeers, UNIX_FRAGMENT_SIZE};
+pub use self::unix_transport::UnixTransport;
+pub use self::websocket_transport::{WebSocketAddr, WebSocketTransport, DEFAULT_PING_INTERVAL};
+
+/// Moves packets between peers. Peers are told apart by a `SocketAddr`,
+/// made up by the transport for those that have none.
+///
+/// Sending and receiving are asynchronous, driven by the tasks of the
+/// `runtime` of the crate; `Network` blocks on them for the blocking API.
+pub trait Transport: Sync + Sized + Clone + Send + 'static {
+  /// The address to listen on or to connect to.
+  type Addr: Clone + Debug + FromStr<Err: Display> + Send + Sync + 'static;
 
-pub trait Transport: Sync + Sized + Clone + Send {
-  fn new(addr: &SocketAddr) -> Self;
-  fn get_addr(&self) -> SocketAddr;
-  fn listen(&mut self);
-  fn connect(&mut self) -> Result<(), String>;
-  fn send(&mut self, addr: &SocketAddr, data: Vec<u8>) -> bool;
-  fn get_recv(&mut self) -> Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>>;
+  fn new(addr: &Self::Addr) -> Self;
+  fn get_addr(&self) -> Self::Addr;
+  /// The address this end puts in the packets it sends.
+  fn local_addr(&self) -> SocketAddr;
+  /// The peer a connected client sends its requests to.
+  fn peer_addr(&self) -> SocketAddr;
+  /// The peer at `addr`, for transports sending to any address from the
+  /// socket they listen on, as `Duplex` does.
+  fn resolve(&self, _addr: &Self::Addr) -> Option<SocketAddr> {
+    None
+  }
+  fn set_max_frame_size(&mut self, size: usize);
+  fn listen(&mut self) -> impl Future<Output = ()> + Send;
+  fn connect(&mut self) -> impl Future<Output = Result<(), String>> + Send;
+  fn send(&self, addr: &SocketAddr, data: Bytes) -> impl Future<Output = bool> + Send;
+  /// Sends a packet of `exchange`, `last` if nothing follows it. Transports
+  /// multiplexing requests, as QUIC does, keep each exchange in order but
+  /// apart from the others; the others send it as any packet.
+  fn send_on(
+    &self,
+    addr: &SocketAddr,
+    _exchange: Option<Exchange>,
+    _last: bool,
+    data: Bytes,
+  ) -> impl Future<Output = bool> + Send {
+    self.send(addr, data)
+  }
+  /// The next packet received from any peer, or `None` once closed.
+  fn recv(&self) -> impl Future<Output = Option<(Bytes, SocketAddr)>> + Send;
+  /// The next peer whose connection dropped, for transports that can tell.
+  fn dropped(&self) -> impl Future<Output = Option<SocketAddr>> + Send {
+    std::future::pending()
+  }
+  /// The certificate `addr` authenticated with, for transports that can
+  /// tell.
+  fn peer_identity(&self, _addr: &SocketAddr) -> Option<PeerIdentity> {
+    None
+  }
+  /// Whether `addr` is still connected, for transports that can tell.
+  fn is_connected(&self, _addr: &SocketAddr) -> bool {
+    true
+  }
   fn is_running(&mut self) -> bool;
   fn close(&mut self);
 }
+
+/// A made-up address for the peer `id` of a transport that has no socket
+/// addresses, such as Unix domain sockets.
+pub fn connection_addr(id: u64) -> SocketAddr {
+  SocketAddr::new(IpAddr::V6(Ipv6Addr::from(u128::from(id))), 0)
+}
@@ -0,0 +1,438 @@
+use bytes::Bytes;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, ServerConfig, TransportConfig, VarInt};
+use rustls::pki_types::CertificateDer;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use super::{Incoming, PeerIdentity, TlsConfig, Transport};
+use crate::byteorder::{ByteOrder, LittleEndian};
+use crate::limits::Limits;
+use crate::proto::Exchange;
+use crate::runtime;
+
+// Longest a client waits for the server to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Streams a peer may have open towards us at once
+const MAX_STREAMS: u32 = 1024;
+
+// Exchanges with nothing sent for that long get their stream finished, for
+// those whose last packet never comes, such as cancelled streams
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ALPN: &[u8] = b"rsrpc";
+
+/// The stream of an exchange, opened with its first packet.
+type Outgoing = Arc<tokio::sync::Mutex<Option<quinn::SendStream>>>;
+
+/// The streams of the exchanges going on with each peer, and when they were
+/// last sent on.
+type Streams = HashMap<(SocketAddr, Exchange), (Outgoing, Instant)>;
+
+struct Peer {
+    conn: Connection,
+    identity: Option<PeerIdentity>,
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+
+    config
+        .max_concurrent_uni_streams(VarInt::from_u32(MAX_STREAMS))
+        .max_concurrent_bidi_streams(VarInt::from_u32(0))
+        .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+
+    Arc::new(config)
+}
+
+/// The peers and what their tasks feed, shared with the tasks.
+#[derive(Clone)]
+struct Peers {
+    clients: Arc<RwLock<HashMap<SocketAddr, Peer>>>,
+    streams: Arc<Mutex<Streams>>,
+    incoming: Incoming,
+    max_frame_size: Arc<RwLock<usize>>,
+}
+
+impl Peers {
+    fn add(&self, addr: SocketAddr, conn: Connection) {
+        let identity = conn
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .and_then(|chain| PeerIdentity::new(&chain));
+
+        self.clients.write().unwrap().insert(
+            addr,
+            Peer {
+                conn: conn.clone(),
+                identity,
+            },
+        );
+
+        let peers = self.clone();
+
+        runtime::spawn(async move {
+            loop {
+                match conn.accept_uni().await {
+                    Ok(stream) => {
+                        runtime::spawn(stream_read(addr, stream, peers.incoming.clone(), peers.max_frame_size.clone()));
+                    }
+                    Err(e) => {
+                        debug!("Disconnected {}: {}", addr, e);
+
+                        break;
+                    }
+                }
+            }
+
+            let mut clients = peers.clients.write().unwrap();
+
+            // Unless it connected again meanwhile
+            if clients.get(&addr).is_some_and(|peer| peer.conn.stable_id() == conn.stable_id()) {
+                clients.remove(&addr);
+
+                peers.streams.lock().unwrap().retain(|(peer, _), _| *peer != addr);
+
+                peers.incoming.disconnected(addr);
+            }
+        });
+    }
+
+    /// The stream to send a packet of `exchange` on, forgotten once `last`
+    /// is sent. Idle ones are finished by dropping them.
+    fn outgoing(&self, addr: &SocketAddr, exchange: Option<Exchange>, last: bool) -> Outgoing {
+        let exchange = match exchange {
+            Some(exchange) => exchange,
+            None => return Arc::new(tokio::sync::Mutex::new(None)),
+        };
+
+        let mut streams = self.streams.lock().unwrap();
+
+        streams.retain(|_, (stream, used)| Arc::strong_count(stream) > 1 || used.elapsed() < STREAM_IDLE_TIMEOUT);
+
+        let key = (*addr, exchange);
+
+        let outgoing = {
+            let entry = streams
+                .entry(key)
+                .or_insert_with(|| (Arc::new(tokio::sync::Mutex::new(None)), Instant::now()));
+
+            entry.1 = Instant::now();
+
+            entry.0.clone()
+        };
+
+        if last {
+            streams.remove(&key);
+        }
+
+        outgoing
+    }
+}
+
+async fn stream_read(addr: SocketAddr, mut stream: RecvStream, incoming: Incoming, max_frame_size: Arc<RwLock<usize>>) {
+    let mut head = [0u8; 8];
+
+    // Until the stream is finished
+    while stream.read_exact(&mut head).await.is_ok() {
+        let size = LittleEndian::read_u64(&head) as usize;
+
+        if size > *max_frame_size.read().unwrap() {
+            warn!("Dropping stream from {} with a frame of {} bytes", addr, size);
+
+            let _ = stream.stop(VarInt::from_u32(0));
+
+            return;
+        }
+
+        let mut buff = vec![0; size];
+
+        if stream.read_exact(&mut buff).await.is_err() {
+            return;
+        }
+
+        trace!("Read {} from {}", size, addr);
+
+        incoming.push(Bytes::from(buff), addr).await;
+    }
+}
+
+/// Frames over QUIC, with quinn, secured by TLS 1.3 as set by a `TlsConfig`.
+///
+/// Every request gets a unidirectional stream each way, opened with its
+/// first packet and finished with its last, so that a slow or lost packet
+/// only holds back its own request. Notifications, batches and credits get
+/// a stream of their own. A frame over `max_frame_size` resets its stream.
+pub struct QuicTransport {
+    pub addr: SocketAddr,
+    endpoint: Option<Endpoint>,
+    server_config: Option<ServerConfig>,
+    client_config: Option<ClientConfig>,
+    server_name: Option<String>,
+    peers: Peers,
+}
+
+impl QuicTransport {
+    /// Loads the certificates and keys of `config`. Listening needs a
+    /// certificate and its key, connecting needs a CA.
+    pub fn set_config(&mut self, config: &TlsConfig) -> Result<(), String> {
+        let (server_config, client_config) = config.rustls_configs(&[&rustls::version::TLS13])?;
+
+        self.server_config = match server_config {
+            Some(mut server_config) => {
+                server_config.alpn_protocols = vec![ALPN.to_vec()];
+
+                let crypto = QuicServerConfig::try_from(server_config).map_err(|e| e.to_string())?;
+
+                let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
+
+                server_config.transport_config(transport_config());
+
+                Some(server_config)
+            }
+            None => None,
+        };
+
+        self.client_config = match client_config {
+            Some(mut client_config) => {
+                client_config.alpn_protocols = vec![ALPN.to_vec()];
+
+                let crypto = QuicClientConfig::try_from(client_config).map_err(|e| e.to_string())?;
+
+                let mut client_config = ClientConfig::new(Arc::new(crypto));
+
+                client_config.transport_config(transport_config());
+
+                Some(client_config)
+            }
+            None => None,
+        };
+
+        self.server_name = config.server_name.clone();
+
+        Ok(())
+    }
+
+    /// Whether a certificate to listen with is configured.
+    pub fn can_listen(&self) -> bool {
+        self.server_config.is_some()
+    }
+}
+
+impl Clone for QuicTransport {
+    fn clone(&self) -> Self {
+        QuicTransport {
+            addr: self.addr,
+            endpoint: self.endpoint.clone(),
+            server_config: self.server_config.clone(),
+            client_config: self.client_config.clone(),
+            server_name: self.server_name.clone(),
+            peers: self.peers.clone(),
+        }
+    }
+}
+
+impl Transport for QuicTransport {
+    type Addr = SocketAddr;
+
+    fn new(addr: &SocketAddr) -> QuicTransport {
+        QuicTransport {
+            addr: *addr,
+            peers: Peers {
+                clients: Arc::new(RwLock::new(HashMap::new())),
+                streams: Arc::new(Mutex::new(HashMap::new())),
+                incoming: Incoming::new(),
+                max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            },
+            endpoint: None,
+            server_config: None,
+            client_config: None,
+            server_name: None,
+        }
+    }
+
+    async fn listen(&mut self) {
+        let config = self.server_config.clone().expect("QuicTransport: No certificate to listen with");
+
+        let endpoint = Endpoint::server(config, self.addr).unwrap();
+
+        self.endpoint = Some(endpoint.clone());
+
+        self.peers.incoming.start();
+
+        let peers = self.peers.clone();
+
+        runtime::spawn(async move {
+            // Until the endpoint is closed
+            while let Some(incoming) = endpoint.accept().await {
+                let addr = incoming.remote_address();
+
+                let peers = peers.clone();
+
+                // Not to hold back the other clients
+                runtime::spawn(async move {
+                    match incoming.await {
+                        Ok(conn) => {
+                            debug!("Accept {:?}", addr);
+
+                            peers.add(addr, conn);
+                        }
+                        Err(e) => warn!("Handshake with {} failed: {}", addr, e),
+                    }
+                });
+            }
+        });
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        let config = match self.client_config.clone() {
+            Some(config) => config,
+            None => return Err("QuicTransport: No CA to verify the server with".to_string()),
+        };
+
+        let bind: SocketAddr = if self.addr.is_ipv6() {
+            "[::]:0".parse().unwrap()
+        } else {
+            "0.0.0.0:0".parse().unwrap()
+        };
+
+        let server_name = self.server_name.clone().unwrap_or_else(|| self.addr.ip().to_string());
+
+        let endpoint = Endpoint::client(bind).map_err(|e| e.to_string())?;
+
+        let connecting = endpoint
+            .connect_with(config, self.addr, &server_name)
+            .map_err(|e| e.to_string())?;
+
+        let conn = match tokio::time::timeout(HANDSHAKE_TIMEOUT, connecting).await {
+            Ok(conn) => conn.map_err(|e| e.to_string())?,
+            Err(_) => return Err(format!("No handshake from {}", self.addr)),
+        };
+
+        self.endpoint = Some(endpoint);
+
+        self.peers.incoming.start();
+
+        self.peers.add(self.addr, conn);
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn peer_identity(&self, addr: &SocketAddr) -> Option<PeerIdentity> {
+        self.peers.clients.read().unwrap().get(addr).and_then(|peer| peer.identity.clone())
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.peers.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        self.send_on(addr, None, true, buff).await
+    }
+
+    async fn send_on(&self, addr: &SocketAddr, exchange: Option<Exchange>, last: bool, buff: Bytes) -> bool {
+        let conn = self.peers.clients.read().unwrap().get(addr).map(|peer| peer.conn.clone());
+
+        let conn = match conn {
+            Some(conn) => conn,
+            None => {
+                trace!("Not connected to {}", addr);
+
+                return false;
+            }
+        };
+
+        let outgoing = self.peers.outgoing(addr, exchange, last);
+
+        let len = buff.len();
+
+        let sent = async move {
+            let mut stream = outgoing.lock().await;
+
+            if stream.is_none() {
+                *stream = Some(conn.open_uni().await.map_err(|e| e.to_string())?);
+            }
+
+            let mut head = [0u8; 8];
+
+            LittleEndian::write_u64(&mut head, buff.len() as u64);
+
+            let res = match stream.as_mut() {
+                Some(stream) => match stream.write_all(&head).await {
+                    Ok(()) => stream.write_chunk(buff).await,
+                    Err(e) => Err(e),
+                },
+                None => unreachable!(),
+            };
+
+            // The next packet of the exchange opens another one
+            if res.is_err() {
+                stream.take();
+            }
+
+            res.map_err(|e| e.to_string())
+        }
+        .await;
+
+        match sent {
+            Ok(()) => {
+                trace!("Sent {} to {}", len, addr);
+
+                true
+            }
+            Err(e) => {
+                debug!("Cannot send to {}: {}", addr, e);
+
+                false
+            }
+        }
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.peers.clients.read().unwrap().contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.peers.incoming.recv().await
+    }
+
+    async fn dropped(&self) -> Option<SocketAddr> {
+        self.peers.incoming.dropped().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.peers.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.peers.incoming.stop();
+
+        self.peers.streams.lock().unwrap().clear();
+
+        for (_, peer) in self.peers.clients.write().unwrap().drain() {
+            peer.conn.close(VarInt::from_u32(0), b"closed");
+        }
+
+        if let Some(endpoint) = self.endpoint.take() {
+            endpoint.close(VarInt::from_u32(0), b"closed");
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::byteorder::{ByteOrder, LittleEndian};
+
+const RAW: u8 = 0;
+const DATA: u8 = 1;
+const ACK: u8 = 2;
+
+/// Kind, sender session and sequence number.
+pub const RELIABLE_HEADER_SIZE: usize = 17;
+
+// Acknowledgements also carry the next sequence number expected, which
+// acknowledges at once all the datagrams before it
+const ACK_SIZE: usize = RELIABLE_HEADER_SIZE + 8;
+
+// Datagrams held per peer while waiting for a missing one
+const MAX_BUFFERED: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReliabilityConfig {
+    /// First retransmission delay, doubled on every retry.
+    pub initial_rto: Duration,
+    pub max_rto: Duration,
+    /// Retransmissions before a datagram is given up.
+    pub max_attempts: u32,
+    /// How long a receiver waits for a missing datagram before skipping it.
+    pub gap_timeout: Duration,
+}
+
+impl Default for ReliabilityConfig {
+    fn default() -> ReliabilityConfig {
+        ReliabilityConfig {
+            initial_rto: Duration::from_millis(50),
+            max_rto: Duration::from_secs(1),
+            max_attempts: 10,
+            gap_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A datagram along with its destination or its sender.
+pub type Addressed = (SocketAddr, Bytes);
+
+struct Pending {
+    datagram: Bytes,
+    next_send: Instant,
+    rto: Duration,
+    attempts: u32,
+}
+
+struct Peer {
+    session: u64,
+    next: u64,
+    buffered: BTreeMap<u64, Bytes>,
+    gap_since: Option<Instant>,
+}
+
+/// Acknowledgements, retransmission, duplicate suppression and per-peer
+/// ordering for datagrams.
+///
+/// Every datagram starts with its kind, so a peer sending raw datagrams can
+/// still talk to a reliable one. Sequence numbers belong to a session drawn
+/// when the sender starts, so a restarted peer is not taken for a replay.
+pub struct Reliability {
+    session: u64,
+    config: ReliabilityConfig,
+    next_seq: HashMap<SocketAddr, u64>,
+    unacked: HashMap<SocketAddr, BTreeMap<u64, Pending>>,
+    peers: HashMap<SocketAddr, Peer>,
+}
+
+impl Reliability {
+    pub fn new(config: ReliabilityConfig) -> Reliability {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        Reliability {
+            session: now.as_secs() ^ (u64::from(now.subsec_nanos()) << 32),
+            config,
+            next_seq: HashMap::new(),
+            unacked: HashMap::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: ReliabilityConfig) {
+        self.config = config;
+    }
+
+    /// Datagrams sent but not acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.unacked.values().map(|pending| pending.len()).sum()
+    }
+
+    /// A datagram sent once, with no delivery guarantee.
+    pub fn raw(payload: &[u8]) -> Bytes {
+        let mut datagram = BytesMut::with_capacity(1 + payload.len());
+
+        datagram.put_u8(RAW);
+        datagram.put_slice(payload);
+
+        datagram.freeze()
+    }
+
+    /// A datagram retransmitted until `to` acknowledges it.
+    pub fn wrap(&mut self, to: SocketAddr, payload: &[u8], now: Instant) -> Bytes {
+        let seq = self.next_seq.entry(to).or_insert(0);
+
+        let datagram = Self::header(DATA, self.session, *seq, payload);
+
+        self.unacked.entry(to).or_default().insert(
+            *seq,
+            Pending {
+                datagram: datagram.clone(),
+                next_send: now + self.config.initial_rto,
+                rto: self.config.initial_rto,
+                attempts: 0,
+            },
+        );
+
+        *seq += 1;
+
+        datagram
+    }
+
+    /// Handles a received datagram. Gives the acknowledgement to send back,
+    /// if any, and the payloads now deliverable in order.
+    pub fn receive(&mut self, from: SocketAddr, mut datagram: Bytes, now: Instant) -> (Option<Bytes>, Vec<Bytes>) {
+        if datagram.is_empty() {
+            return (None, vec![]);
+        }
+
+        let kind = datagram[0];
+
+        if kind == RAW {
+            return (None, vec![datagram.split_off(1)]);
+        }
+
+        if datagram.len() < RELIABLE_HEADER_SIZE {
+            warn!("Dropping datagram of {} bytes from {}: no reliability header", datagram.len(), from);
+
+            return (None, vec![]);
+        }
+
+        let session = LittleEndian::read_u64(&datagram[1..9]);
+        let seq = LittleEndian::read_u64(&datagram[9..17]);
+
+        match kind {
+            ACK if datagram.len() >= ACK_SIZE => {
+                let next = LittleEndian::read_u64(&datagram[17..25]);
+
+                if session == self.session {
+                    if let Some(pending) = self.unacked.get_mut(&from) {
+                        pending.remove(&seq);
+
+                        *pending = pending.split_off(&next);
+                    }
+                }
+
+                (None, vec![])
+            }
+            DATA => {
+                let payload = datagram.split_off(RELIABLE_HEADER_SIZE);
+
+                // Refused datagrams are left for the sender to retransmit
+                let delivered = match self.deliver(from, session, seq, payload, now) {
+                    Some(delivered) => delivered,
+                    None => return (None, vec![]),
+                };
+
+                let mut next = [0u8; 8];
+
+                LittleEndian::write_u64(&mut next, self.peers[&from].next);
+
+                (Some(Self::header(ACK, session, seq, &next)), delivered)
+            }
+            _ => {
+                warn!("Dropping datagram of unknown kind {} from {}", kind, from);
+
+                (None, vec![])
+            }
+        }
+    }
+
+    /// When `poll` has something to do next, if anything.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let resend = self.unacked.values().flat_map(|pending| pending.values().map(|pending| pending.next_send));
+
+        let skip = self
+            .peers
+            .values()
+            .filter_map(|peer| peer.gap_since.map(|since| since + self.config.gap_timeout));
+
+        resend.chain(skip).min()
+    }
+
+    /// Gives the datagrams due for retransmission, and the payloads
+    /// delivered by skipping datagrams missing for too long.
+    pub fn poll(&mut self, now: Instant) -> (Vec<Addressed>, Vec<Addressed>) {
+        let config = self.config;
+
+        let mut resend = vec![];
+
+        for (to, pending) in self.unacked.iter_mut() {
+            pending.retain(|seq, pending| {
+                if pending.next_send > now {
+                    return true;
+                }
+
+                if pending.attempts >= config.max_attempts {
+                    warn!("Giving up datagram {} to {} after {} attempts", seq, to, pending.attempts);
+
+                    return false;
+                }
+
+                pending.attempts += 1;
+                pending.rto = std::cmp::min(pending.rto * 2, config.max_rto);
+                pending.next_send = now + pending.rto;
+
+                resend.push((*to, pending.datagram.clone()));
+
+                true
+            });
+        }
+
+        let mut delivered = vec![];
+
+        for (from, peer) in self.peers.iter_mut() {
+            let stalled = match peer.gap_since {
+                Some(since) => now.duration_since(since) >= config.gap_timeout,
+                None => false,
+            };
+
+            if stalled {
+                let first = *peer.buffered.keys().next().unwrap();
+
+                warn!("Skipping datagrams {} to {} from {}", peer.next, first - 1, from);
+
+                peer.next = first;
+
+                for payload in Self::drain(peer, now) {
+                    delivered.push((*from, payload));
+                }
+            }
+        }
+
+        (resend, delivered)
+    }
+
+    /// Gives the payloads now deliverable, or `None` if the datagram could not
+    /// be held.
+    fn deliver(&mut self, from: SocketAddr, session: u64, seq: u64, payload: Bytes, now: Instant) -> Option<Vec<Bytes>> {
+        let peer = self.peers.entry(from).or_insert_with(|| Peer {
+            session,
+            next: 0,
+            buffered: BTreeMap::new(),
+            gap_since: None,
+        });
+
+        if peer.session != session {
+            debug!("New session from {}", from);
+
+            *peer = Peer {
+                session,
+                next: 0,
+                buffered: BTreeMap::new(),
+                gap_since: None,
+            };
+        }
+
+        if seq < peer.next || peer.buffered.contains_key(&seq) {
+            trace!("Duplicate datagram {} from {}", seq, from);
+
+            return Some(vec![]);
+        }
+
+        if seq > peer.next && peer.buffered.len() >= MAX_BUFFERED {
+            warn!("Dropping datagram {} from {}: too many out of order", seq, from);
+
+            return None;
+        }
+
+        peer.buffered.insert(seq, payload);
+
+        Some(Self::drain(peer, now))
+    }
+
+    fn drain(peer: &mut Peer, now: Instant) -> Vec<Bytes> {
+        let mut delivered = vec![];
+
+        while let Some(payload) = peer.buffered.remove(&peer.next) {
+            delivered.push(payload);
+
+            peer.next += 1;
+        }
+
+        // The wait for a missing datagram starts over once some are delivered
+        peer.gap_since = match peer.gap_since {
+            _ if peer.buffered.is_empty() => None,
+            Some(since) if delivered.is_empty() => Some(since),
+            _ => Some(now),
+        };
+
+        delivered
+    }
+
+    fn header(kind: u8, session: u64, seq: u64, payload: &[u8]) -> Bytes {
+        let mut datagram = BytesMut::with_capacity(RELIABLE_HEADER_SIZE + payload.len());
+
+        datagram.put_u8(kind);
+        datagram.put_u64_le(session);
+        datagram.put_u64_le(seq);
+        datagram.put_slice(payload);
+
+        datagram.freeze()
+    }
+}
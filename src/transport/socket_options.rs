@@ -0,0 +1,86 @@
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Options of the sockets of a `TcpTransport` or a `UdpTransport`, set before
+/// they bind. Those left to `None` keep the defaults of the system.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SocketOptions {
+    /// TCP_NODELAY: small packets are sent at once rather than coalesced.
+    /// TCP only.
+    pub nodelay: Option<bool>,
+    /// SO_KEEPALIVE, probing the peer once the connection is idle for that
+    /// long. TCP only.
+    pub keepalive: Option<Duration>,
+    /// SO_SNDBUF.
+    pub send_buffer_size: Option<usize>,
+    /// SO_RCVBUF.
+    pub recv_buffer_size: Option<usize>,
+    /// SO_REUSEADDR, on by default for TCP listeners.
+    pub reuse_address: Option<bool>,
+    /// SO_REUSEPORT, for several sockets to share a port. Unix only.
+    pub reuse_port: Option<bool>,
+    /// IP_TTL, or the hop limit over IPv6.
+    pub ttl: Option<u32>,
+    /// The address a client binds before connecting, to pick its interface
+    /// or its port. The one the system reaches the server from by default.
+    pub bind_addr: Option<SocketAddr>,
+}
+
+impl SocketOptions {
+    /// A socket of `kind` for the family of `addr`, with the options set.
+    pub(super) fn socket(&self, addr: &SocketAddr, kind: Type, protocol: Protocol) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(*addr), kind, Some(protocol))?;
+
+        self.apply(&SockRef::from(&socket), addr, protocol)?;
+
+        socket.set_nonblocking(true)?;
+
+        Ok(socket)
+    }
+
+    /// Sets the options on `socket`, of the family of `addr`. Also used on
+    /// the streams accepted by a listener, which do not inherit all of them
+    /// on every system.
+    pub(super) fn apply(&self, socket: &SockRef, addr: &SocketAddr, protocol: Protocol) -> io::Result<()> {
+        if protocol == Protocol::TCP {
+            if let Some(nodelay) = self.nodelay {
+                socket.set_tcp_nodelay(nodelay)?;
+            }
+
+            if let Some(time) = self.keepalive {
+                socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+            }
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(reuse) = self.reuse_address {
+            socket.set_reuse_address(reuse)?;
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(reuse) = self.reuse_port {
+                socket.set_reuse_port(reuse)?;
+            }
+        }
+
+        if let Some(ttl) = self.ttl {
+            if addr.is_ipv6() {
+                socket.set_unicast_hops_v6(ttl)?;
+            } else {
+                socket.set_ttl_v4(ttl)?;
+            }
+        }
+
+        Ok(())
+    }
+}
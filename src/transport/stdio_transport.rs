@@ -0,0 +1,239 @@
+use bytes::Bytes;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::process::{Child, Command};
+
+use super::{connection_addr, read_frames, write_frame_async, Incoming, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+// Both ends only know one peer: the other end of the pipes
+const LOCAL_ID: u64 = 0;
+const PEER_ID: u64 = 1;
+
+// Left to a child to exit once its stdin is closed, before it is killed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The program a client spawns and its arguments, parsed from a command line
+/// split on whitespace, as `python3 worker.py --rpc`. Servers serve on their
+/// own stdin and stdout, so any command does for them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StdioCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl FromStr for StdioCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StdioCommand, String> {
+        let mut words = s.split_whitespace().map(String::from);
+
+        Ok(StdioCommand {
+            program: words.next().ok_or_else(|| "Empty command".to_string())?,
+            args: words.collect(),
+        })
+    }
+}
+
+impl fmt::Display for StdioCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Frames over a pair of pipes: the stdin and stdout of a child process
+/// spawned by a client, or those of the current process for a server. Nothing
+/// else may then be printed to stdout, logs going to stderr.
+///
+/// The transport stops once the other end closes its pipe, so that a server
+/// exits with the process that spawned it.
+pub struct StdioTransport {
+    pub command: StdioCommand,
+    child: Arc<Mutex<Option<Child>>>,
+    writer: Arc<tokio::sync::Mutex<Option<Writer>>>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub incoming: Incoming,
+}
+
+impl StdioTransport {
+    /// Exchanges frames with the peer at the other end of `reader` and
+    /// `writer`. Called before listening or connecting, neither standard
+    /// streams nor a child are then used.
+    pub fn attach<R, W>(&mut self, reader: R, writer: W)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        *self.writer.try_lock().unwrap() = Some(Box::new(writer));
+
+        self.incoming.start();
+
+        let incoming = self.incoming.clone();
+        let writer = self.writer.clone();
+        let max_frame_size = self.max_frame_size.clone();
+
+        runtime::spawn(async move {
+            let addr = connection_addr(PEER_ID);
+
+            read_frames(reader, addr, &incoming, &max_frame_size).await;
+
+            debug!("Disconnected {}", addr);
+
+            writer.lock().await.take();
+
+            incoming.stop();
+        });
+    }
+
+    fn is_attached(&self) -> bool {
+        // Only held while a frame is written
+        match self.writer.try_lock() {
+            Ok(writer) => writer.is_some(),
+            Err(_) => true,
+        }
+    }
+
+    fn spawn(&self) -> io::Result<Child> {
+        Command::new(&self.command.program)
+            .args(&self.command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+    }
+}
+
+impl Clone for StdioTransport {
+    fn clone(&self) -> Self {
+        StdioTransport {
+            command: self.command.clone(),
+            child: self.child.clone(),
+            writer: self.writer.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for StdioTransport {
+    type Addr = StdioCommand;
+
+    fn new(command: &StdioCommand) -> StdioTransport {
+        StdioTransport {
+            command: command.clone(),
+            child: Arc::new(Mutex::new(None)),
+            writer: Arc::new(tokio::sync::Mutex::new(None)),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        if !self.is_attached() {
+            self.attach(tokio::io::stdin(), tokio::io::stdout());
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if self.is_attached() {
+            return Ok(());
+        }
+
+        let mut child = self.spawn().map_err(|e| format!("{}: {}", self.command.program, e))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        *self.child.lock().unwrap() = Some(child);
+
+        self.attach(stdout, stdin);
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> StdioCommand {
+        self.command.clone()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        connection_addr(LOCAL_ID)
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        connection_addr(PEER_ID)
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let mut writer = self.writer.lock().await;
+
+        let writer = match writer.as_mut() {
+            Some(writer) if *addr == connection_addr(PEER_ID) => writer,
+            _ => {
+                trace!("Not connected to {}", addr);
+
+                return false;
+            }
+        };
+
+        // Flushed, standard output being line buffered
+        if write_frame_async(writer, &buff).await.is_err() {
+            return false;
+        }
+
+        trace!("Sent {} to {}", buff.len(), addr);
+
+        true
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        *addr == connection_addr(PEER_ID) && self.is_attached()
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        let writer = self.writer.clone();
+
+        let child = self.child.lock().unwrap().take();
+
+        runtime::block_on(async move {
+            // The child sees the end of its stdin
+            writer.lock().await.take();
+
+            if let Some(mut child) = child {
+                if tokio::time::timeout(EXIT_TIMEOUT, child.wait()).await.is_err() {
+                    warn!("Killing {}", self.command);
+
+                    let _ = child.kill().await;
+                }
+            }
+        });
+    }
+}
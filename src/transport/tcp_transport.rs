@@ -1,83 +1,83 @@
+use bytes::Bytes;
+use socket2::{Protocol, SockRef, Type};
 use std::collections::HashMap;
-use std::io::{ErrorKind, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::io;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::task::AbortHandle;
 
-use super::Transport;
+use super::{read_frames, write_frame_async, Incoming, SocketOptions, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+type Writer = Arc<tokio::sync::Mutex<OwnedWriteHalf>>;
 
 pub struct TcpTransport {
     pub addr: SocketAddr,
     pub serv_addr: SocketAddr,
-    pub server: Option<TcpListener>,
-    pub clients: Arc<RwLock<HashMap<String, TcpStream>>>, // socket_addr =>Client
-    pub running: Arc<RwLock<bool>>,
-    pub receiver: Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>>,
-    pub sender: Arc<Mutex<Sender<(Vec<u8>, SocketAddr)>>>,
+    /// The address of the socket, once listening or connected.
+    pub bound: Arc<RwLock<Option<SocketAddr>>>,
+    /// The task accepting peers, once listening.
+    pub server: Arc<Mutex<Option<AbortHandle>>>,
+    pub clients: Arc<RwLock<HashMap<SocketAddr, Writer>>>, // socket_addr =>Client
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub options: SocketOptions,
+    pub incoming: Incoming,
 }
 
-unsafe impl Send for TcpTransport {}
-unsafe impl Sync for TcpTransport {}
-
 impl TcpTransport {
-    fn set_running(&mut self, running: bool) {
-        let mut guard = self.running.write().unwrap();
-
-        *guard = running;
+    /// Sets the options of the sockets, before listening or connecting.
+    pub fn set_options(&mut self, options: SocketOptions) {
+        self.options = options;
     }
 
-    fn get_running(&mut self) -> bool {
-        let guard = self.running.read().unwrap();
+    fn bind_listener(&self) -> io::Result<TcpListener> {
+        let socket = self.options.socket(&self.addr, Type::STREAM, Protocol::TCP)?;
 
-        (*guard).clone()
+        // As tokio does, for a restarted server to bind its port at once
+        if self.options.reuse_address.is_none() {
+            socket.set_reuse_address(true)?;
+        }
+
+        socket.bind(&self.addr.into())?;
+        socket.listen(1024)?;
+
+        TcpListener::from_std(socket.into())
     }
 
-    fn socket_read(&self, addr: SocketAddr, stream: TcpStream) {
-        stream
-            .set_read_timeout(Some(std::time::Duration::from_millis(10)))
-            .unwrap();
+    async fn connect_stream(&self) -> io::Result<TcpStream> {
+        let socket = self.options.socket(&self.addr, Type::STREAM, Protocol::TCP)?;
 
-        let mut stream = stream.try_clone().unwrap();
+        if let Some(bind_addr) = self.options.bind_addr {
+            socket.bind(&bind_addr.into())?;
+        }
 
-        let running = self.running.clone();
-        let sender = self.sender.clone();
+        TcpSocket::from_std_stream(socket.into()).connect(self.addr).await
+    }
 
-        thread::spawn(move || {
-            while running.read().unwrap().clone() {
-                let mut buff = vec![];
+    fn socket_read(&self, addr: SocketAddr, stream: TcpStream) {
+        let (reader, writer) = stream.into_split();
 
-                // is_running = running.read().unwrap().clone();
+        self.clients
+            .write()
+            .unwrap()
+            .insert(addr, Arc::new(tokio::sync::Mutex::new(writer)));
 
-                match stream.read_to_end(&mut buff) {
-                    Ok(_) => {
-                        if !*running.read().unwrap() {
-                            break;
-                        }
+        let clients = self.clients.clone();
+        let incoming = self.incoming.clone();
+        let max_frame_size = self.max_frame_size.clone();
 
-                        if buff.len() > 0 {
-                            sender.lock().unwrap().send((buff, addr)).unwrap();
-                        } else {
-                            break;
-                        }
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        if !*running.read().unwrap() {
-                            break;
-                        }
+        runtime::spawn(async move {
+            read_frames(reader, addr, &incoming, &max_frame_size).await;
 
-                        if buff.len() > 0 {
-                            sender.lock().unwrap().send((buff, addr)).unwrap();
-                        }
-                    }
-                    Err(e) => {
-                        if e.kind() != ErrorKind::Other {
-                            error!("Error read {}", e);
-                        }
+            // The peer is gone, or we are closing: dropping both halves
+            // closes the socket
+            if clients.write().unwrap().remove(&addr).is_some() {
+                debug!("Disconnected {}", addr);
 
-                        break;
-                    }
-                }
+                incoming.disconnected(addr);
             }
         });
     }
@@ -85,64 +85,55 @@ impl TcpTransport {
 
 impl Clone for TcpTransport {
     fn clone(&self) -> Self {
-        let server = match self.server.as_ref() {
-            Some(s) => Some(s.try_clone().unwrap()),
-            None => None,
-        };
-
-        // let clients = HashMap::new();
-
-        // for (addr, client) in *self.clients.read().unwrap() {
-        //     clients.insert(addr.clone(), client.try_clone().unwrap());
-        // }
-
         TcpTransport {
-            server,
+            addr: self.addr,
+            serv_addr: self.serv_addr,
+            bound: self.bound.clone(),
+            server: self.server.clone(),
             clients: self.clients.clone(),
-            addr: self.addr.clone(),
-            serv_addr: self.addr.clone(),
-            running: self.running.clone(),
-            receiver: self.receiver.clone(),
-            sender: self.sender.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            options: self.options.clone(),
+            incoming: self.incoming.clone(),
         }
     }
 }
 
 impl Transport for TcpTransport {
-    fn new(addr: &SocketAddr) -> TcpTransport {
-        let (sender, receiver) = channel();
+    type Addr = SocketAddr;
 
+    fn new(addr: &SocketAddr) -> TcpTransport {
         TcpTransport {
-            addr: addr.clone(),
-            serv_addr: addr.clone(),
-            server: None,
+            addr: *addr,
+            serv_addr: *addr,
+            bound: Arc::new(RwLock::new(None)),
+            server: Arc::new(Mutex::new(None)),
             clients: Arc::new(RwLock::new(HashMap::new())),
-            running: Arc::new(RwLock::new(false)),
-            receiver: Arc::new(Mutex::new(receiver)),
-            sender: Arc::new(Mutex::new(sender)),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            options: SocketOptions::default(),
+            incoming: Incoming::new(),
         }
     }
 
-    fn listen(&mut self) {
-        let socket = TcpListener::bind(self.addr).unwrap();
+    async fn listen(&mut self) {
+        let socket = self.bind_listener().unwrap();
 
-        let clients = self.clients.clone();
+        *self.bound.write().unwrap() = socket.local_addr().ok();
 
-        self.server = Some(socket.try_clone().unwrap());
+        self.incoming.start();
 
-        self.set_running(true);
+        let local_self = self.clone();
 
-        let mut local_self = self.clone();
-
-        thread::spawn(move || {
-            while local_self.get_running() {
-                match socket.accept() {
+        let accept = runtime::spawn(async move {
+            loop {
+                match socket.accept().await {
                     Ok((stream, addr)) => {
-                        debug!("Accept {:?}", addr.clone());
+                        debug!("Accept {:?}", addr);
 
-                        local_self.socket_read(addr, stream.try_clone().unwrap());
+                        if let Err(e) = local_self.options.apply(&SockRef::from(&stream), &addr, Protocol::TCP) {
+                            warn!("Cannot set the options of {}: {}", addr, e);
+                        }
 
-                        clients.write().unwrap().insert(addr.to_string(), stream);
+                        local_self.socket_read(addr, stream);
                     }
                     Err(e) => {
                         error!("Error accept {}", e);
@@ -152,93 +143,80 @@ impl Transport for TcpTransport {
                 }
             }
         });
-    }
 
-    fn connect(&mut self) -> Result<(), String> {
-        let socket = TcpStream::connect(self.addr);
-
-        if socket.is_err() {
-            return Err(socket.unwrap_err().to_string());
-        }
-
-        let socket = socket.unwrap();
+        *self.server.lock().unwrap() = Some(accept.abort_handle());
+    }
 
-        self.clients
-            .write()
-            .unwrap()
-            .insert(self.addr.to_string(), socket.try_clone().unwrap());
+    async fn connect(&mut self) -> Result<(), String> {
+        let socket = self.connect_stream().await.map_err(|e| e.to_string())?;
 
-        let addr = self.addr.clone();
+        *self.bound.write().unwrap() = socket.local_addr().ok();
 
-        self.set_running(true);
+        self.incoming.start();
 
-        self.socket_read(addr, socket);
+        self.socket_read(self.addr, socket);
 
         Ok(())
     }
 
     fn get_addr(&self) -> SocketAddr {
-        // TODO: return the address from the socket instead
-        // self.socket.as_ref().map(|socket | socket.local_addr().unwrap()).unwrap()
-
-        self.addr
+        self.bound.read().unwrap().unwrap_or(self.addr)
+    }
 
-        // if self.server.is_some() {
-        //     self.addr
-        // } else {
-        //     let mut c = self.addr;
+    fn local_addr(&self) -> SocketAddr {
+        self.bound.read().unwrap().unwrap_or(self.addr)
+    }
 
-        //     for (_, client) in self.clients.read().unwrap().iter() {
-        //         c = client.local_addr().unwrap();
-        //     }
+    fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
 
-        //     c
-        // }
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
     }
 
-    fn send(&mut self, addr: &SocketAddr, buff: Vec<u8>) -> bool {
-        let mut clients = self.clients.write().unwrap();
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let writer = self.clients.read().unwrap().get(addr).cloned();
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                trace!("Not connected to {}", addr);
 
-        if let Some(s) = clients.get_mut(&addr.to_string()) {
-            if let Err(_) = s.write_all(buff.as_slice()) {
                 return false;
             }
+        };
 
-            trace!("Sent {} to {}", buff.len(), addr);
+        if write_frame_async(&mut *writer.lock().await, &buff).await.is_err() {
+            return false;
         }
 
+        trace!("Sent {} to {}", buff.len(), addr);
+
         true
     }
 
-    // fn recv(&mut self) -> Result<(Vec<u8>, SocketAddr), Error> {
-    //     if !*self.running.read().unwrap() {
-    //         Err(std::io::Error::new(
-    //             std::io::ErrorKind::Interrupted,
-    //             "Closed",
-    //         ))
-    //     } else if self.req_buffer.read().unwrap().len() == 0 {
-    //         Err(std::io::Error::new(
-    //             std::io::ErrorKind::WouldBlock,
-    //             "WouldBlock",
-    //         ))
-    //     } else {
-    //         Ok(self.req_buffer.write().unwrap().remove(0))
-    //     }
-    // }
-    fn get_recv(&mut self) -> Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>> {
-        self.receiver.clone()
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.clients.read().unwrap().contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    async fn dropped(&self) -> Option<SocketAddr> {
+        self.incoming.dropped().await
     }
 
     fn is_running(&mut self) -> bool {
-        self.running.read().unwrap().clone()
+        self.incoming.is_running()
     }
 
     fn close(&mut self) {
-        self.set_running(false);
+        self.incoming.stop();
 
-        if self.server.is_some() {
-            drop(self.server.take());
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
         }
-        // drop(self.clients);
     }
 }
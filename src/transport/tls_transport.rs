@@ -0,0 +1,411 @@
+use bytes::Bytes;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig, SupportedProtocolVersion};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+use tokio::time::timeout;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+use super::{read_frames, write_frame_async, Incoming, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+// Longest a peer can take to complete its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether a server asks its clients for a certificate.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClientAuth {
+    #[default]
+    Off,
+    /// Clients without a certificate are let in, the others are verified.
+    Optional,
+    Required,
+}
+
+/// Certificates and keys of a `TlsTransport`, as PEM files.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    /// Certificate chain presented to the peer, required to listen.
+    pub cert_file: Option<PathBuf>,
+    /// Private key of `cert_file`.
+    pub key_file: Option<PathBuf>,
+    /// Certificates trusted to sign the peer's, required to connect and to
+    /// verify clients.
+    pub ca_file: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+    /// The name the server certificate must be valid for, its IP address by
+    /// default.
+    pub server_name: Option<String>,
+}
+
+impl TlsConfig {
+    /// The rustls configurations to listen and to connect with, limited to
+    /// `versions`. Either is left out when its files are.
+    pub(super) fn rustls_configs(
+        &self,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<(Option<ServerConfig>, Option<ClientConfig>), String> {
+        let provider = Arc::new(ring::default_provider());
+
+        let identity = match (&self.cert_file, &self.key_file) {
+            (Some(cert), Some(key)) => Some((load_certs(cert)?, load_key(key)?)),
+            (None, None) => None,
+            _ => return Err("A certificate goes with its key".to_string()),
+        };
+
+        let roots = match &self.ca_file {
+            Some(ca) => Some(Arc::new(load_roots(ca)?)),
+            None => None,
+        };
+
+        let mut server_config = None;
+
+        if let Some((certs, key)) = &identity {
+            let builder = ServerConfig::builder_with_provider(provider.clone())
+                .with_protocol_versions(versions)
+                .map_err(|e| e.to_string())?;
+
+            let builder = match (self.client_auth, &roots) {
+                (ClientAuth::Off, _) => builder.with_no_client_auth(),
+                (_, None) => return Err("Verifying clients needs a CA".to_string()),
+                (client_auth, Some(roots)) => {
+                    let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone());
+
+                    let verifier = if client_auth == ClientAuth::Optional {
+                        verifier.allow_unauthenticated()
+                    } else {
+                        verifier
+                    };
+
+                    builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+                }
+            };
+
+            server_config = Some(builder.with_single_cert(certs.clone(), key.clone_key()).map_err(|e| e.to_string())?);
+        }
+
+        let mut client_config = None;
+
+        if let Some(roots) = roots {
+            let builder = ClientConfig::builder_with_provider(provider)
+                .with_protocol_versions(versions)
+                .map_err(|e| e.to_string())?
+                .with_root_certificates(roots);
+
+            client_config = Some(match identity {
+                Some((certs, key)) => builder.with_client_auth_cert(certs, key).map_err(|e| e.to_string())?,
+                None => builder.with_no_client_auth(),
+            });
+        }
+
+        Ok((server_config, client_config))
+    }
+}
+
+/// The certificate a TLS peer authenticated with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerIdentity {
+    /// DER certificates, the peer's own first.
+    pub certificates: Vec<Vec<u8>>,
+    /// DNS names the peer's certificate is valid for.
+    pub dns_names: Vec<String>,
+}
+
+impl PeerIdentity {
+    pub(super) fn new(chain: &[CertificateDer]) -> Option<PeerIdentity> {
+        let cert = webpki::EndEntityCert::try_from(chain.first()?).ok()?;
+
+        Some(PeerIdentity {
+            certificates: chain.iter().map(|cert| cert.to_vec()).collect(),
+            dns_names: cert.valid_dns_names().map(String::from).collect(),
+        })
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{:?}: {}", path, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("{:?}: No certificate", path));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{:?}: {}", path, e))?
+        .ok_or_else(|| format!("{:?}: No private key", path))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| format!("{:?}: {}", path, e))?;
+    }
+
+    Ok(roots)
+}
+
+type Writer = Arc<tokio::sync::Mutex<WriteHalf<TlsStream<TcpStream>>>>;
+
+/// The writing half of a TLS session, and who the peer proved to be.
+struct Peer {
+    writer: Writer,
+    identity: Option<PeerIdentity>,
+}
+
+/// Frames over TLS over TCP, with rustls.
+pub struct TlsTransport {
+    pub addr: SocketAddr,
+    /// The task accepting peers, once listening.
+    pub server: Arc<Mutex<Option<AbortHandle>>>,
+    server_config: Option<Arc<ServerConfig>>,
+    client_config: Option<Arc<ClientConfig>>,
+    server_name: Option<String>,
+    clients: Arc<RwLock<HashMap<SocketAddr, Peer>>>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub incoming: Incoming,
+}
+
+impl TlsTransport {
+    /// Loads the certificates and keys of `config`. Listening needs a
+    /// certificate and its key, connecting needs a CA.
+    pub fn set_config(&mut self, config: &TlsConfig) -> Result<(), String> {
+        let (server_config, client_config) = config.rustls_configs(rustls::DEFAULT_VERSIONS)?;
+
+        self.server_config = server_config.map(Arc::new);
+        self.client_config = client_config.map(Arc::new);
+        self.server_name = config.server_name.clone();
+
+        Ok(())
+    }
+
+    /// Whether a certificate to listen with is configured.
+    pub fn can_listen(&self) -> bool {
+        self.server_config.is_some()
+    }
+
+    fn add_peer(&self, addr: SocketAddr, stream: TlsStream<TcpStream>) {
+        let identity = stream.get_ref().1.peer_certificates().and_then(PeerIdentity::new);
+
+        let (reader, writer) = tokio::io::split(stream);
+
+        self.clients.write().unwrap().insert(
+            addr,
+            Peer {
+                writer: Arc::new(tokio::sync::Mutex::new(writer)),
+                identity,
+            },
+        );
+
+        let clients = self.clients.clone();
+        let incoming = self.incoming.clone();
+        let max_frame_size = self.max_frame_size.clone();
+
+        runtime::spawn(async move {
+            // Closed by the peer with a TLS alert as well, told with one when
+            // it breaks the protocol
+            read_frames(reader, addr, &incoming, &max_frame_size).await;
+
+            // The peer is gone, or we are closing
+            if clients.write().unwrap().remove(&addr).is_some() {
+                debug!("Disconnected {}", addr);
+
+                incoming.disconnected(addr);
+            }
+        });
+    }
+}
+
+impl Clone for TlsTransport {
+    fn clone(&self) -> Self {
+        TlsTransport {
+            addr: self.addr,
+            server: self.server.clone(),
+            server_config: self.server_config.clone(),
+            client_config: self.client_config.clone(),
+            server_name: self.server_name.clone(),
+            clients: self.clients.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for TlsTransport {
+    type Addr = SocketAddr;
+
+    fn new(addr: &SocketAddr) -> TlsTransport {
+        TlsTransport {
+            addr: *addr,
+            server: Arc::new(Mutex::new(None)),
+            server_config: None,
+            client_config: None,
+            server_name: None,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        let config = self.server_config.clone().expect("TlsTransport: No certificate to listen with");
+
+        let acceptor = TlsAcceptor::from(config);
+
+        let socket = TcpListener::bind(self.addr).await.unwrap();
+
+        self.incoming.start();
+
+        let local_self = self.clone();
+
+        let accept = runtime::spawn(async move {
+            loop {
+                match socket.accept().await {
+                    Ok((stream, addr)) => {
+                        debug!("Accept {:?}", addr);
+
+                        let local_self = local_self.clone();
+                        let acceptor = acceptor.clone();
+
+                        // Not to hold back the other clients
+                        runtime::spawn(async move {
+                            match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => local_self.add_peer(addr, TlsStream::from(stream)),
+                                Ok(Err(e)) => warn!("Handshake with {} failed: {}", addr, e),
+                                Err(_) => warn!("Handshake with {} timed out", addr),
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        error!("Error accept {}", e);
+
+                        break;
+                    }
+                }
+            }
+        });
+
+        *self.server.lock().unwrap() = Some(accept.abort_handle());
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        let config = match self.client_config.clone() {
+            Some(config) => config,
+            None => return Err("TlsTransport: No CA to verify the server with".to_string()),
+        };
+
+        let server_name = match &self.server_name {
+            Some(name) => ServerName::try_from(name.clone()).map_err(|e| e.to_string())?,
+            None => ServerName::IpAddress(self.addr.ip().into()),
+        };
+
+        let stream = TcpStream::connect(self.addr).await.map_err(|e| e.to_string())?;
+
+        let stream = timeout(HANDSHAKE_TIMEOUT, TlsConnector::from(config).connect(server_name, stream))
+            .await
+            .map_err(|_| "Handshake timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+
+        self.incoming.start();
+
+        self.add_peer(self.addr, TlsStream::from(stream));
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn peer_identity(&self, addr: &SocketAddr) -> Option<PeerIdentity> {
+        self.clients.read().unwrap().get(addr).and_then(|peer| peer.identity.clone())
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let writer = self.clients.read().unwrap().get(addr).map(|peer| peer.writer.clone());
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                trace!("Not connected to {}", addr);
+
+                return false;
+            }
+        };
+
+        // Flushed for rustls to write the records
+        if write_frame_async(&mut *writer.lock().await, &buff).await.is_err() {
+            return false;
+        }
+
+        trace!("Sent {} to {}", buff.len(), addr);
+
+        true
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.clients.read().unwrap().contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    async fn dropped(&self) -> Option<SocketAddr> {
+        self.incoming.dropped().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
+        }
+
+        let peers: Vec<Peer> = self.clients.write().unwrap().drain().map(|(_, peer)| peer).collect();
+
+        // Sends the close notifications
+        runtime::block_on(async move {
+            for peer in peers {
+                let _ = peer.writer.lock().await.shutdown().await;
+            }
+        });
+    }
+}
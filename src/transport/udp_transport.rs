@@ -1,26 +1,67 @@
-use std::net::{SocketAddr, UdpSocket};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use bytes::{Bytes, BytesMut};
+use socket2::{Protocol, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
-
-use super::Transport;
-
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+
+use super::fragment::{fragment, Reassembler, DEFAULT_FRAGMENT_SIZE, DEFAULT_REASSEMBLY_TIMEOUT};
+use super::reliable::{Reliability, ReliabilityConfig};
+use super::{Incoming, SocketOptions, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+// Largest possible UDP datagram
+const DATAGRAM_SIZE: usize = 64 * 1024;
+
+/// Packets are split into fragments of `fragment_size` bytes, reassembled on
+/// receipt. Incomplete messages are dropped after `reassembly_timeout`, and
+/// messages larger than the max frame size as soon as they grow past it.
+///
+/// When `reliable`, every fragment is acknowledged and retransmitted until
+/// it is, and delivered once and in order by the receiving transport.
 pub struct UdpTransport {
     pub addr: SocketAddr,
-    pub socket: Option<UdpSocket>,
-    pub running: Arc<RwLock<bool>>,
-    pub receiver: Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>>,
-    pub sender: Sender<(Vec<u8>, SocketAddr)>,
+    pub socket: Option<Arc<UdpSocket>>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub fragment_size: usize,
+    pub reassembly_timeout: Duration,
+    pub next_msg_id: Arc<AtomicU64>,
+    pub reliable: bool,
+    pub reliability: Arc<Mutex<Reliability>>,
+    // Tells the reading task that a datagram waits for its acknowledgement
+    retransmit: Arc<Notify>,
+    pub options: SocketOptions,
+    pub incoming: Incoming,
 }
 
-unsafe impl Send for UdpTransport {}
-unsafe impl Sync for UdpTransport {}
-
 impl UdpTransport {
-    fn set_running(&mut self, running: bool) {
-        let mut guard = self.running.write().unwrap();
+    #[allow(dead_code)]
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.fragment_size = size;
+    }
 
-        *guard = running;
+    #[allow(dead_code)]
+    pub fn set_reassembly_timeout(&mut self, timeout: Duration) {
+        self.reassembly_timeout = timeout;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_reliable(&mut self, reliable: bool) {
+        self.reliable = reliable;
+    }
+
+    /// Sets the options of the socket, before listening or connecting.
+    pub fn set_options(&mut self, options: SocketOptions) {
+        self.options = options;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_reliability_config(&mut self, config: ReliabilityConfig) {
+        self.reliability.lock().unwrap().set_config(config);
     }
 
     // fn get_running(&mut self) -> bool {
@@ -29,127 +70,213 @@ impl UdpTransport {
     //     (*guard).clone()
     // }
 
-    fn socket_read(&self, addr: SocketAddr, stream: UdpSocket) {
-        stream
-            .set_read_timeout(Some(std::time::Duration::from_millis(10)))
-            .unwrap();
+    async fn bind(&mut self, addr: SocketAddr) -> io::Result<()> {
+        let socket = self.options.socket(&addr, Type::DGRAM, Protocol::UDP)?;
 
-        let stream = stream.try_clone().unwrap();
+        socket.bind(&addr.into())?;
 
-        let running = self.running.clone();
-        let sender = self.sender.clone();
+        let socket = Arc::new(UdpSocket::from_std(socket.into())?);
 
-        thread::spawn(move || {
-            let mut is_running = true;
+        self.incoming.start();
 
-            while is_running {
-                is_running = *running.read().unwrap();
+        self.socket_read(socket.clone());
 
-                let mut buff = [0; 100 * 1024];
+        self.socket = Some(socket);
 
-                match stream.recv_from(&mut buff) {
-                    Ok((amount, from)) => {
-                        trace!("Read {} from {}", amount, from);
+        Ok(())
+    }
 
-                        if amount == 0 && is_running {
-                            trace!("Forced read 0 and not running");
+    /// The address of the socket once bound, with the port picked by the
+    /// system for port 0.
+    fn bound_addr(&self) -> Option<SocketAddr> {
+        self.socket.as_ref().and_then(|socket| socket.local_addr().ok())
+    }
 
-                            break;
-                        } else {
-                            let res = buff[..amount].to_vec();
+    fn socket_read(&self, socket: Arc<UdpSocket>) {
+        let incoming = self.incoming.clone();
+        let max_frame_size = self.max_frame_size.clone();
+        let reassembly_timeout = self.reassembly_timeout;
+        let reliability = self.reliability.clone();
+        let retransmit = self.retransmit.clone();
+
+        runtime::spawn(async move {
+            let mut buff = BytesMut::new();
+            let mut reassembler = Reassembler::new(*max_frame_size.read().unwrap(), reassembly_timeout);
+
+            loop {
+                // Nothing wakes the task up but datagrams, unless some are to
+                // be resent, skipped or dropped
+                let deadline = reliability.lock().unwrap().next_deadline();
+                let deadline = deadline.into_iter().chain(reassembler.next_deadline()).min();
+
+                let timer = async move {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
 
-                            if buff.len() > 0 {
-                                sender.send((res, addr)).unwrap();
+                buff.resize(DATAGRAM_SIZE, 0);
+
+                tokio::select! {
+                    _ = incoming.stopped() => break,
+                    _ = retransmit.notified() => (),
+                    _ = timer => {
+                        let now = Instant::now();
+
+                        reassembler.expire(now);
+
+                        let (resend, delivered) = reliability.lock().unwrap().poll(now);
+
+                        for (to, datagram) in resend {
+                            trace!("Resend {} to {}", datagram.len(), to);
+
+                            let _ = socket.send_to(&datagram, to).await;
+                        }
+
+                        for (from, payload) in delivered {
+                            if let Some(res) = reassembler.push(from, payload) {
+                                incoming.push(res, from).await;
                             }
                         }
                     }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        if !*running.read().unwrap() {
-                            drop(sender);
+                    read = socket.recv_from(&mut buff) => match read {
+                        Ok((amount, from)) => {
+                            trace!("Read {} from {}", amount, from);
 
-                            break;
-                        }
+                            reassembler.set_max_message_size(*max_frame_size.read().unwrap());
 
-                        continue;
-                    }
-                    Err(e) => {
-                        error!("Error: {}", e);
+                            let datagram = buff.split_to(amount).freeze();
 
-                        drop(sender);
+                            let (ack, delivered) = reliability.lock().unwrap().receive(from, datagram, Instant::now());
 
-                        // drop(*sender.lock().unwrap());
+                            if let Some(ack) = ack {
+                                let _ = socket.send_to(&ack, from).await;
+                            }
 
-                        break;
-                    }
-                };
+                            for payload in delivered {
+                                if let Some(res) = reassembler.push(from, payload) {
+                                    incoming.push(res, from).await;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!("Error: {}", e);
+
+                            break;
+                        }
+                    },
+                }
             }
         });
     }
 }
 
+/// The address the system sends from to reach `addr`, found by connecting a
+/// probe socket: the local address of a socket bound to any interface is
+/// unspecified, so it cannot tell peers where it is. Any interface of the
+/// family of `addr` when there is no route to it.
+fn source_ip(addr: &SocketAddr) -> IpAddr {
+    let any = if addr.is_ipv6() {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    };
+
+    std::net::UdpSocket::bind(SocketAddr::new(any, 0))
+        .and_then(|probe| probe.connect(addr).and_then(|_| probe.local_addr()))
+        .map(|local| local.ip())
+        .unwrap_or(any)
+}
+
 impl Clone for UdpTransport {
     fn clone(&self) -> Self {
-        let socket = match self.socket.as_ref() {
-            Some(s) => Some(s.try_clone().unwrap()),
-            None => None,
-        };
-
         UdpTransport {
-            socket,
-            addr: self.addr.clone(),
-            running: self.running.clone(),
-            receiver: self.receiver.clone(),
-            sender: self.sender.clone(),
+            socket: self.socket.clone(),
+            addr: self.addr,
+            max_frame_size: self.max_frame_size.clone(),
+            fragment_size: self.fragment_size,
+            reassembly_timeout: self.reassembly_timeout,
+            next_msg_id: self.next_msg_id.clone(),
+            reliable: self.reliable,
+            reliability: self.reliability.clone(),
+            retransmit: self.retransmit.clone(),
+            options: self.options.clone(),
+            incoming: self.incoming.clone(),
         }
     }
 }
 
 impl Transport for UdpTransport {
-    fn new(addr: &SocketAddr) -> UdpTransport {
-        let (sender, receiver) = channel();
+    type Addr = SocketAddr;
 
+    fn new(addr: &SocketAddr) -> UdpTransport {
         UdpTransport {
-            addr: addr.clone(),
+            addr: *addr,
             socket: None,
-            running: Arc::new(RwLock::new(false)),
-            receiver: Arc::new(Mutex::new(receiver)),
-            sender,
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            reassembly_timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            next_msg_id: Arc::new(AtomicU64::new(0)),
+            reliable: false,
+            reliability: Arc::new(Mutex::new(Reliability::new(ReliabilityConfig::default()))),
+            retransmit: Arc::new(Notify::new()),
+            options: SocketOptions::default(),
+            incoming: Incoming::new(),
         }
     }
 
-    fn listen(&mut self) {
-        let socket = UdpSocket::bind(self.addr).unwrap();
-
-        self.socket_read(self.addr, socket.try_clone().unwrap());
-
-        self.socket = Some(socket);
-
-        self.set_running(true);
+    async fn listen(&mut self) {
+        self.bind(self.addr).await.unwrap();
     }
 
-    fn connect(&mut self) -> Result<(), String> {
-        let client_addr = self.addr.clone();
+    async fn connect(&mut self) -> Result<(), String> {
+        let bind_addr = match self.options.bind_addr {
+            Some(bind_addr) => bind_addr,
+            None => SocketAddr::new(source_ip(&self.addr), 0),
+        };
 
-        self.addr = "127.0.0.1:0".parse().unwrap();
+        self.bind(bind_addr).await.map_err(|e| e.to_string())
+    }
 
-        self.listen();
+    fn get_addr(&self) -> SocketAddr {
+        self.bound_addr().unwrap_or(self.addr)
+    }
 
-        self.addr = client_addr;
+    fn local_addr(&self) -> SocketAddr {
+        self.bound_addr().unwrap_or(self.addr)
+    }
 
-        Ok(())
+    fn peer_addr(&self) -> SocketAddr {
+        self.addr
     }
 
-    fn get_addr(&self) -> SocketAddr {
-        // TODO: return the address from the socket instead
-        // self.socket.as_ref().map(|socket | socket.local_addr().unwrap()).unwrap()
+    fn resolve(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        Some(*addr)
+    }
 
-        self.addr
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
     }
 
-    fn send(&mut self, addr: &SocketAddr, buff: Vec<u8>) -> bool {
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
         if let Some(s) = self.socket.as_ref() {
-            if let Err(_) = s.send_to(buff.as_slice(), addr) {
-                return false;
+            let msg_id = self.next_msg_id.fetch_add(1, Ordering::Relaxed);
+
+            for payload in fragment(msg_id, &buff, self.fragment_size) {
+                let datagram = if self.reliable {
+                    self.reliability.lock().unwrap().wrap(*addr, &payload, Instant::now())
+                } else {
+                    Reliability::raw(&payload)
+                };
+
+                if s.send_to(&datagram, addr).await.is_err() {
+                    return false;
+                }
+            }
+
+            if self.reliable {
+                self.retransmit.notify_one();
             }
         }
 
@@ -158,40 +285,17 @@ impl Transport for UdpTransport {
         true
     }
 
-    fn get_recv(&mut self) -> Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>> {
-        self.receiver.clone()
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
     }
 
-    // fn recv(&mut self) -> Result<(Vec<u8>, SocketAddr), Error> {
-    //     let mut buff = [0; 100 * 1024];
-
-    //     if !self.get_running() {
-    //         return Err(Error::new(ErrorKind::Other, "Not running"));
-    //     }
-
-    //     match self.socket.as_ref().unwrap().recv_from(&mut buff) {
-    //         Ok((amount, from)) => {
-    //             trace!("Read {} from {}", amount, from);
-
-    //             if amount == 0 && !self.get_running() {
-    //                 trace!("Forced read 0 and not running");
-
-    //                 Err(Error::new(ErrorKind::Other, "Read 0"))
-    //             } else {
-    //                 Ok((buff[..amount].to_vec(), from))
-    //             }
-    //         }
-    //         Err(e) => Err(e),
-    //     }
-    // }
-
     fn is_running(&mut self) -> bool {
-        self.running.read().unwrap().clone()
+        self.incoming.is_running()
     }
 
     fn close(&mut self) {
-        self.set_running(false);
+        self.incoming.stop();
 
-        drop(self.socket.take().unwrap());
+        self.socket.take();
     }
 }
@@ -0,0 +1,304 @@
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::UnixDatagram;
+
+use super::fragment::{fragment, Reassembler, DEFAULT_REASSEMBLY_TIMEOUT};
+use super::unix_transport::remove_stale_socket;
+use super::{connection_addr, Incoming, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+/// Payload per datagram. Local datagrams have no MTU, only the socket buffer
+/// size to fit in.
+pub const UNIX_FRAGMENT_SIZE: usize = 32 * 1024;
+
+const DATAGRAM_SIZE: usize = 64 * 1024;
+
+// Paths of the sockets bound by clients in this process
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(0);
+
+// Peers known before those whose socket is gone are forgotten
+const MAX_PEERS: usize = 1024;
+
+/// The paths of the peers, and the addresses they are known by.
+///
+/// Peers are forgotten once their socket is found gone, when sending to them
+/// or when too many are known.
+#[derive(Default)]
+pub struct UnixPeers {
+    addrs: HashMap<PathBuf, SocketAddr>,
+    paths: HashMap<SocketAddr, PathBuf>,
+    next_id: u64,
+    prune_at: usize,
+}
+
+impl UnixPeers {
+    pub fn addr_of(&mut self, path: &Path) -> SocketAddr {
+        if let Some(addr) = self.addrs.get(path) {
+            return *addr;
+        }
+
+        if self.paths.len() >= self.prune_at.max(MAX_PEERS) {
+            self.prune();
+        }
+
+        // Ours is 0
+        self.next_id += 1;
+
+        let addr = connection_addr(self.next_id);
+
+        self.addrs.insert(path.to_path_buf(), addr);
+        self.paths.insert(addr, path.to_path_buf());
+
+        addr
+    }
+
+    pub fn path_of(&self, addr: &SocketAddr) -> Option<PathBuf> {
+        self.paths.get(addr).cloned()
+    }
+
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        if let Some(path) = self.paths.remove(addr) {
+            self.addrs.remove(&path);
+        }
+    }
+
+    fn prune(&mut self) {
+        self.paths.retain(|_, path| path.exists());
+        self.addrs.retain(|path, _| path.exists());
+
+        // Pruned again once as many are known, if all of them are alive
+        self.prune_at = self.paths.len() * 2;
+    }
+}
+
+/// Datagrams over Unix domain sockets, addressed by filesystem paths. Like
+/// `UdpTransport`, packets are split into fragments of `fragment_size` bytes
+/// and reassembled on receipt, and one socket both serves and sends requests.
+///
+/// Answers go to the path the request came from, so clients bind a socket of
+/// their own under the temporary directory.
+pub struct UnixDatagramTransport {
+    pub path: PathBuf,
+    pub bound: Option<PathBuf>,
+    pub socket: Option<Arc<UnixDatagram>>,
+    pub peers: Arc<RwLock<UnixPeers>>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub fragment_size: usize,
+    pub reassembly_timeout: Duration,
+    pub next_msg_id: Arc<AtomicU64>,
+    pub incoming: Incoming,
+}
+
+impl UnixDatagramTransport {
+    #[allow(dead_code)]
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.fragment_size = size;
+    }
+
+    #[allow(dead_code)]
+    pub fn set_reassembly_timeout(&mut self, timeout: Duration) {
+        self.reassembly_timeout = timeout;
+    }
+
+    fn bind(&mut self, path: PathBuf) -> Result<(), String> {
+        remove_stale_socket(&path, |path| std::os::unix::net::UnixDatagram::unbound()?.connect(path));
+
+        let socket = Arc::new(UnixDatagram::bind(&path).map_err(|e| format!("{:?}: {}", path, e))?);
+
+        self.incoming.start();
+
+        self.socket_read(socket.clone());
+
+        self.socket = Some(socket);
+        self.bound = Some(path);
+
+        Ok(())
+    }
+
+    fn socket_read(&self, socket: Arc<UnixDatagram>) {
+        let incoming = self.incoming.clone();
+        let peers = self.peers.clone();
+        let max_frame_size = self.max_frame_size.clone();
+        let reassembly_timeout = self.reassembly_timeout;
+
+        runtime::spawn(async move {
+            let mut buff = BytesMut::new();
+            let mut reassembler = Reassembler::new(*max_frame_size.read().unwrap(), reassembly_timeout);
+
+            loop {
+                let deadline = reassembler.next_deadline();
+
+                let timer = async move {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                buff.resize(DATAGRAM_SIZE, 0);
+
+                tokio::select! {
+                    _ = incoming.stopped() => break,
+                    _ = timer => reassembler.expire(Instant::now()),
+                    read = socket.recv_from(&mut buff) => match read {
+                        Ok((amount, from)) => {
+                            // Nothing can be answered to an unbound socket
+                            let from = match from.as_pathname() {
+                                Some(path) => peers.write().unwrap().addr_of(path),
+                                None => {
+                                    debug!("Dropped {} from an unbound socket", amount);
+
+                                    continue;
+                                }
+                            };
+
+                            trace!("Read {} from {}", amount, from);
+
+                            reassembler.set_max_message_size(*max_frame_size.read().unwrap());
+
+                            if let Some(res) = reassembler.push(from, buff.split_to(amount).freeze()) {
+                                incoming.push(res, from).await;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Error: {}", e);
+
+                            break;
+                        }
+                    },
+                }
+            }
+        });
+    }
+}
+
+impl Clone for UnixDatagramTransport {
+    fn clone(&self) -> Self {
+        UnixDatagramTransport {
+            path: self.path.clone(),
+            bound: self.bound.clone(),
+            socket: self.socket.clone(),
+            peers: self.peers.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            fragment_size: self.fragment_size,
+            reassembly_timeout: self.reassembly_timeout,
+            next_msg_id: self.next_msg_id.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for UnixDatagramTransport {
+    type Addr = PathBuf;
+
+    fn new(path: &PathBuf) -> UnixDatagramTransport {
+        UnixDatagramTransport {
+            path: path.clone(),
+            bound: None,
+            socket: None,
+            peers: Arc::new(RwLock::new(UnixPeers::default())),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            fragment_size: UNIX_FRAGMENT_SIZE,
+            reassembly_timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            next_msg_id: Arc::new(AtomicU64::new(0)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        let path = self.path.clone();
+
+        self.bind(path).unwrap();
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Err(format!("No socket at {:?}", self.path));
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "rsrpc-{}-{}.sock",
+            std::process::id(),
+            NEXT_CLIENT.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        self.bind(path)
+    }
+
+    fn get_addr(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        connection_addr(0)
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.peers.write().unwrap().addr_of(&self.path)
+    }
+
+    fn resolve(&self, path: &PathBuf) -> Option<SocketAddr> {
+        Some(self.peers.write().unwrap().addr_of(path))
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let path = self.peers.read().unwrap().path_of(addr);
+
+        let path = match path {
+            Some(path) => path,
+            None => {
+                trace!("Unknown peer {}", addr);
+
+                return false;
+            }
+        };
+
+        if let Some(s) = self.socket.as_ref() {
+            let msg_id = self.next_msg_id.fetch_add(1, Ordering::Relaxed);
+
+            for datagram in fragment(msg_id, &buff, self.fragment_size) {
+                if let Err(e) = s.send_to(&datagram, &path).await {
+                    // Its socket is gone for good
+                    if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) {
+                        self.peers.write().unwrap().forget(addr);
+                    }
+
+                    return false;
+                }
+            }
+        }
+
+        trace!("Sent {} to {}", buff.len(), addr);
+
+        true
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        self.socket.take();
+
+        if let Some(path) = self.bound.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::AbortHandle;
+
+use super::{connection_addr, read_frames, write_frame_async, Incoming, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+type Writer = Arc<tokio::sync::Mutex<OwnedWriteHalf>>;
+
+// The server, as seen from a client
+const SERVER_ID: u64 = 0;
+
+/// Removes a socket left behind at `path` by a previous server, which would
+/// fail the bind. Only a socket refusing `connect` is stale: a live one, or
+/// anything else than a socket, is left alone.
+pub(super) fn remove_stale_socket<F: FnOnce(&Path) -> io::Result<()>>(path: &Path, connect: F) {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            if let Err(e) = connect(path) {
+                if e.kind() == io::ErrorKind::ConnectionRefused {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+    }
+}
+
+/// Frames over Unix domain stream sockets, addressed by a filesystem path.
+/// Peers have no socket address, so each connection is given one with
+/// `connection_addr`.
+pub struct UnixTransport {
+    pub path: PathBuf,
+    /// The task accepting peers, once listening.
+    pub server: Arc<Mutex<Option<AbortHandle>>>,
+    pub clients: Arc<RwLock<HashMap<SocketAddr, Writer>>>,
+    pub next_id: Arc<AtomicU64>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub incoming: Incoming,
+}
+
+impl UnixTransport {
+    fn socket_read(&self, addr: SocketAddr, stream: UnixStream) {
+        let (reader, writer) = stream.into_split();
+
+        self.clients
+            .write()
+            .unwrap()
+            .insert(addr, Arc::new(tokio::sync::Mutex::new(writer)));
+
+        let clients = self.clients.clone();
+        let incoming = self.incoming.clone();
+        let max_frame_size = self.max_frame_size.clone();
+
+        runtime::spawn(async move {
+            read_frames(reader, addr, &incoming, &max_frame_size).await;
+
+            // The peer is gone, or we are closing
+            if clients.write().unwrap().remove(&addr).is_some() {
+                debug!("Disconnected {}", addr);
+
+                incoming.disconnected(addr);
+            }
+        });
+    }
+}
+
+impl Clone for UnixTransport {
+    fn clone(&self) -> Self {
+        UnixTransport {
+            path: self.path.clone(),
+            server: self.server.clone(),
+            clients: self.clients.clone(),
+            next_id: self.next_id.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for UnixTransport {
+    type Addr = PathBuf;
+
+    fn new(path: &PathBuf) -> UnixTransport {
+        UnixTransport {
+            path: path.clone(),
+            server: Arc::new(Mutex::new(None)),
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(SERVER_ID + 1)),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        remove_stale_socket(&self.path, |path| std::os::unix::net::UnixStream::connect(path).map(drop));
+
+        let socket = UnixListener::bind(&self.path).unwrap();
+
+        self.incoming.start();
+
+        let local_self = self.clone();
+
+        let accept = runtime::spawn(async move {
+            loop {
+                match socket.accept().await {
+                    Ok((stream, _)) => {
+                        let addr = connection_addr(local_self.next_id.fetch_add(1, Ordering::SeqCst));
+
+                        debug!("Accept {} on {:?}", addr, local_self.path);
+
+                        local_self.socket_read(addr, stream);
+                    }
+                    Err(e) => {
+                        error!("Error accept {}", e);
+
+                        break;
+                    }
+                }
+            }
+        });
+
+        *self.server.lock().unwrap() = Some(accept.abort_handle());
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        let socket = UnixStream::connect(&self.path).await.map_err(|e| e.to_string())?;
+
+        self.incoming.start();
+
+        self.socket_read(connection_addr(SERVER_ID), socket);
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        connection_addr(SERVER_ID)
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        connection_addr(SERVER_ID)
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let writer = self.clients.read().unwrap().get(addr).cloned();
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                trace!("Not connected to {}", addr);
+
+                return false;
+            }
+        };
+
+        if write_frame_async(&mut *writer.lock().await, &buff).await.is_err() {
+            return false;
+        }
+
+        trace!("Sent {} to {}", buff.len(), addr);
+
+        true
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.clients.read().unwrap().contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    async fn dropped(&self) -> Option<SocketAddr> {
+        self.incoming.dropped().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        // Their reading tasks drop the other halves
+        self.clients.write().unwrap().clear();
+
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
+
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
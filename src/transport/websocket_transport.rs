@@ -0,0 +1,323 @@
+use bytes::Bytes;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::Message;
+
+use super::{Incoming, Transport};
+use crate::limits::Limits;
+use crate::runtime;
+
+// Longest a peer can take to complete its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A socket address and the path of the WebSocket endpoint on it, parsed
+/// from `ws://127.0.0.1:3000/rpc` or `127.0.0.1:3000/rpc`. The path is `/`
+/// if none is given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebSocketAddr {
+    pub addr: SocketAddr,
+    pub path: String,
+}
+
+impl FromStr for WebSocketAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WebSocketAddr, String> {
+        let s = s.strip_prefix("ws://").unwrap_or(s);
+
+        let (addr, path) = match s.find('/') {
+            Some(index) => s.split_at(index),
+            None => (s, "/"),
+        };
+
+        Ok(WebSocketAddr {
+            addr: addr.parse().map_err(|e| format!("{}", e))?,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for WebSocketAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ws://{}{}", self.addr, self.path)
+    }
+}
+
+type Writer = Arc<tokio::sync::Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>;
+
+/// Every `Packet` in a binary WebSocket message, over TCP with tungstenite.
+///
+/// Peers are pinged every `ping_interval`, and dropped when nothing came
+/// from them for twice as long.
+pub struct WebSocketTransport {
+    pub addr: WebSocketAddr,
+    /// The address of the socket, once listening or connected.
+    pub bound: Arc<RwLock<Option<SocketAddr>>>,
+    /// The task accepting peers, once listening.
+    pub server: Arc<Mutex<Option<AbortHandle>>>,
+    pub ping_interval: Duration,
+    clients: Arc<RwLock<HashMap<SocketAddr, Writer>>>,
+    pub max_frame_size: Arc<RwLock<usize>>,
+    pub incoming: Incoming,
+}
+
+impl WebSocketTransport {
+    #[allow(dead_code)]
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.ping_interval = interval;
+    }
+
+    fn config(&self) -> WebSocketConfig {
+        WebSocketConfig::default().max_message_size(Some(*self.max_frame_size.read().unwrap()))
+    }
+
+    fn add_peer(&self, addr: SocketAddr, socket: WebSocketStream<TcpStream>) {
+        let (writer, mut reader) = socket.split();
+
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+
+        self.clients.write().unwrap().insert(addr, writer.clone());
+
+        let clients = self.clients.clone();
+        let incoming = self.incoming.clone();
+        let ping_interval = self.ping_interval;
+
+        runtime::spawn(async move {
+            let mut last_seen = Instant::now();
+            let mut last_ping = Instant::now();
+
+            loop {
+                tokio::select! {
+                    _ = incoming.stopped() => break,
+                    msg = reader.next() => {
+                        last_seen = Instant::now();
+
+                        match msg {
+                            Some(Ok(Message::Binary(data))) => incoming.push(data, addr).await,
+                            // Answered by tungstenite
+                            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => (),
+                            Some(Ok(Message::Close(_))) | None => break,
+                            Some(Ok(msg)) => warn!("Dropping non-binary message of {} bytes from {}", msg.len(), addr),
+                            Some(Err(e)) => {
+                                warn!("WebSocket error from {}: {}", addr, e);
+
+                                break;
+                            }
+                        }
+                    }
+                    _ = sleep_until(last_seen + ping_interval * 2) => {
+                        warn!("No pong from {}", addr);
+
+                        break;
+                    }
+                    _ = sleep_until(last_ping + ping_interval) => {
+                        last_ping = Instant::now();
+
+                        if writer.lock().await.send(Message::Ping(Bytes::new())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // The peer is gone, or we are closing
+            if clients.write().unwrap().remove(&addr).is_some() {
+                debug!("Disconnected {}", addr);
+
+                incoming.disconnected(addr);
+            }
+        });
+    }
+}
+
+impl Clone for WebSocketTransport {
+    fn clone(&self) -> Self {
+        WebSocketTransport {
+            addr: self.addr.clone(),
+            bound: self.bound.clone(),
+            server: self.server.clone(),
+            ping_interval: self.ping_interval,
+            clients: self.clients.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl Transport for WebSocketTransport {
+    type Addr = WebSocketAddr;
+
+    fn new(addr: &WebSocketAddr) -> WebSocketTransport {
+        WebSocketTransport {
+            addr: addr.clone(),
+            bound: Arc::new(RwLock::new(None)),
+            server: Arc::new(Mutex::new(None)),
+            ping_interval: DEFAULT_PING_INTERVAL,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            max_frame_size: Arc::new(RwLock::new(Limits::default().max_frame_size)),
+            incoming: Incoming::new(),
+        }
+    }
+
+    async fn listen(&mut self) {
+        let socket = TcpListener::bind(self.addr.addr).await.unwrap();
+
+        *self.bound.write().unwrap() = socket.local_addr().ok();
+
+        self.incoming.start();
+
+        let local_self = self.clone();
+
+        let accept = runtime::spawn(async move {
+            loop {
+                match socket.accept().await {
+                    Ok((stream, addr)) => {
+                        debug!("Accept {:?}", addr);
+
+                        let local_self = local_self.clone();
+
+                        // Not to hold back the other clients
+                        runtime::spawn(async move {
+                            let path = local_self.addr.path.clone();
+
+                            // The error type is set by tungstenite's `Callback`
+                            #[allow(clippy::result_large_err)]
+                            let check_path = move |req: &Request, res: Response| -> Result<Response, ErrorResponse> {
+                                if req.uri().path() == path {
+                                    return Ok(res);
+                                }
+
+                                let mut err = ErrorResponse::new(Some(format!("No endpoint at {}", req.uri().path())));
+
+                                *err.status_mut() = tungstenite::http::StatusCode::NOT_FOUND;
+
+                                Err(err)
+                            };
+
+                            let handshake =
+                                tokio_tungstenite::accept_hdr_async_with_config(stream, check_path, Some(local_self.config()));
+
+                            match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                                Ok(Ok(socket)) => local_self.add_peer(addr, socket),
+                                Ok(Err(e)) => warn!("Handshake with {} failed: {}", addr, e),
+                                Err(_) => warn!("Handshake with {} timed out", addr),
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        error!("Error accept {}", e);
+
+                        break;
+                    }
+                }
+            }
+        });
+
+        *self.server.lock().unwrap() = Some(accept.abort_handle());
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        let stream = TcpStream::connect(self.addr.addr).await.map_err(|e| e.to_string())?;
+
+        *self.bound.write().unwrap() = stream.local_addr().ok();
+
+        let handshake = tokio_tungstenite::client_async_with_config(self.addr.to_string(), stream, Some(self.config()));
+
+        let (socket, _) = timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| "Handshake timed out".to_string())?
+            .map_err(|e| e.to_string())?;
+
+        self.incoming.start();
+
+        self.add_peer(self.addr.addr, socket);
+
+        Ok(())
+    }
+
+    fn get_addr(&self) -> WebSocketAddr {
+        self.addr.clone()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.bound.read().unwrap().unwrap_or(self.addr.addr)
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.addr.addr
+    }
+
+    fn set_max_frame_size(&mut self, size: usize) {
+        *self.max_frame_size.write().unwrap() = size;
+    }
+
+    async fn send(&self, addr: &SocketAddr, buff: Bytes) -> bool {
+        let writer = self.clients.read().unwrap().get(addr).cloned();
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                trace!("Not connected to {}", addr);
+
+                return false;
+            }
+        };
+
+        let len = buff.len();
+
+        if writer.lock().await.send(Message::Binary(buff)).await.is_err() {
+            return false;
+        }
+
+        trace!("Sent {} to {}", len, addr);
+
+        true
+    }
+
+    fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.clients.read().unwrap().contains_key(addr)
+    }
+
+    async fn recv(&self) -> Option<(Bytes, SocketAddr)> {
+        self.incoming.recv().await
+    }
+
+    async fn dropped(&self) -> Option<SocketAddr> {
+        self.incoming.dropped().await
+    }
+
+    fn is_running(&mut self) -> bool {
+        self.incoming.is_running()
+    }
+
+    fn close(&mut self) {
+        self.incoming.stop();
+
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
+        }
+
+        let writers: Vec<Writer> = self.clients.write().unwrap().drain().map(|(_, writer)| writer).collect();
+
+        // Sends the close frames
+        runtime::block_on(async move {
+            for writer in writers {
+                let _ = writer.lock().await.close().await;
+            }
+        });
+    }
+}
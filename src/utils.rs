@@ -1,26 +1,69 @@
 use std::net::SocketAddr;
 use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
 use std::hash::Hasher;
+use std::io::Read;
+use std::str::FromStr;
 use std::sync::{ Arc, Mutex };
 
+use bincode::Options;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
 use super::byteorder::{LittleEndian, ByteOrder};
+use super::error::Error;
+
+pub fn prepend_u64(num: u64, data: &[u8]) -> Bytes {
+  let mut buff = BytesMut::with_capacity(8 + data.len());
+
+  buff.put_u64_le(num);
+  buff.extend_from_slice(data);
+
+  buff.freeze()
+}
+
+/// Splits the `u64` heading `data`, which may come short from any peer.
+pub fn extract_u64_head(data: Bytes) -> Result<(u64, Bytes), Error> {
+  if data.len() < 8 {
+    return Err(Error::Deserialize(format!("Missing the 8 bytes head, got {}", data.len())));
+  }
+
+  let num = LittleEndian::read_u64(&data[..8]);
+
+  Ok((num, data.slice(8..)))
+}
+
+pub fn serialize_with_u64_head<T: Serialize>(num: u64, value: &T) -> Bytes {
+  let mut buff = BytesMut::new();
 
-pub fn prepend_u64 (num: u64, vec: Vec<u8>) -> Vec<u8> {
-  let mut s_id_vec = [0u8; 8].to_vec();
+  buff.put_u64_le(num);
 
-  LittleEndian::write_u64(&mut s_id_vec, num);
+  bincode::serialize_into((&mut buff).writer(), value).unwrap();
 
-  let data_iter = s_id_vec.into_iter().chain(vec.into_iter());
+  buff.freeze()
+}
 
-  data_iter.collect()
+fn bounded_options(limit: usize) -> impl Options {
+  bincode::DefaultOptions::new()
+    .with_fixint_encoding()
+    .allow_trailing_bytes()
+    .with_limit(limit as u64)
 }
 
-pub fn extract_u64_head(vec: Vec<u8>) -> (u64, Vec<u8>) {
-  let num = LittleEndian::read_u64(&vec);
+fn bounded_error(e: bincode::ErrorKind) -> Error {
+  match e {
+    bincode::ErrorKind::SizeLimit => Error::MessageTooLarge,
+    e => Error::Deserialize(e.to_string()),
+  }
+}
 
-  let vec: Vec<u8> = vec.into_iter().skip(8).collect();
+pub fn deserialize_bounded<'a, T: Deserialize<'a>>(bytes: &'a [u8], limit: usize) -> Result<T, Error> {
+  bounded_options(limit).deserialize(bytes).map_err(|e| bounded_error(*e))
+}
 
-  (num, vec)
+pub fn deserialize_bounded_from<R: Read, T: DeserializeOwned>(reader: R, limit: usize) -> Result<T, Error> {
+  bounded_options(limit).deserialize_from(reader).map_err(|e| bounded_error(*e))
 }
 
 pub fn hash_ident_fn(id: &str) -> usize {
@@ -41,6 +84,16 @@ pub fn to_socket_addr(s: &str) -> SocketAddr {
   }
 }
 
+/// Parses the address of any transport, as `to_socket_addr` does.
+pub fn to_addr<A: FromStr>(s: &str) -> A where A::Err: Display {
+  match s.parse::<A>() {
+    Ok(addr) => addr,
+    Err(e) => {
+      panic!("Invalid address: {}, {}", s, e);
+    },
+  }
+}
+
 #[macro_export]
 macro_rules! hash_ident {
   ($x:ident) => ( $crate::utils::hash_ident_fn(stringify!($x)) )